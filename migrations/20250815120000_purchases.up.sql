@@ -0,0 +1,13 @@
+CREATE TABLE
+    "purchases" (
+        "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+        "phone_number" TEXT NOT NULL,
+        "gift_id" INTEGER NOT NULL,
+        "stars" INTEGER NOT NULL,
+        "status" TEXT NOT NULL,
+        "error" TEXT,
+        -- unix millis
+        "detected_at" INTEGER NOT NULL,
+        "payment_form_at" INTEGER,
+        "sent_at" INTEGER
+    );
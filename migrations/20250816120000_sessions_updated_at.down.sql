@@ -0,0 +1,2 @@
+ALTER TABLE "sessions"
+DROP COLUMN "updated_at";
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE "sessions"
+ADD COLUMN "updated_at" INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE "sessions"
+DROP COLUMN "deauthorized_at";
\ No newline at end of file
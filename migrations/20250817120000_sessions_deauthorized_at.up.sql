@@ -0,0 +1,2 @@
+ALTER TABLE "sessions"
+ADD COLUMN "deauthorized_at" INTEGER;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE "gifts_first_seen";
\ No newline at end of file
@@ -0,0 +1,6 @@
+CREATE TABLE
+    "gifts_first_seen" (
+        "gift_id" INTEGER PRIMARY KEY NOT NULL,
+        -- unix millis
+        "first_seen_at" INTEGER NOT NULL
+    );
\ No newline at end of file
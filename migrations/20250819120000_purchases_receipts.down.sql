@@ -0,0 +1,8 @@
+ALTER TABLE "purchases"
+DROP COLUMN "transaction_id";
+
+ALTER TABLE "purchases"
+DROP COLUMN "msg_id";
+
+ALTER TABLE "purchases"
+DROP COLUMN "form_id";
\ No newline at end of file
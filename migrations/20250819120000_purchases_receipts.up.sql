@@ -0,0 +1,9 @@
+ALTER TABLE "purchases"
+ADD COLUMN "form_id" INTEGER;
+
+ALTER TABLE "purchases"
+ADD COLUMN "msg_id" INTEGER;
+
+-- id of the matching GetStarsTransactions entry
+ALTER TABLE "purchases"
+ADD COLUMN "transaction_id" TEXT;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE "stars_transactions";
\ No newline at end of file
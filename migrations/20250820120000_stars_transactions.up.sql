@@ -0,0 +1,15 @@
+CREATE TABLE
+    "stars_transactions" (
+        "phone_number" TEXT NOT NULL,
+        "transaction_id" TEXT NOT NULL,
+        -- negative when spent
+        "stars" INTEGER NOT NULL,
+        -- unix millis
+        "date" INTEGER NOT NULL,
+        "gift_id" INTEGER,
+        "msg_id" INTEGER,
+        "refund" BOOLEAN NOT NULL,
+        "pending" BOOLEAN NOT NULL,
+        "failed" BOOLEAN NOT NULL,
+        PRIMARY KEY ("phone_number", "transaction_id")
+    );
\ No newline at end of file
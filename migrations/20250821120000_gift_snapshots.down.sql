@@ -0,0 +1 @@
+DROP TABLE "gift_snapshots";
\ No newline at end of file
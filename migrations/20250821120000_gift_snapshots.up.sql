@@ -0,0 +1,9 @@
+CREATE TABLE
+    "gift_snapshots" (
+        "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+        -- unix millis
+        "taken_at" INTEGER NOT NULL,
+        "hash" INTEGER NOT NULL,
+        -- serialized payments.StarGifts
+        "data" BLOB NOT NULL
+    );
\ No newline at end of file
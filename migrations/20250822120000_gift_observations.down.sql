@@ -0,0 +1 @@
+DROP TABLE "gift_observations";
\ No newline at end of file
@@ -0,0 +1,11 @@
+CREATE TABLE
+    "gift_observations" (
+        "gift_id" INTEGER NOT NULL,
+        -- unix millis
+        "observed_at" INTEGER NOT NULL,
+        "stars" INTEGER NOT NULL,
+        "availability_remains" INTEGER,
+        "sold_out" BOOLEAN NOT NULL
+    );
+
+CREATE INDEX "gift_observations_gift_id_observed_at" ON "gift_observations" ("gift_id", "observed_at");
\ No newline at end of file
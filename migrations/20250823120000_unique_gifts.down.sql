@@ -0,0 +1 @@
+DROP TABLE "unique_gifts";
\ No newline at end of file
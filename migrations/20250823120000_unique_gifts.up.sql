@@ -0,0 +1,21 @@
+CREATE TABLE
+    "unique_gifts" (
+        -- id of the unique gift, not of the gift it was upgraded from
+        "unique_id" INTEGER PRIMARY KEY,
+        "phone_number" TEXT NOT NULL,
+        "gift_id" INTEGER NOT NULL,
+        -- the service message of the upgraded gift
+        "msg_id" INTEGER NOT NULL,
+        "slug" TEXT NOT NULL,
+        "num" INTEGER NOT NULL,
+        "model" TEXT NOT NULL,
+        "model_permille" INTEGER NOT NULL,
+        "pattern" TEXT NOT NULL,
+        "pattern_permille" INTEGER NOT NULL,
+        "backdrop" TEXT NOT NULL,
+        "backdrop_permille" INTEGER NOT NULL,
+        -- share of upgrades rolling an equally rare or rarer combination, in percent
+        "percentile" REAL NOT NULL,
+        -- unix millis
+        "upgraded_at" INTEGER NOT NULL
+    );
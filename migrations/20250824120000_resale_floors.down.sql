@@ -0,0 +1 @@
+DROP TABLE "resale_floors";
\ No newline at end of file
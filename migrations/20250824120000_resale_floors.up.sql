@@ -0,0 +1,12 @@
+CREATE TABLE
+    "resale_floors" (
+        "gift_id" INTEGER NOT NULL,
+        -- unix millis
+        "sampled_at" INTEGER NOT NULL,
+        -- null when nothing is listed
+        "floor_stars" INTEGER,
+        -- among the sampled cheapest listings
+        "listings" INTEGER NOT NULL
+    );
+
+CREATE INDEX "resale_floors_gift_id_sampled_at" ON "resale_floors" ("gift_id", "sampled_at");
@@ -0,0 +1,8 @@
+CREATE TABLE
+    "leases" (
+        "name" TEXT PRIMARY KEY,
+        -- instance_id of the instance holding the lease
+        "holder" TEXT NOT NULL,
+        -- unix millis
+        "expires_at" INTEGER NOT NULL
+    );
@@ -0,0 +1 @@
+DROP TABLE "buy_jobs";
\ No newline at end of file
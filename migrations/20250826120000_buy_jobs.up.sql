@@ -0,0 +1,11 @@
+CREATE TABLE
+    "buy_jobs" (
+        "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+        -- unix millis
+        "created_at" INTEGER NOT NULL,
+        "detected_at" INTEGER NOT NULL,
+        -- TL-serialized vector of the detected gifts, in priority order
+        "gifts" BLOB NOT NULL,
+        -- JSON of the buy limits
+        "limits" TEXT NOT NULL
+    );
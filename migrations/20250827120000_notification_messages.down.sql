@@ -0,0 +1 @@
+DROP TABLE "notification_messages";
\ No newline at end of file
@@ -0,0 +1,13 @@
+CREATE TABLE
+    "notification_messages" (
+        "chat_id" INTEGER NOT NULL,
+        "message_id" INTEGER NOT NULL,
+        "gift_id" INTEGER NOT NULL,
+        -- "gift" or "buy_status"
+        "kind" TEXT NOT NULL,
+        -- unix millis
+        "sent_at" INTEGER NOT NULL,
+        PRIMARY KEY ("chat_id", "message_id")
+    );
+
+CREATE INDEX "notification_messages_gift_id" ON "notification_messages" ("gift_id");
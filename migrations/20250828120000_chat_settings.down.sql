@@ -0,0 +1 @@
+DROP TABLE "chat_settings";
\ No newline at end of file
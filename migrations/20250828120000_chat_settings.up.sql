@@ -0,0 +1,6 @@
+CREATE TABLE
+    "chat_settings" (
+        "chat_id" INTEGER PRIMARY KEY,
+        -- `i18n::Language::code`
+        "language" TEXT NOT NULL
+    );
@@ -0,0 +1 @@
+DROP TABLE "admin_destinations";
\ No newline at end of file
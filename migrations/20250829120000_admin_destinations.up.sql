@@ -0,0 +1,6 @@
+CREATE TABLE
+    "admin_destinations" (
+        "username" TEXT PRIMARY KEY,
+        -- `self`, a user ID, or a @username, see `core::BuyGiftsDestination`
+        "destination" TEXT NOT NULL
+    );
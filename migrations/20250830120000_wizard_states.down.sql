@@ -0,0 +1 @@
+DROP TABLE "wizard_states";
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE
+    "wizard_states" (
+        "username" TEXT PRIMARY KEY,
+        -- `wizard::Step`, what the admin is asked for next
+        "step" TEXT NOT NULL,
+        "gift_id" INTEGER,
+        -- units per account
+        "quantity" INTEGER,
+        -- `BuyDestinations`, `NULL` buys to the default destination
+        "destination" TEXT,
+        "updated_at" INTEGER NOT NULL
+    );
@@ -0,0 +1,5 @@
+ALTER TABLE "buy_jobs"
+DROP COLUMN "finished_at";
+
+ALTER TABLE "buy_jobs"
+DROP COLUMN "status";
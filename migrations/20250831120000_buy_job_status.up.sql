@@ -0,0 +1,13 @@
+-- queued, running, or `jobs::JobStatus` once finished
+ALTER TABLE "buy_jobs"
+ADD COLUMN "status" TEXT NOT NULL DEFAULT 'queued';
+
+-- unix millis
+ALTER TABLE "buy_jobs"
+ADD COLUMN "finished_at" INTEGER;
+
+-- every worker used to buy every job, the ones queued until now were handled already
+UPDATE "buy_jobs"
+SET
+    "status" = 'skipped',
+    "finished_at" = "created_at";
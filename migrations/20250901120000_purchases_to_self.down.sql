@@ -0,0 +1,2 @@
+ALTER TABLE "purchases"
+DROP COLUMN "to_self";
@@ -0,0 +1,4 @@
+-- whether the gift was bought to the buying account itself, `NULL` for purchases made
+-- before this was recorded
+ALTER TABLE "purchases"
+ADD COLUMN "to_self" INTEGER;
@@ -0,0 +1,10 @@
+CREATE TABLE
+    "topups" (
+        "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+        "phone_number" TEXT NOT NULL,
+        "stars" INTEGER NOT NULL,
+        -- unix millis, stored before the payment is sent
+        "attempted_at" INTEGER NOT NULL
+    );
+
+CREATE INDEX "topups_phone_number_attempted_at" ON "topups" ("phone_number", "attempted_at");
@@ -1,31 +1,59 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
 
 use futures::{
-    StreamExt,
+    StreamExt, TryFutureExt,
     future::{join_all, try_join_all},
 };
 use grammers_client::{
     InvocationError,
     grammers_tl_types::{
         self,
-        enums::{Document, InputFileLocation, upload::File},
+        enums::{Document, InputFileLocation, PhotoSize, upload::File},
         functions::upload::GetFile,
-        types::InputDocumentFileLocation,
+        types::{self, InputDocumentFileLocation},
     },
 };
 use sqlx::SqlitePool;
 use teloxide::{
     Bot,
-    payloads::SendPhotoSetters,
+    payloads::{
+        AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters, SendPhotoSetters,
+    },
     prelude::Requester,
-    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Update, UpdateKind},
+    types::{
+        ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, InputMedia, InputMediaPhoto,
+        Message, MessageId, ReplyParameters, Update, UpdateKind,
+    },
     update_listeners::{AsUpdateStream, polling_default},
 };
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    core::{BuyGiftsDestination, buy_gifts},
-    db::{self, get_chats, insert_chat},
-    wrapped_client::WrappedClient,
+    config,
+    core::{BuyDestinations, GiftOptions, PURCHASE_TASKS, PurchaseCoordinator, buy_gifts},
+    db::{
+        self, NotificationMessage, delete_admin_destination, delete_notification_messages,
+        get_admin_destination, get_chat_language, get_chats, get_chats_with_language,
+        get_notification_messages, get_purchase_history, insert_chat, insert_notification_message,
+        prune_notification_messages, set_admin_destination, set_chat_language, to_unix_millis,
+    },
+    health::{accounts_report, fleet_report},
+    i18n::{BuyState, Language, Text},
+    mini_app::Catalog,
+    stats::{DELIVERY, STATS, format_time, latency_report},
+    telegram_api::TelegramApi,
+    updates::UpdateWatchers,
+    wizard::{self, WIZARD_CALLBACK_PREFIX},
+    wrapped_client::{Clients, LoginCodeSource, WrappedClient},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -36,32 +64,201 @@ pub enum Error {
     TeloxideRequest(#[from] teloxide::RequestError),
     #[error(transparent)]
     GrammersInvocation(#[from] grammers_client::InvocationError),
+    #[error("login code request cancelled (phone_number = {0})")]
+    LoginCodeRequestCancelled(String),
+    #[error("file redirected to a CDN")]
+    CdnRedirect,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 const GET_FILE_LIMIT_MAX: i32 = 1024 * 1023;
 
-pub async fn run_bot(
+// `sticker_thumb_size` fetching the sticker itself instead of a thumbnail
+pub const FULL_STICKER: &str = "full";
+
+// fetched when the configured size can't be, the smallest thumbnail is the quickest to
+// fetch again
+const FALLBACK_THUMB_SIZE: &str = "s";
+
+// photos per album, larger drops are split
+const MEDIA_GROUP_MAX: usize = 10;
+
+const STATUS_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+const CLIENTS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const SETDEST_USAGE: &str = "Usage: /setdest <@channel|@username|user id|self|reset>, several destinations \
+    are weighted as @channel:60,self:40";
+
+// callback data of the /language buttons, followed by the language code
+const LANGUAGE_CALLBACK_PREFIX: &str = "lang:";
+
+// callback data of the Cancel buttons of progress messages, followed by the job ID
+const CANCEL_CALLBACK_PREFIX: &str = "cancel:";
+
+// callback data of the /history page buttons, followed by `page:page size`
+const HISTORY_CALLBACK_PREFIX: &str = "history:";
+
+const HISTORY_PAGE_SIZE: i64 = 10;
+
+const HISTORY_PAGE_SIZE_MAX: i64 = 50;
+
+// progress messages are edited at most this often, Telegram throttles frequent edits
+const PROGRESS_EDIT_INTERVAL: Duration = Duration::from_secs(3);
+
+// notifications of gifts still on sale after this long aren't followed up anymore
+const NOTIFICATION_MESSAGES_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// hands login codes sent by admins via `/code <phone number> <code>` to the clients waiting for them
+pub struct BotLoginCodes {
     bot: Arc<Bot>,
     pool: Arc<SqlitePool>,
-    clients: Vec<Arc<WrappedClient>>,
-    admin_usernames: Arc<[String]>,
-    buy_limit: Option<u64>,
-    buy_dest: Arc<BuyGiftsDestination>,
-) -> Result<()> {
-    let clients: Arc<[_]> = clients.into();
+    pending: Mutex<HashMap<String, oneshot::Sender<String>>>,
+}
+
+impl BotLoginCodes {
+    pub fn new(bot: Arc<Bot>, pool: Arc<SqlitePool>) -> Self {
+        Self {
+            bot,
+            pool,
+            pending: Default::default(),
+        }
+    }
+
+    pub async fn request(&self, phone_number: &str) -> Result<String> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(phone_number.to_string(), sender);
+
+        let chats = get_chats(&*self.pool).await?;
+
+        try_join_all(chats.iter().map(|chat_id| {
+            self.bot
+                .send_message(
+                    ChatId(*chat_id),
+                    format!(
+                        "🔑 Login code required for {phone_number}\n\n\
+                        Reply with /code {phone_number} 1 2 3 4 5\n\
+                        Separate the digits, Telegram revokes codes that are sent as is"
+                    ),
+                )
+                .into_future()
+        }))
+        .await?;
+
+        tracing::info!(phone_number, "waiting for login code from bot");
+
+        receiver
+            .await
+            .map_err(|_| Error::LoginCodeRequestCancelled(phone_number.to_string()))
+    }
+
+    // `text` is expected to start with `/code`
+    async fn on_code_message(&self, message: &Message, text: &str) -> Result<()> {
+        let mut args = text.split_whitespace().skip(1);
+        let phone_number = args.next().unwrap_or_default();
+        let code: String = args
+            .flat_map(str::chars)
+            .filter(char::is_ascii_digit)
+            .collect();
+
+        let reply = if code.is_empty() {
+            "Usage: /code <phone number> <code>".to_string()
+        } else {
+            let sender = self.pending.lock().unwrap().remove(phone_number);
+            match sender {
+                Some(sender) => {
+                    let _ = sender.send(code);
+                    format!("Login code accepted for {phone_number}")
+                }
+                None => format!("No login pending for {phone_number}"),
+            }
+        };
+
+        self.bot.send_message(message.chat.id, reply).await?;
+
+        Ok(())
+    }
+
+    // receives `/code` messages while `run_bot` isn't running yet (e.g. during startup logins)
+    pub async fn poll(self: Arc<Self>, admin_usernames: Arc<[String]>) -> Result<()> {
+        let mut polling = polling_default(self.bot.clone()).await;
+
+        polling
+            .as_stream()
+            .for_each_concurrent(None, |update| {
+                let this = self.clone();
+                let admin_usernames = admin_usernames.clone();
+
+                async move {
+                    let Ok(Update {
+                        kind: UpdateKind::Message(message),
+                        ..
+                    }) = update
+                    else {
+                        return;
+                    };
+
+                    let Some(text) = message.text().filter(|text| text.starts_with("/code")) else {
+                        return;
+                    };
 
-    let mut polling = polling_default(bot.clone()).await;
+                    if !is_from_admin(&message, &admin_usernames) {
+                        tracing::debug!(user = ?message.from, "user not in admins list");
+                        return;
+                    }
+
+                    if let Err(err) = this.on_code_message(&message, text).await {
+                        tracing::error!(?err, "failed to process login code message");
+                    }
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+fn is_from_admin(message: &Message, admin_usernames: &[String]) -> bool {
+    match &message.from {
+        Some(user) => {
+            user.username.is_some() && admin_usernames.contains(user.username.as_ref().unwrap())
+        }
+        _ => false,
+    }
+}
+
+pub struct BotState {
+    pub bot: Arc<Bot>,
+    pub pool: Arc<SqlitePool>,
+    pub clients: Clients,
+    pub admin_usernames: Arc<[String]>,
+    pub buy_limit: Option<u64>,
+    // used by admins without their own destination set with /setdest
+    pub buy_dest: Arc<BuyDestinations>,
+    pub gift_options: Arc<GiftOptions>,
+    pub coordinator: Arc<PurchaseCoordinator>,
+    pub login_codes: Arc<BotLoginCodes>,
+    // credentials for accounts added with /addaccount
+    pub accounts: config::Accounts,
+    // phone numbers /addaccount is logging in, a second /addaccount would take over the
+    // login code request of the first one
+    pub adding_accounts: Mutex<HashSet<String>>,
+    pub update_watchers: Option<UpdateWatchers>,
+    // gifts /wizard lets admins pick from
+    pub catalog: Catalog,
+}
+
+pub async fn run_bot(state: Arc<BotState>) -> Result<()> {
+    let mut polling = polling_default(state.bot.clone()).await;
 
     polling
         .as_stream()
         .for_each_concurrent(None, |update| {
-            let bot = bot.clone();
-            let pool = pool.clone();
-            let clients = clients.clone();
-            let admin_usernames = admin_usernames.clone();
-            let buy_dest = buy_dest.clone();
+            let state = state.clone();
 
             async move {
                 let update = match update {
@@ -73,17 +270,7 @@ pub async fn run_bot(
                 };
 
                 let update_id = update.id.0;
-                if let Err(err) = on_update(
-                    bot,
-                    pool,
-                    clients,
-                    admin_usernames,
-                    update,
-                    buy_limit,
-                    buy_dest,
-                )
-                .await
-                {
+                if let Err(err) = on_update(state, update).await {
                     tracing::debug!(update_id, ?err, "failed to process update");
                 }
             }
@@ -93,46 +280,182 @@ pub async fn run_bot(
     Ok(())
 }
 
-async fn on_update(
-    bot: Arc<Bot>,
-    pool: Arc<SqlitePool>,
-    clients: Arc<[Arc<WrappedClient>]>,
-    admin_usernames: Arc<[String]>,
-    update: Update,
-    buy_limit: Option<u64>,
-    buy_dest: Arc<BuyGiftsDestination>,
-) -> Result<()> {
+async fn on_update(state: Arc<BotState>, update: Update) -> Result<()> {
     tracing::trace!(?update);
 
+    let bot = &state.bot;
+
     match update.kind {
         UpdateKind::Message(message) => {
-            let is_from_admin = match &message.from {
-                Some(user) => {
-                    user.username.is_some()
-                        && admin_usernames.contains(user.username.as_ref().unwrap())
-                }
-                _ => false,
-            };
-            if !is_from_admin {
+            let language = chat_language(&state.pool, message.chat.id).await?;
+
+            if !is_from_admin(&message, &state.admin_usernames) {
                 tracing::debug!(user = ?message.from, "user not in admins list");
-                bot.send_message(message.chat.id, "User not in admins list")
+                bot.send_message(message.chat.id, language.text(Text::UserNotAdmin))
                     .await?;
 
                 return Ok(());
             }
 
-            let result = insert_chat(&*pool, message.chat.id.0).await;
-            let is_unique_violation = match &result {
-                Err(db::Error::Sqlx(sqlx::Error::Database(err))) => err.is_unique_violation(),
-                _ => false,
-            };
-            if !is_unique_violation {
-                result?;
+            let text = message.text().unwrap_or_default();
+            let mut args = text.split_whitespace();
+
+            // admins always have a username
+            let username = message
+                .from
+                .as_ref()
+                .and_then(|user| user.username.as_deref())
+                .unwrap_or_default();
+
+            if !text.is_empty()
+                && !text.starts_with('/')
+                && wizard::on_text(&state, language, message.chat.id, username, text).await?
+            {
+                return Ok(());
             }
 
-            tracing::debug!(chat_id = message.chat.id.0, "added to trusted chats");
-            bot.send_message(message.chat.id, "Added to trusted chats")
-                .await?;
+            match args.next().unwrap_or_default() {
+                "/status" => {
+                    let clients = state.clients.snapshot();
+                    let report = format!(
+                        "{}\n{}\n{}\n{}",
+                        STATS.report(&clients),
+                        latency_report(&state.pool, STATUS_WINDOW).await?,
+                        accounts_report(&clients),
+                        DELIVERY.report()
+                    );
+                    bot.send_message(message.chat.id, report).await?;
+                }
+                "/code" => state.login_codes.on_code_message(&message, text).await?,
+                "/cancel" => {
+                    let reply = if state.coordinator.cancel_all() {
+                        Text::CancellingPurchases
+                    } else {
+                        Text::NoPurchasesRunning
+                    };
+                    bot.send_message(message.chat.id, language.text(reply))
+                        .await?;
+                }
+                "/language" => {
+                    let inline_keyboard =
+                        InlineKeyboardMarkup::new(Language::ALL.map(|language| {
+                            vec![InlineKeyboardButton::callback(
+                                language.name(),
+                                format!("{LANGUAGE_CALLBACK_PREFIX}{}", language.code()),
+                            )]
+                        }));
+                    bot.send_message(message.chat.id, language.text(Text::ChooseLanguage))
+                        .reply_markup(inline_keyboard)
+                        .await?;
+                }
+                "/addaccount" => {
+                    let Some(phone_number) = args.next() else {
+                        bot.send_message(message.chat.id, "Usage: /addaccount <phone number>")
+                            .await?;
+                        return Ok(());
+                    };
+
+                    let reply = if state.clients.get(phone_number).is_some() {
+                        Some("Account is already active")
+                    } else if !state
+                        .adding_accounts
+                        .lock()
+                        .unwrap()
+                        .insert(phone_number.to_string())
+                    {
+                        Some("Account is being logged in already")
+                    } else {
+                        None
+                    };
+                    if let Some(reply) = reply {
+                        bot.send_message(message.chat.id, reply).await?;
+                        return Ok(());
+                    }
+
+                    // the login flow waits for a /code reply, which is handled by another
+                    // update, it's spawned before anything can fail, so it clears
+                    // `adding_accounts` in any case
+                    tokio::spawn(add_account(
+                        state.clone(),
+                        message.chat.id,
+                        phone_number.to_string(),
+                    ));
+
+                    bot.send_message(message.chat.id, format!("Logging in {phone_number}"))
+                        .await?;
+                }
+                "/removeaccount" => {
+                    let Some(phone_number) = args.next() else {
+                        bot.send_message(message.chat.id, "Usage: /removeaccount <phone number>")
+                            .await?;
+                        return Ok(());
+                    };
+
+                    let reply = match state.clients.remove(phone_number) {
+                        Some(_) => format!(
+                            "Removed {phone_number}, its session is kept, \
+                            remove it from phone_numbers to keep it inactive after restart"
+                        ),
+                        None => format!("{phone_number} is not active"),
+                    };
+                    bot.send_message(message.chat.id, reply).await?;
+                }
+                "/accounts" => {
+                    let accounts = state.accounts.all().unwrap_or_else(|err| {
+                        tracing::error!(?err, "failed to resolve configured accounts");
+                        vec![]
+                    });
+                    let report = fleet_report(&accounts, &state.clients.snapshot()).await;
+                    bot.send_message(message.chat.id, report).await?;
+                }
+                "/history" => {
+                    let page_size = args
+                        .next()
+                        .and_then(|page_size| page_size.parse().ok())
+                        .unwrap_or(HISTORY_PAGE_SIZE)
+                        .clamp(1, HISTORY_PAGE_SIZE_MAX);
+                    let (text, keyboard) = history_page(&state.pool, 0, page_size).await?;
+                    bot.send_message(message.chat.id, text)
+                        .reply_markup(keyboard)
+                        .await?;
+                }
+                "/wizard" => wizard::start(&state, language, message.chat.id, username).await?,
+                "/setdest" => {
+                    let reply = match args.next() {
+                        None => match get_admin_destination(&*state.pool, username).await? {
+                            Some(destination) => format!(
+                                "Your gifts are bought to {destination}\n\
+                                {SETDEST_USAGE}"
+                            ),
+                            None => SETDEST_USAGE.to_string(),
+                        },
+                        Some("reset") => {
+                            delete_admin_destination(&*state.pool, username).await?;
+                            "Your gifts are bought to the default destination".to_string()
+                        }
+                        Some(destination) => {
+                            set_destination(language, &state, username, destination).await?
+                        }
+                    };
+                    bot.send_message(message.chat.id, reply).await?;
+                }
+                _ => {
+                    let result = insert_chat(&*state.pool, message.chat.id.0).await;
+                    let is_unique_violation = match &result {
+                        Err(db::Error::Sqlx(sqlx::Error::Database(err))) => {
+                            err.is_unique_violation()
+                        }
+                        _ => false,
+                    };
+                    if !is_unique_violation {
+                        result?;
+                    }
+
+                    tracing::debug!(chat_id = message.chat.id.0, "added to trusted chats");
+                    bot.send_message(message.chat.id, language.text(Text::AddedToTrustedChats))
+                        .await?;
+                }
+            }
         }
         UpdateKind::CallbackQuery(callback_query) => {
             let Some(callback_data) = callback_query.data.as_deref() else {
@@ -143,6 +466,68 @@ async fn on_update(
                 );
                 return Ok(());
             };
+            if let Some(code) = callback_data.strip_prefix(LANGUAGE_CALLBACK_PREFIX) {
+                let (Some(language), Some(message)) =
+                    (Language::from_code(code), callback_query.message.as_ref())
+                else {
+                    tracing::debug!(
+                        callback_query_id = callback_query.id.0,
+                        callback_data,
+                        "unknown language or inaccessible message"
+                    );
+                    return Ok(());
+                };
+                let chat_id = message.chat().id;
+
+                set_chat_language(&*state.pool, chat_id.0, language.code()).await?;
+                tracing::debug!(
+                    chat_id = chat_id.0,
+                    language = language.code(),
+                    "language set"
+                );
+
+                bot.answer_callback_query(callback_query.id).await?;
+                bot.send_message(chat_id, language.text(Text::LanguageSet))
+                    .await?;
+                return Ok(());
+            }
+            if let Some(page) = callback_data.strip_prefix(HISTORY_CALLBACK_PREFIX) {
+                let Some((page, page_size)) = page.split_once(':').and_then(|(page, page_size)| {
+                    Some((page.parse().ok()?, page_size.parse().ok()?))
+                }) else {
+                    tracing::debug!(callback_data, "invalid history page");
+                    return Ok(());
+                };
+                bot.answer_callback_query(callback_query.id.clone()).await?;
+                if let Some(message) = &callback_query.message {
+                    let (text, keyboard) = history_page(&state.pool, page, page_size).await?;
+                    bot.edit_message_text(message.chat().id, message.id(), text)
+                        .reply_markup(keyboard)
+                        .await?;
+                }
+                return Ok(());
+            }
+            if let Some(job_id) = callback_data.strip_prefix(CANCEL_CALLBACK_PREFIX) {
+                let language = match &callback_query.message {
+                    Some(message) => chat_language(&state.pool, message.chat().id).await?,
+                    None => Language::default(),
+                };
+                let reply = if job_id
+                    .parse()
+                    .is_ok_and(|job_id| state.coordinator.cancel(job_id))
+                {
+                    Text::CancellingPurchases
+                } else {
+                    Text::NoPurchasesRunning
+                };
+                bot.answer_callback_query(callback_query.id)
+                    .text(language.text(reply))
+                    .await?;
+                return Ok(());
+            }
+            if let Some(data) = callback_data.strip_prefix(WIZARD_CALLBACK_PREFIX) {
+                return wizard::on_callback(&state, &callback_query, data).await;
+            }
             let gift_id: i64 = match callback_data.parse() {
                 Ok(t) => t,
                 Err(err) => {
@@ -156,15 +541,22 @@ async fn on_update(
                 }
             };
             bot.answer_callback_query(callback_query.id).await?;
+            let detected_at = SystemTime::now();
+            let buy_dest =
+                admin_buy_destinations(&state, callback_query.from.username.as_deref()).await?;
+            let state = state.clone();
             tokio::spawn(async move {
                 buy_gifts(
-                    &clients,
-                    bot.clone(),
-                    pool.clone(),
+                    &state.clients.snapshot(),
+                    state.bot.clone(),
+                    state.pool.clone(),
+                    &state.coordinator,
                     vec![gift_id],
                     None,
-                    buy_limit,
+                    &state.buy_limit.into(),
                     &buy_dest,
+                    &state.gift_options,
+                    detected_at,
                 )
                 .await
                 .inspect_err(|err| tracing::error!(?err, "buy_gifts exited with error"))
@@ -176,15 +568,178 @@ async fn on_update(
     Ok(())
 }
 
+// purchases grouped by detection, gift, account and result, newest first
+async fn history_page(
+    pool: &SqlitePool,
+    page: i64,
+    page_size: i64,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    // one more than shown, to know whether there's a next page
+    let mut purchases = get_purchase_history(pool, page_size + 1, page * page_size).await?;
+    let has_next = purchases.len() as i64 > page_size;
+    purchases.truncate(page_size as usize);
+
+    let mut text = if purchases.is_empty() {
+        "No purchases".to_string()
+    } else {
+        format!("🧾 Purchases, page {}\n\n", page + 1)
+    };
+    for purchase in &purchases {
+        writeln!(
+            text,
+            "{} · gift {} · {} · {} for {} ⭐ · {}",
+            format_time(purchase.detected_at),
+            purchase.gift_id,
+            purchase.phone_number,
+            purchase.count,
+            purchase.stars,
+            purchase.status
+        )
+        .unwrap();
+    }
+
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            "◀️",
+            format!("{HISTORY_CALLBACK_PREFIX}{}:{page_size}", page - 1),
+        ));
+    }
+    if has_next {
+        navigation.push(InlineKeyboardButton::callback(
+            "▶️",
+            format!("{HISTORY_CALLBACK_PREFIX}{}:{page_size}", page + 1),
+        ));
+    }
+
+    Ok((text, InlineKeyboardMarkup::new(vec![navigation])))
+}
+
+// the admin's /setdest destinations, the configured ones when none are set
+pub async fn admin_buy_destinations(
+    state: &BotState,
+    username: Option<&str>,
+) -> Result<Arc<BuyDestinations>> {
+    Ok(match username {
+        Some(username) => get_admin_destination(&*state.pool, username)
+            .await?
+            // stored destinations were parsed by /setdest already
+            .and_then(|destination| destination.parse().ok())
+            .map(Arc::new)
+            .unwrap_or_else(|| state.buy_dest.clone()),
+        None => state.buy_dest.clone(),
+    })
+}
+
+// parses the destinations and resolves them with the first active account, returns why
+// gifts can't be bought to them
+pub async fn check_destination(
+    language: Language,
+    state: &BotState,
+    destination: &str,
+) -> Option<String> {
+    let dest: BuyDestinations = match destination.parse() {
+        Ok(t) => t,
+        Err(err) => return Some(language.invalid_destination(destination, &err.to_string())),
+    };
+
+    let Some(client) = state
+        .clients
+        .snapshot()
+        .into_iter()
+        .find(|client| !client.is_deauthorized())
+    else {
+        return Some(language.text(Text::NoActiveAccounts).to_string());
+    };
+    for dest in dest.destinations() {
+        if let Err(err) = dest.resolve(&client).await {
+            return Some(language.unresolved_destination(&format!("{dest:?}"), &err.to_string()));
+        }
+    }
+
+    None
+}
+
+// the destinations are stored once the first active account can resolve them,
+// returns the reply to /setdest
+async fn set_destination(
+    language: Language,
+    state: &BotState,
+    username: &str,
+    destination: &str,
+) -> Result<String> {
+    if let Some(err) = check_destination(language, state, destination).await {
+        return Ok(err);
+    }
+
+    set_admin_destination(&*state.pool, username, destination).await?;
+    tracing::info!(username, destination, "admin destination set");
+
+    Ok(format!("Your gifts are bought to {destination}"))
+}
+
+async fn add_account(state: Arc<BotState>, chat_id: ChatId, phone_number: String) {
+    let result = match state.accounts.get(&phone_number) {
+        Ok(account) => WrappedClient::new(
+            state.pool.clone(),
+            account,
+            &LoginCodeSource::Bot,
+            Some(state.login_codes.as_ref()),
+        )
+        .await
+        .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    let reply = match result {
+        Ok(client) => {
+            let client = Arc::new(client);
+            state.clients.add(client.clone());
+            if let Some(update_watchers) = &state.update_watchers {
+                update_watchers.spawn(client);
+            }
+            tracing::info!(phone_number, "account added");
+            format!("Added {phone_number}, add it to phone_numbers to keep it active after restart")
+        }
+        Err(err) => {
+            tracing::error!(%err, phone_number, "failed to add account");
+            format!("Failed to add {phone_number}: {err}")
+        }
+    };
+    state.adding_accounts.lock().unwrap().remove(&phone_number);
+
+    if let Err(err) = state.bot.send_message(chat_id, reply).await {
+        tracing::error!(?err, phone_number, "failed to reply to /addaccount");
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn notify_gifts(
     bot: Arc<Bot>,
     pool: Arc<SqlitePool>,
     client: Arc<WrappedClient>,
     gifts: Vec<grammers_tl_types::types::StarGift>,
+    sell_out_etas: HashMap<i64, Duration>,
+    // units per account a Buy press buys, and the accounts buying them
+    buy_limit: u64,
+    accounts: u64,
+    // recipients of Buy presses, linked in the captions
+    buy_dest: Arc<BuyDestinations>,
+    // thumbnail type of the stickers, or `FULL_STICKER`
+    thumb_size: Arc<str>,
 ) -> Result<()> {
-    let chats: Arc<[i64]> = get_chats(&*pool).await?.into();
+    let chats: Arc<[(i64, Language)]> = get_chat_languages(&pool).await?.into();
 
-    join_all(
+    // links in the text rather than URL buttons, a button Telegram rejects fails the whole
+    // message
+    let dest_links: Arc<str> = buy_dest
+        .destinations()
+        .filter_map(|dest| Some(format!("\n📍 {}: {}", dest.label(), dest.link()?)))
+        .collect::<String>()
+        .into();
+
+    // without its sticker a gift is notified with text only
+    let mut notices = join_all(
         gifts
             .iter()
             .filter_map(|gift| match &gift.sticker {
@@ -192,90 +747,716 @@ pub async fn notify_gifts(
                 Document::Empty(_) => None,
             })
             .map(|(gift, document)| {
-                let request = GetFile {
-                    precise: true,
-                    cdn_supported: false,
-                    location: InputFileLocation::InputDocumentFileLocation(
-                        InputDocumentFileLocation {
-                            id: document.id,
-                            access_hash: document.access_hash,
-                            file_reference: document.file_reference.clone(),
-                            thumb_size: "s".to_string(),
-                        },
-                    ),
-                    offset: 0,
-                    limit: GET_FILE_LIMIT_MAX,
-                };
-
-                let client = client.clone();
-                let bot = bot.clone();
-                let chats = chats.clone();
-
+                let thumb_size = sticker_thumb_type(document, &thumb_size);
+                let sell_out_eta = sell_out_etas.get(&gift.id).copied();
+                let client = &client;
                 async move {
-                    // let span = tracing::info_span!("notify_gift", gift_id = gift.id);
-                    // let _guard = span.enter();
-
-                    let file = client
-                        .invoke_in_dc(&request, document.dc_id)
-                        .await
-                        .inspect_err(|err| {
-                            tracing::error!(?err, gift_id = gift.id, "failed to get file")
-                        })?;
-
-                    if let File::File(file) = file {
-                        let caption = format!(
-                            "ID: `{}`\n\n\
-                            Limited: *{}*\n\n\
-                            Stars: *{}* ⭐️\n\n\
-                            Supply: *{:?}*\n\
-                            Remains: *{:?}*",
-                            gift.id,
-                            gift.limited,
-                            gift.stars,
-                            gift.availability_total,
-                            gift.availability_remains,
-                        );
-
-                        let inline_keyboard =
-                            InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
-                                "Buy",
-                                gift.id.to_string(),
-                            )]]);
-
-                        let input_file = InputFile::memory(file.bytes);
-
-                        try_join_all(chats.iter().map(|chat_id| {
-                            let bot = bot.clone();
-                            let caption = caption.clone();
-                            let inline_keyboard = inline_keyboard.clone();
-                            let input_file = input_file.clone();
-                            async move {
-                                bot.send_photo(ChatId(*chat_id), input_file)
-                                    .caption(caption)
-                                    .reply_markup(inline_keyboard)
-                                    // .parse_mode(ParseMode::MarkdownV2)
-                                    .await
-                                    .inspect_err(|err| {
-                                        tracing::error!(
-                                            ?err,
-                                            gift_id = gift.id,
-                                            "failed to send photo"
-                                        )
-                                    })
-                            }
-                        }))
-                        .await?;
+                    GiftNotice {
+                        gift,
+                        sticker: fetch_sticker(client, gift.id, document, thumb_size)
+                            .await
+                            .map(InputFile::memory),
+                        sell_out_eta,
                     }
-
-                    Result::<_, Error>::Ok(())
                 }
             }),
     )
     .await;
+    if notices.is_empty() {
+        return Ok(());
+    }
+
+    let send = |&(chat_id, language): &(i64, Language), notices: Vec<GiftNotice>| {
+        let bot = bot.clone();
+        let pool = pool.clone();
+        let dest_links = dest_links.clone();
+        async move {
+            send_gift_notices(
+                &bot,
+                &pool,
+                chat_id,
+                language,
+                &notices,
+                buy_limit,
+                accounts,
+                &dest_links,
+            )
+            .await
+        }
+    };
+
+    // the stickers are uploaded with the first notification that goes through, the
+    // other chats get their file_ids instead of the bytes again
+    let mut chats = chats.iter();
+    let mut failed = None;
+    for chat in chats.by_ref() {
+        match send(chat, notices.clone()).await {
+            Ok(photos) => {
+                let mut file_ids = photos.iter().map(|message| {
+                    let photo = message.photo()?.last()?;
+                    Some(InputFile::file_id(photo.file.id.clone()))
+                });
+                for notice in notices.iter_mut().filter(|notice| notice.sticker.is_some()) {
+                    if let Some(Some(file_id)) = file_ids.next() {
+                        notice.sticker = Some(file_id);
+                    }
+                }
+                break;
+            }
+            Err(err) => failed = Some(err),
+        }
+    }
+
+    try_join_all(chats.map(|chat| send(chat, notices.clone()))).await?;
+
+    failed.map_or(Ok(()), Err)
+}
+
+#[derive(Clone)]
+struct GiftNotice<'a> {
+    gift: &'a grammers_tl_types::types::StarGift,
+    sticker: Option<InputFile>,
+    sell_out_eta: Option<Duration>,
+}
+
+// a single gift is sent as its sticker with the Buy button, a drop of several as albums
+// of their stickers followed by a summary with the Buy buttons, since album photos can't
+// have buttons, returns the messages with the stickers in order
+#[allow(clippy::too_many_arguments)]
+async fn send_gift_notices(
+    bot: &Bot,
+    pool: &SqlitePool,
+    chat_id: i64,
+    language: Language,
+    notices: &[GiftNotice<'_>],
+    buy_limit: u64,
+    accounts: u64,
+    // appended to the caption of a single gift and to the summary of a drop
+    dest_links: &str,
+) -> Result<Vec<Message>> {
+    let caption = |notice: &GiftNotice| {
+        gift_caption(
+            language,
+            notice.gift,
+            notice.sell_out_eta,
+            buy_limit,
+            accounts,
+        )
+    };
+    let buy_button = |text: String, notice: &GiftNotice| {
+        InlineKeyboardButton::callback(text, notice.gift.id.to_string())
+    };
+
+    if let [notice] = notices {
+        let inline_keyboard = InlineKeyboardMarkup::new(vec![vec![buy_button(
+            language.text(Text::Buy).to_string(),
+            notice,
+        )]]);
+        let caption = caption(notice) + dest_links;
+
+        let result = match notice.sticker.clone() {
+            Some(input_file) => {
+                bot.send_photo(ChatId(chat_id), input_file)
+                    .caption(caption)
+                    .reply_markup(inline_keyboard)
+                    // .parse_mode(ParseMode::MarkdownV2)
+                    .await
+            }
+            None => {
+                bot.send_message(ChatId(chat_id), caption)
+                    .reply_markup(inline_keyboard)
+                    .await
+            }
+        };
+        record_delivery(bot, pool, chat_id, &result);
+        let message = result.inspect_err(|err| {
+            tracing::error!(
+                ?err,
+                gift_id = notice.gift.id,
+                "failed to send notification"
+            )
+        })?;
+        record_notification(pool, &message, NotificationKind::Gift, notice.gift.id).await;
+
+        return Ok(vec![message]);
+    }
+
+    let with_stickers: Vec<_> = notices
+        .iter()
+        .filter_map(|notice| Some((notice, notice.sticker.clone()?)))
+        .collect();
+    let mut photos = vec![];
+    for chunk in with_stickers.chunks(MEDIA_GROUP_MAX) {
+        // albums take 2 photos or more
+        let result = match chunk {
+            [(notice, input_file)] => bot
+                .send_photo(ChatId(chat_id), input_file.clone())
+                .caption(caption(notice))
+                .await
+                .map(|message| vec![message]),
+            _ => {
+                bot.send_media_group(
+                    ChatId(chat_id),
+                    chunk.iter().map(|(notice, input_file)| {
+                        InputMedia::Photo(
+                            InputMediaPhoto::new(input_file.clone()).caption(caption(notice)),
+                        )
+                    }),
+                )
+                .await
+            }
+        };
+        record_delivery(bot, pool, chat_id, &result);
+        let messages = result
+            .inspect_err(|err| tracing::error!(?err, chat_id, "failed to send gift album"))?;
+        for ((notice, _), message) in chunk.iter().zip(&messages) {
+            record_notification(pool, message, NotificationKind::Gift, notice.gift.id).await;
+        }
+        photos.extend(messages);
+    }
+
+    let mut summary = language.drop_summary(notices.len());
+    let mut rows = vec![];
+    for notice in notices {
+        let gift = notice.gift;
+        let title = gift.title.as_deref().unwrap_or("untitled");
+        write!(
+            summary,
+            "\n\n{title} `{}`: *{}* ⭐️, {}: *{:?}*",
+            gift.id,
+            gift.stars,
+            language.text(Text::Remains),
+            gift.availability_remains,
+        )
+        .unwrap();
+        if let Some(eta) = notice.sell_out_eta {
+            summary.push('\n');
+            summary.push_str(&language.sell_out_eta(eta.as_secs().div_ceil(60)));
+        }
+        rows.push(vec![buy_button(
+            format!("{} {title} · {} ⭐", language.text(Text::Buy), gift.stars),
+            notice,
+        )]);
+    }
+    summary.push_str(dest_links);
+
+    let result = bot
+        .send_message(ChatId(chat_id), summary)
+        .reply_markup(InlineKeyboardMarkup::new(rows))
+        .await;
+    record_delivery(bot, pool, chat_id, &result);
+    result.inspect_err(|err| tracing::error!(?err, chat_id, "failed to send drop summary"))?;
+
+    Ok(photos)
+}
+
+// the thumbnail type to fetch for `requested`, empty for the sticker itself, which is
+// only fetched for static stickers since animated ones can't be sent as photos, the
+// largest thumbnail is fetched instead of those and of types the sticker doesn't have
+fn sticker_thumb_type(document: &types::Document, requested: &str) -> String {
+    if requested == FULL_STICKER && document.mime_type == "image/webp" {
+        return String::new();
+    }
+
+    let thumbs = document
+        .thumbs
+        .iter()
+        .flatten()
+        .filter_map(|thumb| match thumb {
+            PhotoSize::Size(size) => Some((&size.r#type, size.w * size.h)),
+            PhotoSize::CachedSize(size) => Some((&size.r#type, size.w * size.h)),
+            PhotoSize::Progressive(size) => Some((&size.r#type, size.w * size.h)),
+            _ => None,
+        });
+    if thumbs.clone().any(|(r#type, _)| r#type == requested) {
+        return requested.to_string();
+    }
+    thumbs
+        .max_by_key(|&(_, area)| area)
+        .map_or_else(|| requested.to_string(), |(r#type, _)| r#type.clone())
+}
+
+// `thumb_size` and then `FALLBACK_THUMB_SIZE` when it fails to be fetched (e.g. the size
+// doesn't exist or a request fails), `None` when neither could be fetched
+async fn fetch_sticker(
+    client: &WrappedClient,
+    gift_id: i64,
+    document: &types::Document,
+    thumb_size: String,
+) -> Option<Vec<u8>> {
+    let mut thumb_sizes = vec![thumb_size];
+    if thumb_sizes[0] != FALLBACK_THUMB_SIZE {
+        thumb_sizes.push(FALLBACK_THUMB_SIZE.to_string());
+    }
+
+    for thumb_size in thumb_sizes {
+        match download_sticker(client, document, thumb_size.clone()).await {
+            Ok(bytes) => return Some(bytes),
+            Err(err) => tracing::error!(?err, gift_id, thumb_size, "failed to get file"),
+        }
+    }
+
+    None
+}
+
+// in as many requests as the file takes, from the DC of the document since CDN DCs would
+// need their own connections and decryption
+async fn download_sticker(
+    client: &WrappedClient,
+    document: &types::Document,
+    thumb_size: String,
+) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    loop {
+        let request = GetFile {
+            precise: true,
+            cdn_supported: false,
+            location: InputFileLocation::InputDocumentFileLocation(InputDocumentFileLocation {
+                id: document.id,
+                access_hash: document.access_hash,
+                file_reference: document.file_reference.clone(),
+                thumb_size: thumb_size.clone(),
+            }),
+            offset: bytes.len() as i64,
+            limit: GET_FILE_LIMIT_MAX,
+        };
+
+        // shouldn't happen without `cdn_supported`, the next thumb size is tried if it does
+        let File::File(file) = client.invoke_in_dc(&request, document.dc_id).await? else {
+            return Err(Error::CdnRedirect);
+        };
+        let done = file.bytes.len() < GET_FILE_LIMIT_MAX as usize;
+        bytes.extend(file.bytes);
+        if done {
+            return Ok(bytes);
+        }
+    }
+}
+
+fn gift_caption(
+    language: Language,
+    gift: &grammers_tl_types::types::StarGift,
+    sell_out_eta: Option<Duration>,
+    buy_limit: u64,
+    accounts: u64,
+) -> String {
+    let mut caption = format!(
+        "ID: `{}`\n\n\
+        {}: *{}*\n\n\
+        {}: *{}* ⭐️\n\n\
+        {}: *{:?}*\n\
+        {}: *{:?}*",
+        gift.id,
+        language.text(Text::Limited),
+        gift.limited,
+        language.text(Text::Stars),
+        gift.stars,
+        language.text(Text::Supply),
+        gift.availability_total,
+        language.text(Text::Remains),
+        gift.availability_remains,
+    );
+    if let Some(eta) = sell_out_eta {
+        caption.push('\n');
+        caption.push_str(&language.sell_out_eta(eta.as_secs().div_ceil(60)));
+    }
+    caption.push_str("\n\n");
+    caption.push_str(&language.estimated_cost(buy_limit, gift.stars, accounts));
+    caption
+}
+
+// English until the chat picks another language with /language
+pub async fn chat_language(pool: &SqlitePool, chat_id: ChatId) -> Result<Language> {
+    Ok(get_chat_language(pool, chat_id.0)
+        .await?
+        .and_then(|code| Language::from_code(&code))
+        .unwrap_or_default())
+}
+
+async fn get_chat_languages(pool: &SqlitePool) -> Result<Vec<(i64, Language)>> {
+    Ok(get_chats_with_language(pool)
+        .await?
+        .into_iter()
+        .map(|(chat_id, code)| {
+            let language = code
+                .and_then(|code| Language::from_code(&code))
+                .unwrap_or_default();
+            (chat_id, language)
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum NotificationKind {
+    // a detected gift with the Buy button
+    Gift,
+    BuyStatus,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gift => "gift",
+            Self::BuyStatus => "buy_status",
+        }
+    }
+}
+
+// kept so that follow-ups (sold out edits, bought counts) can find the messages of a gift,
+// failing to record doesn't fail the notification
+async fn record_notification(
+    pool: &SqlitePool,
+    message: &Message,
+    kind: NotificationKind,
+    gift_id: i64,
+) {
+    let notification = NotificationMessage {
+        chat_id: message.chat.id.0,
+        message_id: message.id.0,
+        gift_id,
+        kind: kind.as_str().to_string(),
+        sent_at: to_unix_millis(SystemTime::now()),
+    };
+    if let Err(err) = insert_notification_message(pool, &notification).await {
+        tracing::error!(?err, ?notification, "failed to record notification");
+    }
+}
+
+// replies to the notifications of a gift detected before its sell rate was known with
+// the sell-out ETA estimated from the following polls, once `notified` completes
+pub fn spawn_notify_sell_out_eta(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    gift_id: i64,
+    eta: Duration,
+    notified: impl Future<Output = ()> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        notified.await;
+        if let Err(err) = notify_sell_out_eta(&bot, &pool, gift_id, eta).await {
+            tracing::error!(?err, gift_id, "failed to notify sell-out ETA");
+        }
+    });
+}
+
+async fn notify_sell_out_eta(
+    bot: &Bot,
+    pool: &SqlitePool,
+    gift_id: i64,
+    eta: Duration,
+) -> Result<()> {
+    let languages: HashMap<_, _> = get_chat_languages(pool).await?.into_iter().collect();
+    let notifications =
+        get_notification_messages(pool, gift_id, NotificationKind::Gift.as_str()).await?;
+    for notification in notifications {
+        let language = languages
+            .get(&notification.chat_id)
+            .copied()
+            .unwrap_or_default();
+        let result = bot
+            .send_message(
+                ChatId(notification.chat_id),
+                language.sell_out_eta(eta.as_secs().div_ceil(60)),
+            )
+            .reply_parameters(ReplyParameters::new(MessageId(notification.message_id)))
+            .await;
+        if let Err(err) = result {
+            tracing::error!(?err, ?notification, "failed to send sell-out ETA");
+        }
+    }
+    Ok(())
+}
+
+// removes the Buy buttons from the notifications of gifts that sold out, pressing them
+// could only fail, the notifications are forgotten then, as are the ones too old
+pub fn spawn_mark_sold_out(bot: Arc<Bot>, pool: Arc<SqlitePool>, gift_ids: Vec<i64>) {
+    tokio::spawn(async move {
+        for gift_id in gift_ids {
+            if let Err(err) = mark_sold_out(&bot, &pool, gift_id).await {
+                tracing::error!(?err, gift_id, "failed to mark notifications as sold out");
+            }
+        }
+
+        let before = to_unix_millis(SystemTime::now() - NOTIFICATION_MESSAGES_RETENTION);
+        if let Err(err) = prune_notification_messages(&*pool, before).await {
+            tracing::error!(?err, "failed to prune notification messages");
+        }
+    });
+}
+
+async fn mark_sold_out(bot: &Bot, pool: &SqlitePool, gift_id: i64) -> Result<()> {
+    let notifications =
+        get_notification_messages(pool, gift_id, NotificationKind::Gift.as_str()).await?;
+    for notification in &notifications {
+        // photos of albums have no buttons, these edits fail
+        let result = bot
+            .edit_message_reply_markup(
+                ChatId(notification.chat_id),
+                MessageId(notification.message_id),
+            )
+            .await;
+        if let Err(err) = result {
+            tracing::debug!(?err, ?notification, "failed to remove Buy button");
+        }
+    }
+    delete_notification_messages(pool, gift_id).await?;
+    Ok(())
+}
+
+pub async fn alert_chats(bot: &Bot, pool: &SqlitePool, text: &str) -> Result<()> {
+    let chats = get_chats(pool).await?;
+
+    try_join_all(chats.into_iter().map(|chat_id| async move {
+        let result = bot.send_message(ChatId(chat_id), text).await;
+        record_delivery(bot, pool, chat_id, &result);
+        result
+    }))
+    .await?;
 
     Ok(())
 }
 
+// like `alert_chats`, with the text in the language of each chat
+pub async fn alert_chats_localized(
+    bot: &Bot,
+    pool: &SqlitePool,
+    text: impl Fn(Language) -> String,
+) -> Result<()> {
+    let chats = get_chat_languages(pool).await?;
+
+    try_join_all(chats.into_iter().map(|(chat_id, language)| {
+        let text = text(language);
+        async move {
+            let result = bot.send_message(ChatId(chat_id), text).await;
+            record_delivery(bot, pool, chat_id, &result);
+            result
+        }
+    }))
+    .await?;
+
+    Ok(())
+}
+
+// counts the send in `DELIVERY`, when the chat becomes undeliverable or recovers
+// the other chats are told, the chat itself likely can't be reached
+fn record_delivery<T>(
+    bot: &Bot,
+    pool: &SqlitePool,
+    chat_id: i64,
+    result: &Result<T, teloxide::RequestError>,
+) {
+    let alert = match result {
+        Ok(_) => DELIVERY.record_sent(chat_id),
+        Err(err) => DELIVERY.record_failed(chat_id, err),
+    };
+    let Some(text) = alert else {
+        return;
+    };
+    tracing::warn!(chat_id, text, "notification delivery changed");
+
+    let (bot, pool) = (bot.clone(), pool.clone());
+    tokio::spawn(async move {
+        let chats = match get_chats(&pool).await {
+            Ok(chats) => chats,
+            Err(err) => {
+                tracing::error!(?err, "failed to get chats");
+                return;
+            }
+        };
+        for other in chats.into_iter().filter(|&other| other != chat_id) {
+            if let Err(err) = bot.send_message(ChatId(other), &text).await {
+                tracing::error!(?err, chat_id = other, "failed to send delivery alert");
+            }
+        }
+    });
+}
+
+// alerts once per outage when an account runs out of reconnect attempts, and once it recovers,
+// and once when Telegram revokes a session
+pub async fn watch_clients(bot: Arc<Bot>, pool: Arc<SqlitePool>, clients: Clients) {
+    let mut disconnected = HashSet::new();
+
+    loop {
+        tokio::time::sleep(CLIENTS_CHECK_INTERVAL).await;
+
+        for client in clients.snapshot() {
+            let phone_number = client.phone_number();
+
+            if client.is_deauthorized() {
+                alert_deauthorized(&bot, &pool, &client).await;
+                continue;
+            }
+
+            let text = match (
+                client.reconnect_failures() > 0,
+                disconnected.contains(phone_number),
+            ) {
+                (true, false) => {
+                    disconnected.insert(phone_number.to_string());
+                    format!("⚠️ {phone_number} keeps failing to reconnect to Telegram")
+                }
+                (false, true) => {
+                    disconnected.remove(phone_number);
+                    format!("✅ {phone_number} reconnected")
+                }
+                _ => continue,
+            };
+
+            if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                tracing::error!(?err, phone_number, "failed to send connection alert");
+            }
+        }
+    }
+}
+
+// once per revoked session, by the health checks or `watch_clients`, whichever notices first
+pub async fn alert_deauthorized(bot: &Bot, pool: &SqlitePool, client: &WrappedClient) {
+    if !client.claim_deauthorization_alert() {
+        return;
+    }
+
+    let phone_number = client.phone_number();
+    let text = format!(
+        "🚨 URGENT: {} was logged out by Telegram and won't buy until it's logged in \
+        again with `login` or /removeaccount and /addaccount",
+        client.display_name()
+    );
+    if let Err(err) = alert_chats(bot, pool, &text).await {
+        tracing::error!(?err, phone_number, "failed to send deauthorization alert");
+    }
+}
+
+// units a buy job bought so far, shown in a message in every trusted chat whose Cancel
+// button cancels the job (when the process runs the bot handling it), the message is
+// final once the job finishes
+#[derive(Default)]
+pub struct BuyProgress {
+    bought: AtomicU64,
+    failed: AtomicU64,
+    cancelled: AtomicBool,
+    finished: CancellationToken,
+}
+
+impl BuyProgress {
+    // `job_id` is `None` without a Cancel button
+    pub fn spawn(
+        bot: Arc<Bot>,
+        pool: Arc<SqlitePool>,
+        job_id: Option<u64>,
+        gifts: usize,
+    ) -> Arc<Self> {
+        let progress = Arc::new(Self::default());
+        PURCHASE_TASKS.spawn(
+            report_buy_progress(bot, pool, job_id, gifts, progress.clone()).inspect_err(
+                move |err| tracing::error!(?err, ?job_id, "failed to report buy progress"),
+            ),
+        );
+        progress
+    }
+
+    pub fn record(&self, success: bool) {
+        let count = if success { &self.bought } else { &self.failed };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self, cancelled: bool) {
+        self.cancelled.store(cancelled, Ordering::Relaxed);
+        self.finished.cancel();
+    }
+
+    // (bought, failed)
+    pub fn counts(&self) -> (u64, u64) {
+        (
+            self.bought.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+        )
+    }
+
+    fn progress(&self) -> (BuyState, u64, u64) {
+        let state = if !self.finished.is_cancelled() {
+            BuyState::Buying
+        } else if self.cancelled.load(Ordering::Relaxed) {
+            BuyState::Cancelled
+        } else {
+            BuyState::Finished
+        };
+        (
+            state,
+            self.bought.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+async fn report_buy_progress(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    job_id: Option<u64>,
+    gifts: usize,
+    progress: Arc<BuyProgress>,
+) -> Result<()> {
+    let chats = get_chat_languages(&pool).await?;
+    let keyboard = |language: Language| {
+        job_id.map(|job_id| {
+            InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+                language.text(Text::Cancel),
+                format!("{CANCEL_CALLBACK_PREFIX}{job_id}"),
+            )]])
+        })
+    };
+
+    let mut shown = progress.progress();
+    let messages = try_join_all(chats.iter().map(|&(chat_id, language)| {
+        let (state, bought, failed) = shown;
+        let mut request = bot.send_message(
+            ChatId(chat_id),
+            language.buy_progress(state, gifts, bought, failed),
+        );
+        if let Some(keyboard) = keyboard(language) {
+            request = request.reply_markup(keyboard);
+        }
+        request
+            .into_future()
+            .map_ok(move |message| (message, language))
+    }))
+    .await?;
+
+    loop {
+        let finished = tokio::select! {
+            _ = progress.finished.cancelled() => true,
+            _ = tokio::time::sleep(PROGRESS_EDIT_INTERVAL) => false,
+        };
+
+        let latest = progress.progress();
+        if latest == shown && !finished {
+            continue;
+        }
+        shown = latest;
+        let (state, bought, failed) = shown;
+
+        join_all(messages.iter().map(|(message, language)| {
+            let edit = bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                language.buy_progress(state, gifts, bought, failed),
+            );
+            // without a keyboard the button is removed
+            let edit = match keyboard(*language) {
+                Some(keyboard) if !finished => edit.reply_markup(keyboard),
+                _ => edit,
+            };
+            edit.into_future().inspect_err(move |err| {
+                tracing::error!(?err, ?job_id, "failed to edit progress message")
+            })
+        }))
+        .await;
+
+        if finished {
+            return Ok(());
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum GiftBuyStatus {
     PaymentFormError(InvocationError),
@@ -283,6 +1464,23 @@ pub enum GiftBuyStatus {
     Success,
 }
 
+impl GiftBuyStatus {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::PaymentFormError(_) => "payment_form_error",
+            Self::SendStarsFormError(_) => "send_stars_form_error",
+            Self::Success => "success",
+        }
+    }
+
+    pub fn error(&self) -> Option<String> {
+        match self {
+            Self::PaymentFormError(err) | Self::SendStarsFormError(err) => Some(err.to_string()),
+            Self::Success => None,
+        }
+    }
+}
+
 pub async fn notify_gift_buy_status(
     bot: Arc<Bot>,
     pool: Arc<SqlitePool>,
@@ -291,34 +1489,64 @@ pub async fn notify_gift_buy_status(
     balance: i64,
     gift_id: i64,
     status: GiftBuyStatus,
+    // where the gift was bought to, see `BuyGiftsDestination::link`
+    dest_link: Option<String>,
+    // the bought gift, see `BuyGiftsDestination::message_link`
+    gift_link: Option<String>,
 ) -> Result<()> {
-    let chats: Arc<[i64]> = get_chats(&*pool).await?.into();
+    let chats = get_chat_languages(&pool).await?;
+
+    let is_success = matches!(status, GiftBuyStatus::Success);
 
     // let use_markdown_v2 = match status {
     //     GiftBuyStatus::PaymentFormError(_) | GiftBuyStatus::SendStarsFormError(_) => false,
     //     GiftBuyStatus::Success => true,
     // };
 
-    let title = match status {
-        GiftBuyStatus::PaymentFormError(err) => format!("❌ Error\\(PaymentForm\\): {err}"),
-        GiftBuyStatus::SendStarsFormError(err) => format!("❌ Error\\(SendStarsForm\\): {err}"),
-        GiftBuyStatus::Success => "✅ Gift bought".to_string(),
-    };
-
-    try_join_all(chats.iter().map(|chat_id| {
-        let text = format!(
+    try_join_all(chats.iter().map(|(chat_id, language)| {
+        let title = match &status {
+            GiftBuyStatus::PaymentFormError(err) => {
+                format!("❌ {}\\(PaymentForm\\): {err}", language.text(Text::Error))
+            }
+            GiftBuyStatus::SendStarsFormError(err) => {
+                format!(
+                    "❌ {}\\(SendStarsForm\\): {err}",
+                    language.text(Text::Error)
+                )
+            }
+            GiftBuyStatus::Success => format!("✅ {}", language.text(Text::GiftBought)),
+        };
+        let mut text = format!(
             "{title}\n\n\
-            Count: *{count}*\n\
-            Phone Number: *{}*\n\
-            Balance: {balance} ⭐️\n\
+            {}: *{count}*\n\
+            {}: *{}*\n\
+            {}: {balance} ⭐️\n\
             ID: `{gift_id}`",
-            phone_number.replace("+", "\\+")
+            language.text(Text::Count),
+            language.text(Text::PhoneNumber),
+            phone_number.replace("+", "\\+"),
+            language.text(Text::Balance),
         );
-        let mut builder = bot.send_message(ChatId(*chat_id), text);
+        if is_success {
+            if let Some(dest_link) = &dest_link {
+                write!(text, "\n📍 {}: {dest_link}", language.text(Text::Recipient)).unwrap();
+            }
+            if let Some(gift_link) = &gift_link {
+                write!(text, "\n🎁 {}: {gift_link}", language.text(Text::Gift)).unwrap();
+            }
+        }
+        let builder = bot.send_message(ChatId(*chat_id), text);
         // if use_markdown_v2 {
         //     builder = builder.parse_mode(ParseMode::MarkdownV2)
         // }
-        builder.into_future()
+        let (bot, pool) = (bot.clone(), pool.clone());
+        async move {
+            let result = builder.await;
+            record_delivery(&bot, &pool, *chat_id, &result);
+            let message = result?;
+            record_notification(&pool, &message, NotificationKind::BuyStatus, gift_id).await;
+            Result::<_, Error>::Ok(())
+        }
     }))
     .await?;
 
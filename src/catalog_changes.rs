@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    sync::Arc,
+};
+
+use grammers_client::grammers_tl_types::enums::StarGift;
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::bot::alert_chats;
+
+struct GiftState {
+    title: Option<String>,
+    sold_out: bool,
+    resale_floor: Option<i64>,
+}
+
+enum CatalogChange {
+    Removed,
+    SoldOut,
+    // the floor price of the first listing
+    ResaleAvailable(i64),
+}
+
+// the last catalog, to report the transitions new gift notifications don't cover:
+// gifts leaving the catalog, selling out and getting resale listings
+#[derive(Default)]
+pub struct CatalogChanges {
+    // `None` until the first catalog, which is the baseline
+    last: Option<HashMap<i64, GiftState>>,
+}
+
+impl CatalogChanges {
+    // a report of what changed since the previous catalog, `None` when nothing did
+    pub fn update(&mut self, gifts: &[StarGift]) -> Option<String> {
+        let current: HashMap<_, _> = gifts
+            .iter()
+            .filter_map(|gift| match gift {
+                StarGift::Gift(gift) => Some((
+                    gift.id,
+                    GiftState {
+                        title: gift.title.clone(),
+                        sold_out: gift.sold_out,
+                        resale_floor: gift.resell_min_stars,
+                    },
+                )),
+                StarGift::Unique(_) => None,
+            })
+            .collect();
+
+        let last = self.last.replace(current)?;
+        let current = self.last.as_ref().unwrap();
+
+        let mut changes: Vec<_> = last
+            .iter()
+            .filter(|(gift_id, _)| !current.contains_key(gift_id))
+            .map(|(&gift_id, state)| (gift_id, state, CatalogChange::Removed))
+            .collect();
+        for (&gift_id, state) in current {
+            let Some(previous) = last.get(&gift_id) else {
+                continue;
+            };
+            if state.sold_out && !previous.sold_out {
+                changes.push((gift_id, state, CatalogChange::SoldOut));
+            }
+            if let Some(floor) = state.resale_floor
+                && previous.resale_floor.is_none()
+            {
+                changes.push((gift_id, state, CatalogChange::ResaleAvailable(floor)));
+            }
+        }
+
+        if changes.is_empty() {
+            return None;
+        }
+        changes.sort_by_key(|&(gift_id, ..)| gift_id);
+
+        let mut report = "🗂 Catalog changed\n".to_string();
+        for (gift_id, state, change) in changes {
+            let title = state.title.as_deref().unwrap_or("untitled");
+            match change {
+                CatalogChange::Removed => {
+                    writeln!(report, "➖ {title} ({gift_id}) removed from the catalog")
+                }
+                CatalogChange::SoldOut => writeln!(report, "⛔ {title} ({gift_id}) sold out"),
+                CatalogChange::ResaleAvailable(floor) => {
+                    writeln!(report, "🏷 {title} ({gift_id}) on resale from {floor} ⭐")
+                }
+            }
+            .unwrap();
+        }
+
+        Some(report)
+    }
+}
+
+// collections of unique gifts seen in the catalog, by the id of the gift they're
+// upgraded from, a collection launches when a gift becomes upgradable or its first
+// unique shows up in the catalog, gifts new to the catalog are left to the new gift
+// notifications, which tell their upgrade price
+#[derive(Default)]
+pub struct CollectionLaunches {
+    // `None` until the first catalog, which is the baseline
+    known: Option<HashSet<i64>>,
+    // the gifts of the previous catalog
+    listed: HashSet<i64>,
+}
+
+impl CollectionLaunches {
+    // a report of the collections launched since the previous catalog, `None` when none were
+    pub fn update(&mut self, gifts: &[StarGift]) -> Option<String> {
+        let is_baseline = self.known.is_none();
+        let known = self.known.get_or_insert_default();
+        let listed = std::mem::replace(
+            &mut self.listed,
+            gifts
+                .iter()
+                .filter_map(|gift| match gift {
+                    StarGift::Gift(gift) => Some(gift.id),
+                    StarGift::Unique(_) => None,
+                })
+                .collect(),
+        );
+
+        let mut report = "🆕 New unique collection\n".to_string();
+        let mut launched = false;
+        for gift in gifts {
+            let line = match gift {
+                StarGift::Gift(gift) if gift.upgrade_stars.is_some() => {
+                    if !known.insert(gift.id) || !listed.contains(&gift.id) {
+                        continue;
+                    }
+                    format!(
+                        "⬆️ {} ({}) can be upgraded for {} ⭐",
+                        gift.title.as_deref().unwrap_or("untitled"),
+                        gift.id,
+                        gift.upgrade_stars.unwrap_or_default()
+                    )
+                }
+                StarGift::Gift(_) => continue,
+                StarGift::Unique(unique) => {
+                    if !known.insert(unique.gift_id) {
+                        continue;
+                    }
+                    format!(
+                        "💎 {} ({}) https://t.me/nft/{}",
+                        unique.title, unique.gift_id, unique.slug
+                    )
+                }
+            };
+            writeln!(report, "{line}").unwrap();
+            launched = true;
+        }
+
+        (launched && !is_baseline).then_some(report)
+    }
+}
+
+pub fn spawn_notify_catalog_changes(bot: Arc<Bot>, pool: Arc<SqlitePool>, report: String) {
+    tokio::spawn(async move {
+        if let Err(err) = alert_chats(&bot, &pool, &report).await {
+            tracing::error!(?err, "failed to send catalog changes");
+        }
+    });
+}
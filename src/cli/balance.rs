@@ -0,0 +1,104 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    cli::ExitError,
+    config::{self, Account},
+    wrapped_client::WrappedClient,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+#[derive(Serialize)]
+struct AccountBalance {
+    phone_number: String,
+    balance: Option<i64>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Balances {
+    accounts: Vec<AccountBalance>,
+    total: i64,
+}
+
+// connects from stored sessions only, accounts that aren't logged in are reported instead
+pub async fn process(config_path: &Path, phone_numbers: Vec<String>, json: bool) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    let accounts = if phone_numbers.is_empty() {
+        accounts.all()?
+    } else {
+        phone_numbers
+            .iter()
+            .map(|phone_number| accounts.get(phone_number))
+            .collect::<Result<_, _>>()?
+    };
+
+    let balances = join_all(accounts.into_iter().map(|account| {
+        let pool = pool.clone();
+        async move {
+            let phone_number = account.phone_number.clone();
+            (phone_number, get_balance(pool, account).await)
+        }
+    }))
+    .await;
+
+    let accounts: Vec<_> = balances
+        .into_iter()
+        .map(|(phone_number, result)| match result {
+            Ok(balance) => AccountBalance {
+                phone_number,
+                balance: Some(balance),
+                error: None,
+            },
+            Err(err) => AccountBalance {
+                phone_number,
+                balance: None,
+                error: Some(err.to_string()),
+            },
+        })
+        .collect();
+    let total = accounts.iter().filter_map(|account| account.balance).sum();
+
+    if json {
+        let balances = Balances { accounts, total };
+        println!("{}", serde_json::to_string_pretty(&balances)?);
+        return Ok(());
+    }
+
+    println!("{:<16} BALANCE", "PHONE NUMBER");
+
+    for account in accounts {
+        match (account.balance, account.error) {
+            (Some(balance), _) => println!("{:<16} {balance} ⭐", account.phone_number),
+            (None, error) => println!(
+                "{:<16} error: {}",
+                account.phone_number,
+                error.unwrap_or_default()
+            ),
+        }
+    }
+
+    println!("{:<16} {total} ⭐", "TOTAL");
+
+    Ok(())
+}
+
+async fn get_balance(pool: Arc<SqlitePool>, account: Account) -> Result<i64> {
+    let client = WrappedClient::connect(pool, account).await?;
+    if !client.is_authorized().await? {
+        bail!(ExitError::Auth("not logged in".to_string()));
+    }
+    Ok(client.get_stars_balance().await?)
+}
@@ -1,58 +1,129 @@
-use std::sync::Arc;
+use std::{collections::HashMap, path::Path, sync::Arc, time::SystemTime};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use serde::Deserialize;
 use sqlx::SqlitePool;
 use teloxide::Bot;
 
 use crate::{
-    core::{BuyGiftsDestination, buy_gifts},
-    wrapped_client::WrappedClient,
+    bot::BotLoginCodes,
+    cli::{ExitError, load_daily_limits},
+    config,
+    core::{
+        Allocation, BuyDestinations, BuyLimits, GiftOptions, PurchaseCoordinator, buy_gifts,
+        wait_purchase_tasks,
+    },
+    wrapped_client::{LoginCodeSource, WrappedClient},
 };
 
 #[derive(Deserialize)]
 struct Config {
-    api_id: i32,
-    api_hash: String,
-    phone_numbers: Vec<String>,
     bot_token: String,
+    // allowed to send login codes with `login_code_source = "bot"`
+    #[serde(default, deserialize_with = "config::comma_separated")]
+    admin_usernames: Vec<String>,
     database_url: String,
+    #[serde(default)]
+    login_code_source: LoginCodeSource,
     // dest_channel_username: String,
 }
 
-pub async fn process(gift_id: i64, limit: Option<u64>) -> Result<()> {
-    let config: Config = envy::from_env()?;
+// all gifts are bought in one job, so accounts log in once
+pub async fn process(
+    config_path: &Path,
+    gifts: Vec<(i64, Option<u64>)>,
+    limit: Option<u64>,
+    dest: &BuyDestinations,
+    gift_options: &GiftOptions,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+    let coordinator = Arc::new(PurchaseCoordinator::with_daily_limits(load_daily_limits(
+        config_path,
+    )?));
 
     let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
     let bot = Arc::new(Bot::new(config.bot_token));
 
+    let clients = login_clients(
+        pool.clone(),
+        bot.clone(),
+        &accounts,
+        &config.login_code_source,
+        config.admin_usernames,
+    )
+    .await?;
+
+    let gift_ids = gifts.iter().map(|&(gift_id, _)| gift_id).collect();
+    let limits = BuyLimits {
+        default: limit,
+        per_gift: gifts
+            .iter()
+            .filter_map(|&(gift_id, limit)| Some((gift_id, limit?)))
+            .collect(),
+        totals: HashMap::new(),
+        // bought by hand, so not capped by `max_price`
+        max_price: None,
+        allocation: Allocation::PerAccount,
+        max_buy_duration: None,
+    };
+
+    let outcome = buy_gifts(
+        &clients,
+        bot.clone(),
+        pool.clone(),
+        &coordinator,
+        gift_ids,
+        None,
+        &limits,
+        dest,
+        gift_options,
+        SystemTime::now(),
+    )
+    .await?;
+    wait_purchase_tasks().await;
+
+    if outcome.failed > 0 {
+        bail!(ExitError::PartialBuy {
+            bought: outcome.bought,
+            failed: outcome.failed,
+        });
+    }
+
+    Ok(())
+}
+
+// logs in every configured account, prompting for codes as needed
+pub(super) async fn login_clients(
+    pool: Arc<SqlitePool>,
+    bot: Arc<Bot>,
+    accounts: &config::Accounts,
+    login_code_source: &LoginCodeSource,
+    admin_usernames: Vec<String>,
+) -> Result<Vec<Arc<WrappedClient>>> {
+    let admin_usernames: Arc<[String]> = admin_usernames.into();
+    let login_codes = Arc::new(BotLoginCodes::new(bot, pool.clone()));
+    // `run_bot` isn't running yet, so `/code` replies have to be received separately
+    let login_polling = matches!(login_code_source, LoginCodeSource::Bot)
+        .then(|| tokio::spawn(login_codes.clone().poll(admin_usernames.clone())));
+
     let mut clients = vec![];
 
-    for phone_number in config.phone_numbers {
+    for account in accounts.all()? {
         clients.push(Arc::new(
             WrappedClient::new(
                 pool.clone(),
-                phone_number,
-                config.api_id,
-                config.api_hash.clone(),
+                account,
+                login_code_source,
+                Some(login_codes.as_ref()),
             )
             .await?,
         ));
     }
 
-    // let dest = MaybeResolvedChannel::Username(config.dest_channel_username);
-    let buy_dest = BuyGiftsDestination::PeerSelf;
-
-    buy_gifts(
-        &clients,
-        bot.clone(),
-        pool.clone(),
-        vec![gift_id],
-        None,
-        limit,
-        &buy_dest,
-    )
-    .await?;
+    if let Some(login_polling) = login_polling {
+        login_polling.abort();
+    }
 
-    Ok(())
+    Ok(clients)
 }
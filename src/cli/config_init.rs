@@ -0,0 +1,30 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use anyhow::Result;
+
+const CONFIG_TEMPLATE: &str = include_str!("../../config.example.toml");
+const ENV_TEMPLATE: &str = include_str!("../../.env.example");
+
+pub fn process(config_path: &Path, env_path: &Path, force: bool) -> Result<()> {
+    write_template(config_path, CONFIG_TEMPLATE, force)?;
+    write_template(env_path, ENV_TEMPLATE, force)?;
+    Ok(())
+}
+
+fn write_template(path: &Path, template: &str, force: bool) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        // don't clobber an already filled in config
+        options.create_new(true);
+    }
+
+    options.open(path)?.write_all(template.as_bytes())?;
+
+    tracing::info!(path = %path.display(), "written template");
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::{collections::BTreeSet, fmt::Display, path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use teloxide::{Bot, prelude::Requester};
+
+use crate::{
+    config,
+    core::MaybeResolvedChannel,
+    db::{MIGRATOR, get_applied_migrations},
+    wrapped_client::WrappedClient,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    bot_token: String,
+    database_url: String,
+    dest_channel_username: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Check {
+    check: String,
+    ok: bool,
+    details: String,
+}
+
+// with `json` the checks are printed at once by `print_json`
+struct Report {
+    json: bool,
+    checks: Vec<Check>,
+    failures: usize,
+}
+
+impl Report {
+    fn ok(&mut self, check: &str, details: impl Display) {
+        if !self.json {
+            println!("✅ {check}: {details}");
+        }
+        self.checks.push(Check {
+            check: check.to_string(),
+            ok: true,
+            details: details.to_string(),
+        });
+    }
+
+    fn fail(&mut self, check: &str, err: impl Display) {
+        if !self.json {
+            println!("❌ {check}: {err}");
+        }
+        self.checks.push(Check {
+            check: check.to_string(),
+            ok: false,
+            details: err.to_string(),
+        });
+        self.failures += 1;
+    }
+
+    fn print_json(&self) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&self.checks)?);
+        }
+        Ok(())
+    }
+}
+
+pub async fn process(config_path: &Path, json: bool) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let mut report = Report {
+        json,
+        checks: vec![],
+        failures: 0,
+    };
+
+    let pool = match SqlitePool::connect(&config.database_url).await {
+        Ok(pool) => {
+            report.ok("database", &config.database_url);
+            Arc::new(pool)
+        }
+        Err(err) => {
+            report.fail("database", err);
+            report.print_json()?;
+            bail!("database is required for the remaining checks");
+        }
+    };
+
+    match get_applied_migrations(&*pool).await {
+        Ok(applied) => {
+            let applied: BTreeSet<_> = applied.into_iter().collect();
+            let pending: Vec<_> = MIGRATOR
+                .iter()
+                .filter(|migration| !migration.migration_type.is_down_migration())
+                .filter(|migration| !applied.contains(&migration.version))
+                .map(|migration| migration.description.to_string())
+                .collect();
+
+            if pending.is_empty() {
+                report.ok("schema", "all migrations applied");
+            } else {
+                report.fail(
+                    "schema",
+                    format!("pending migrations: {}", pending.join(", ")),
+                );
+            }
+        }
+        Err(err) => report.fail("schema", err),
+    }
+
+    match Bot::new(config.bot_token).get_me().await {
+        Ok(me) => report.ok("bot", format!("@{}", me.username())),
+        Err(err) => report.fail("bot", err),
+    }
+
+    let mut clients = vec![];
+
+    for account in accounts.all()? {
+        let check = format!("session {}", account.phone_number);
+
+        let client = match WrappedClient::connect(pool.clone(), account).await {
+            Ok(t) => t,
+            Err(err) => {
+                report.fail(&check, err);
+                continue;
+            }
+        };
+
+        match client.is_authorized().await {
+            Ok(true) => {}
+            Ok(false) => {
+                report.fail(&check, "not authorized, run `login`");
+                continue;
+            }
+            Err(err) => {
+                report.fail(&check, err);
+                continue;
+            }
+        }
+
+        match client.get_stars_balance().await {
+            Ok(balance) => report.ok(&check, format!("authorized, balance {balance} ⭐️")),
+            Err(err) => report.fail(&check, err),
+        }
+
+        clients.push(client);
+    }
+
+    if let Some(username) = config.dest_channel_username {
+        let check = format!("destination @{username}");
+
+        match clients.first() {
+            Some(client) => match MaybeResolvedChannel::Username(username)
+                .resolve(&client.client())
+                .await
+            {
+                Ok(channel) => report.ok(&check, format!("channel_id {}", channel.channel_id)),
+                Err(err) => report.fail(&check, err),
+            },
+            None => report.fail(&check, "no authorized client to resolve with"),
+        }
+    }
+
+    report.print_json()?;
+
+    if report.failures > 0 {
+        bail!("{} check(s) failed", report.failures);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    cli::ExitError, config, core::set_gift_saved, db::get_purchase_message_ids,
+    wrapped_client::WrappedClient,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+// `bought` adds the gifts this bot bought to the account itself to `msg_ids`, a failed
+// gift doesn't stop the rest
+pub async fn process(
+    config_path: &Path,
+    phone_number: &str,
+    mut msg_ids: Vec<i32>,
+    bought: bool,
+    show: bool,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    if bought {
+        msg_ids.extend(get_purchase_message_ids(&*pool, phone_number).await?);
+        msg_ids.sort_unstable();
+        msg_ids.dedup();
+    }
+
+    let account = accounts.get(phone_number)?;
+    let client = WrappedClient::connect(pool, account).await?;
+    if !client.is_authorized().await? {
+        bail!(ExitError::Auth(format!(
+            "{phone_number} isn't logged in, run `login` first"
+        )));
+    }
+
+    let mut failed = 0;
+    for msg_id in &msg_ids {
+        match set_gift_saved(&client, *msg_id, show).await {
+            Ok(()) => println!("{msg_id}: {}", if show { "shown" } else { "hidden" }),
+            Err(err) => {
+                failed += 1;
+                println!("{msg_id}: error: {err}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} gifts failed", msg_ids.len());
+    }
+
+    Ok(())
+}
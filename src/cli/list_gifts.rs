@@ -0,0 +1,128 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use grammers_client::grammers_tl_types::{
+    enums::{Document, DocumentAttribute, StarGift, payments::StarGifts},
+    functions::payments::GetStarGifts,
+    types,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{cli::connect_first_account, config};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+#[derive(Serialize)]
+struct GiftRow {
+    id: i64,
+    title: String,
+    stars: i64,
+    supply: Option<i32>,
+    remains: Option<i32>,
+    limited: bool,
+    sold_out: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    pub limited: bool,
+    pub available: bool,
+    pub max_price: Option<i64>,
+    pub max_supply: Option<i32>,
+}
+
+impl Filter {
+    fn matches(&self, gift: &types::StarGift) -> bool {
+        (!self.limited || gift.limited)
+            && (!self.available || !gift.sold_out)
+            && self
+                .max_price
+                .is_none_or(|max_price| gift.stars <= max_price)
+            && self.max_supply.is_none_or(|max_supply| {
+                gift.availability_total
+                    .is_some_and(|supply| supply <= max_supply)
+            })
+    }
+}
+
+// only needs the first account with a stored session
+pub async fn process(config_path: &Path, filter: Filter, json: bool) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    let client = connect_first_account(pool, &accounts).await?;
+
+    let StarGifts::Gifts(star_gifts) = client.invoke(&GetStarGifts { hash: 0 }).await? else {
+        bail!("unexpected not modified");
+    };
+
+    let rows: Vec<_> = star_gifts
+        .gifts
+        .into_iter()
+        .filter_map(|gift| match gift {
+            StarGift::Gift(gift) if filter.matches(&gift) => Some(GiftRow {
+                id: gift.id,
+                title: title(&gift),
+                stars: gift.stars,
+                supply: gift.availability_total,
+                remains: gift.availability_remains,
+                limited: gift.limited,
+                sold_out: gift.sold_out,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<24} {:>8} {:>10} {:>10} {:<8} SOLD OUT",
+        "ID", "TITLE", "STARS", "SUPPLY", "REMAINS", "LIMITED"
+    );
+
+    let format_count = |count: Option<i32>| count.map_or("-".to_string(), |c| c.to_string());
+
+    for row in rows {
+        println!(
+            "{:<20} {:<24} {:>8} {:>10} {:>10} {:<8} {}",
+            row.id,
+            row.title,
+            row.stars,
+            format_count(row.supply),
+            format_count(row.remains),
+            if row.limited { "yes" } else { "no" },
+            if row.sold_out { "yes" } else { "no" },
+        );
+    }
+
+    Ok(())
+}
+
+// the title when set, otherwise the emoji of the sticker
+fn title(gift: &types::StarGift) -> String {
+    if let Some(title) = &gift.title {
+        return title.clone();
+    }
+
+    let Document::Document(document) = &gift.sticker else {
+        return "-".to_string();
+    };
+
+    document
+        .attributes
+        .iter()
+        .find_map(|attribute| match attribute {
+            DocumentAttribute::Sticker(sticker) => Some(sticker.alt.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
@@ -1,32 +1,71 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use serde::Deserialize;
 use sqlx::SqlitePool;
+use teloxide::Bot;
 
-use crate::wrapped_client::WrappedClient;
+use crate::{
+    bot::BotLoginCodes,
+    cli::ExitError,
+    config,
+    wrapped_client::{LoginCodeSource, WrappedClient},
+};
 
 #[derive(Deserialize)]
 struct Config {
-    api_id: i32,
-    api_hash: String,
-    phone_numbers: Vec<String>,
     database_url: String,
+    #[serde(default)]
+    login_code_source: LoginCodeSource,
+    // only required with `login_code_source = "bot"`
+    bot_token: Option<String>,
+    #[serde(default, deserialize_with = "config::comma_separated")]
+    admin_usernames: Vec<String>,
 }
 
-pub async fn process() -> Result<()> {
-    let config: Config = envy::from_env()?;
+// `qr` authorizes by scanning a QR code instead of entering a login code
+pub async fn process(config_path: &Path, qr: bool) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
 
     let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
 
-    for phone_number in config.phone_numbers {
-        WrappedClient::new(
+    let login_codes = match (&config.login_code_source, config.bot_token) {
+        (LoginCodeSource::Bot, Some(bot_token)) => Some(Arc::new(BotLoginCodes::new(
+            Arc::new(Bot::new(bot_token)),
             pool.clone(),
-            phone_number,
-            config.api_id,
-            config.api_hash.clone(),
-        )
-        .await?;
+        ))),
+        (LoginCodeSource::Bot, None) => {
+            bail!(ExitError::Config(
+                "bot_token is required with login_code_source = \"bot\"".to_string()
+            ))
+        }
+        _ => None,
+    };
+
+    let login_polling = login_codes
+        .clone()
+        .map(|login_codes| tokio::spawn(login_codes.poll(config.admin_usernames.into())));
+
+    for account in accounts.all()? {
+        if qr {
+            WrappedClient::connect(pool.clone(), account)
+                .await?
+                .qr_login(&config.login_code_source)
+                .await?;
+        } else {
+            WrappedClient::new(
+                pool.clone(),
+                account,
+                &config.login_code_source,
+                login_codes.as_deref(),
+            )
+            .await?;
+        }
+    }
+
+    if let Some(login_polling) = login_polling {
+        login_polling.abort();
     }
 
     Ok(())
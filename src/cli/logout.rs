@@ -0,0 +1,45 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Result;
+use grammers_client::grammers_tl_types::functions::auth::{LogOut, ResetAuthorizations};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{config, db::delete_session, wrapped_client::WrappedClient};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+// `terminate_others` also revokes every other authorized session of the accounts
+pub async fn process(
+    config_path: &Path,
+    phone_numbers: Vec<String>,
+    terminate_others: bool,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    for phone_number in phone_numbers {
+        let client = WrappedClient::connect(pool.clone(), accounts.get(&phone_number)?).await?;
+
+        if client.is_authorized().await? {
+            if terminate_others {
+                client.invoke(&ResetAuthorizations {}).await?;
+                tracing::info!(phone_number, "terminated other sessions");
+            }
+
+            client.invoke(&LogOut {}).await?;
+            tracing::info!(phone_number, "logged out");
+        } else {
+            tracing::warn!(phone_number, "session is not authorized, only deleting it");
+        }
+
+        delete_session(&*pool, &phone_number).await?;
+    }
+
+    Ok(())
+}
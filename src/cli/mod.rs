@@ -1,51 +1,581 @@
-use anyhow::Result;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
 use clap::{Parser, Subcommand};
+use sqlx::SqlitePool;
+
+use crate::{
+    config,
+    core::{
+        BuyDestinations, BuyGiftsDestination, DailySpendLimits, GiftOptions, GiftType, SupplyRules,
+    },
+    daemon, db,
+    wrapped_client::{self, ErrorClass, WrappedClient},
+};
 
+mod balance;
 mod buy_gifts;
+mod config_init;
+mod doctor;
+mod hide_gifts;
+mod list_gifts;
 mod login;
+mod logout;
+mod send_gift;
+mod sessions;
+mod simulate;
 mod start;
+mod stats;
+mod upgrade_preview;
+mod watch_gift;
+mod worker;
+
+// process exit codes wrapper scripts and systemd OnFailure handlers can branch on,
+// any other error exits with 1
+pub const EXIT_CONFIG: u8 = 2;
+pub const EXIT_AUTH: u8 = 3;
+pub const EXIT_DB: u8 = 4;
+pub const EXIT_PARTIAL_BUY: u8 = 5;
+
+// failures raised by the commands themselves that map to an exit code
+#[derive(Debug, thiserror::Error)]
+pub enum ExitError {
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    Auth(String),
+    #[error("{failed} purchase(s) failed, {bought} bought")]
+    PartialBuy { bought: u64, failed: u64 },
+}
+
+// the first cause in the chain with its own exit code decides it
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ExitError>() {
+            return match err {
+                ExitError::Config(_) => EXIT_CONFIG,
+                ExitError::Auth(_) => EXIT_AUTH,
+                ExitError::PartialBuy { .. } => EXIT_PARTIAL_BUY,
+            };
+        }
+        if cause.is::<figment::Error>() {
+            return EXIT_CONFIG;
+        }
+        if cause.is::<db::Error>()
+            || cause.is::<sqlx::Error>()
+            || cause.is::<sqlx::migrate::MigrateError>()
+        {
+            return EXIT_DB;
+        }
+        if let Some(err) = cause.downcast_ref::<wrapped_client::Error>() {
+            match err {
+                wrapped_client::Error::AppDb(_) => return EXIT_DB,
+                wrapped_client::Error::GrammersAuthorization(_)
+                | wrapped_client::Error::GrammersSignIn(_)
+                | wrapped_client::Error::UnexpectedLoginToken
+                | wrapped_client::Error::PasswordNotConfigured(_)
+                | wrapped_client::Error::UnsupportedPassword(_) => return EXIT_AUTH,
+                wrapped_client::Error::GrammersInvocation(err)
+                    if ErrorClass::of(err) == ErrorClass::Auth =>
+                {
+                    return EXIT_AUTH;
+                }
+                _ => {}
+            }
+        }
+        if let Some(err) = cause.downcast_ref::<grammers_client::InvocationError>()
+            && ErrorClass::of(err) == ErrorClass::Auth
+        {
+            return EXIT_AUTH;
+        }
+    }
+
+    1
+}
 
 #[derive(Debug, Parser)]
 pub struct Cli {
     #[clap(subcommand)]
     command: Command,
+    /// TOML config file, values from env vars take precedence
+    #[clap(long, global = true, default_value = "config.toml")]
+    config: PathBuf,
+    #[clap(long, global = true, default_value = "gift-sniper.pid")]
+    pid_file: PathBuf,
+    /// Where stdout/stderr are redirected in daemon mode
+    #[clap(long, global = true, default_value = "logs/daemon.log")]
+    log_file: PathBuf,
+    /// Print JSON instead of tables (list-gifts, balance, stats, sessions, doctor)
+    #[clap(long, global = true)]
+    json: bool,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
     Start(Start),
     BuyGift(BuyGift),
-    Login,
+    Login(Login),
+    /// Log out accounts and delete their stored sessions
+    Logout(Logout),
+    /// List stored sessions and check whether they are still authorized
+    Sessions,
+    /// Stop the instance started with --daemon
+    Stop,
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Check config, database, bot and sessions before a drop
+    Doctor,
+    /// Print purchase latency percentiles, or the price/supply history of a gift
+    Stats(Stats),
+    /// Print the current star gift catalog
+    ListGifts(ListGifts),
+    /// Print the stars balance of every account
+    Balance(Balance),
+    /// Send a new or an owned unique gift to a user or channel
+    SendGift(SendGift),
+    /// Print the upgrade cost and possible attributes of a gift
+    UpgradePreview(UpgradePreview),
+    /// Poll a single gift until a condition is met, then alert (and buy)
+    WatchGift(WatchGift),
+    /// Replay recorded catalog snapshots against a strategy and compare with purchases
+    Simulate(Simulate),
+    /// Hide received gifts from an account's profile, or display them with --show
+    HideGifts(HideGifts),
+    /// Buy the gifts queued by `start --watch-only`
+    Worker(Worker),
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Write annotated config and .env templates
+    Init(ConfigInit),
 }
 
 #[derive(Debug, Parser)]
 struct Start {
-    #[clap(long)]
+    /// Gift types to detect, comma separated: limited, unlimited, resale
+    #[clap(long, value_delimiter = ',', default_value = "limited")]
+    gift_types: Vec<GiftType>,
+    /// Deprecated, same as adding `unlimited` to --gift-types
+    #[clap(long, hide = true)]
     ignore_not_limited: bool,
     #[clap(long)]
     buy: bool,
     #[clap(long)]
     buy_limit: Option<u64>,
+    /// Queue the gifts to buy for `worker` processes instead of buying them
+    #[clap(long, conflicts_with = "buy")]
+    watch_only: bool,
+    /// Run a single poll cycle (detect, notify and buy with --buy) and exit, e.g. from cron,
+    /// gifts detected by earlier runs are skipped and the first run only records the catalog
+    #[clap(long)]
+    once: bool,
+    /// Detach from the terminal and run in the background
+    #[clap(long)]
+    daemon: bool,
+}
+
+#[derive(Debug, Parser)]
+struct Worker {
+    /// Detach from the terminal and run in the background
+    #[clap(long)]
+    daemon: bool,
 }
 
 #[derive(Debug, Parser)]
 struct BuyGift {
-    gift_id: i64,
+    /// Gift IDs in priority order, each optionally with its own limit as ID:LIMIT
+    #[clap(required = true, value_parser = parse_gift_limit)]
+    gifts: Vec<(i64, Option<u64>)>,
+    /// Units of each gift per account, unless set per gift
+    #[clap(long)]
     limit: Option<u64>,
+    /// Recipient of the gifts, `self` or a @username of a user or channel, units are split
+    /// between several comma-separated ones by their weights, as @channel:60,self:40
+    #[clap(long, default_value = "self")]
+    dest: BuyDestinations,
+    /// Hide the sender's name from the recipient
+    #[clap(long)]
+    hide_name: bool,
+    /// Also pay for the upgrade to a unique gift
+    #[clap(long)]
+    upgrade: bool,
+    /// Message attached to the gifts
+    #[clap(long)]
+    message: Option<String>,
+    /// Hide the gifts from the account's profile, with `--dest self` only
+    #[clap(long)]
+    hide_on_profile: bool,
+}
+
+fn parse_gift_limit(s: &str) -> Result<(i64, Option<u64>), String> {
+    let (gift_id, limit) = match s.split_once(':') {
+        Some((gift_id, limit)) => (gift_id, Some(limit)),
+        None => (s, None),
+    };
+
+    let gift_id = gift_id
+        .parse()
+        .map_err(|err| format!("invalid gift id: {err}"))?;
+    let limit = limit
+        .map(str::parse)
+        .transpose()
+        .map_err(|err| format!("invalid limit: {err}"))?;
+
+    Ok((gift_id, limit))
+}
+
+#[derive(Debug, Parser)]
+struct Login {
+    /// Authorize by scanning a QR code from the app instead of entering a login code
+    #[clap(long)]
+    qr: bool,
+}
+
+#[derive(Debug, Parser)]
+struct Logout {
+    #[clap(required = true)]
+    phone_numbers: Vec<String>,
+    /// Also terminate all other sessions of the accounts
+    #[clap(long)]
+    terminate_others: bool,
+}
+
+#[derive(Debug, Parser)]
+struct Stats {
+    #[clap(long, default_value_t = 24)]
+    hours: u64,
+    /// Print the observed price, remains and sold out state of this gift instead
+    #[clap(long)]
+    gift: Option<i64>,
+    /// Export the gift history as CSV
+    #[clap(long, requires = "gift", conflicts_with = "json")]
+    csv: bool,
+}
+
+#[derive(Debug, Parser)]
+struct ListGifts {
+    /// Only limited gifts
+    #[clap(long)]
+    limited: bool,
+    /// Hide sold out gifts
+    #[clap(long)]
+    available: bool,
+    #[clap(long)]
+    max_price: Option<i64>,
+    /// Only limited gifts with a total supply of at most this
+    #[clap(long)]
+    max_supply: Option<i32>,
+}
+
+#[derive(Debug, Parser)]
+struct Balance {
+    /// Only these accounts instead of all configured ones
+    phone_numbers: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+#[clap(group(clap::ArgGroup::new("gift").required(true).args(["gift_id", "msg_id"])))]
+struct SendGift {
+    /// Account sending the gift
+    phone_number: String,
+    /// A @username of a user or channel, or a user ID from the account's chats
+    recipient: BuyGiftsDestination,
+    /// Buy a new gift for the recipient
+    #[clap(long)]
+    gift_id: Option<i64>,
+    /// Transfer an owned unique gift, by the ID of the message it was received with
+    #[clap(long)]
+    msg_id: Option<i32>,
+    /// Hide the sender's name from the recipient, new gifts only
+    #[clap(long)]
+    hide_name: bool,
+    /// Message attached to new gifts
+    #[clap(long)]
+    message: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct UpgradePreview {
+    gift_id: i64,
+}
+
+#[derive(Debug, Parser)]
+struct WatchGift {
+    gift_id: i64,
+    /// Wait until the gift isn't sold out
+    #[clap(long)]
+    available: bool,
+    #[clap(long)]
+    max_price: Option<i64>,
+    /// Wait until a resale listing costs at most this
+    #[clap(long)]
+    max_resale_price: Option<i64>,
+    #[clap(long, default_value_t = 500)]
+    interval_ms: u64,
+    /// Buy up to this many units per account once the condition is met, not with
+    /// --max-resale-price, since resale listings aren't bought from the catalog
+    #[clap(long, conflicts_with = "max_resale_price")]
+    buy: Option<u64>,
+}
+
+#[derive(Debug, Parser)]
+struct Simulate {
+    /// Config with the rules to simulate (e.g. supply_rules), defaults to --config
+    #[clap(long)]
+    rules: Option<PathBuf>,
+    /// Gift types to simulate buying, comma separated: limited, unlimited
+    #[clap(long, value_delimiter = ',', default_value = "limited")]
+    gift_types: Vec<GiftType>,
+    /// Deprecated, same as adding `unlimited` to --gift-types
+    #[clap(long, hide = true)]
+    ignore_not_limited: bool,
+    #[clap(long)]
+    buy_limit: Option<u64>,
+    #[clap(long, default_value_t = 1)]
+    accounts: u64,
+    /// Stars of every account, unlimited when unset
+    #[clap(long)]
+    balance: Option<i64>,
+    #[clap(long, default_value_t = 24 * 7)]
+    hours: u64,
+}
+
+#[derive(Debug, Parser)]
+#[clap(group(clap::ArgGroup::new("gifts").required(true).args(["msg_ids", "bought"])))]
+struct HideGifts {
+    phone_number: String,
+    /// IDs of the messages the gifts were received with
+    msg_ids: Vec<i32>,
+    /// Every gift this bot bought to the account itself, not the ones bought to others
+    #[clap(long)]
+    bought: bool,
+    /// Display the gifts on the profile instead
+    #[clap(long)]
+    show: bool,
+}
+
+#[derive(Debug, Parser)]
+struct ConfigInit {
+    #[clap(long, default_value = ".env.example")]
+    env_path: PathBuf,
+    /// Overwrite existing files
+    #[clap(long)]
+    force: bool,
+}
+
+// for read-only commands, which don't need every account logged in
+async fn connect_first_account(
+    pool: Arc<SqlitePool>,
+    accounts: &config::Accounts,
+) -> Result<WrappedClient> {
+    let Some(account) = accounts.all()?.into_iter().next() else {
+        bail!(ExitError::Config("no accounts configured".to_string()));
+    };
+    let phone_number = account.phone_number.clone();
+
+    let client = WrappedClient::connect(pool, account).await?;
+    if !client.is_authorized().await? {
+        bail!(ExitError::Auth(format!(
+            "{phone_number} isn't logged in, run `login` first"
+        )));
+    }
+
+    Ok(client)
+}
+
+// the daily spend caps, shared by every command that buys
+fn load_daily_limits(config_path: &Path) -> Result<DailySpendLimits> {
+    let limits: DailySpendLimits = config::load(config_path)?;
+    if limits.reset_hour >= 24 {
+        bail!(ExitError::Config(format!(
+            "daily_reset_hour must be below 24, got {}",
+            limits.reset_hour
+        )));
+    }
+
+    Ok(limits)
+}
+
+// `supply_rules`, or the deprecated `max_supply` it replaced as a single `max` band,
+// `None` when neither is set
+fn parse_supply_rules(
+    supply_rules: Option<&str>,
+    max_supply: Option<i32>,
+) -> Result<Option<SupplyRules>> {
+    match (supply_rules, max_supply) {
+        (Some(_), Some(_)) => bail!(ExitError::Config(
+            "set either supply_rules or the deprecated max_supply, not both".to_string()
+        )),
+        (Some(supply_rules), None) => Ok(Some(supply_rules.parse()?)),
+        (None, Some(max_supply)) => {
+            tracing::warn!("max_supply is deprecated, use supply_rules = \"{max_supply}:max\"");
+            Ok(Some(format!("{max_supply}:max").parse()?))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+// the deprecated --ignore-not-limited of `start` and `simulate`
+fn add_unlimited_gift_type(gift_types: &mut Vec<GiftType>) {
+    tracing::warn!("--ignore-not-limited is deprecated, use --gift-types limited,unlimited");
+    if !gift_types.contains(&GiftType::Unlimited) {
+        gift_types.push(GiftType::Unlimited);
+    }
 }
 
 impl Cli {
+    pub fn daemon(&self) -> Option<(&Path, &Path)> {
+        let daemon = match &self.command {
+            Command::Start(start) => start.daemon,
+            Command::Worker(worker) => worker.daemon,
+            _ => false,
+        };
+        daemon.then_some((self.pid_file.as_path(), self.log_file.as_path()))
+    }
+
     pub async fn process(self) -> Result<()> {
         match self.command {
             Command::Start(Start {
+                mut gift_types,
                 ignore_not_limited,
                 buy,
                 buy_limit,
-            }) => start::process(ignore_not_limited, buy, buy_limit).await,
-            Command::BuyGift(BuyGift { gift_id, limit }) => {
-                buy_gifts::process(gift_id, limit).await
+                watch_only,
+                once,
+                ..
+            }) => {
+                if ignore_not_limited {
+                    add_unlimited_gift_type(&mut gift_types);
+                }
+                start::process(&self.config, &gift_types, buy, buy_limit, watch_only, once).await
+            }
+            Command::BuyGift(BuyGift {
+                gifts,
+                limit,
+                dest,
+                hide_name,
+                upgrade,
+                message,
+                hide_on_profile,
+            }) => {
+                let gift_options = GiftOptions {
+                    hide_name,
+                    include_upgrade: upgrade,
+                    message,
+                    unsave: hide_on_profile,
+                };
+                buy_gifts::process(&self.config, gifts, limit, &dest, &gift_options).await
+            }
+            Command::Login(Login { qr }) => login::process(&self.config, qr).await,
+            Command::Sessions => sessions::process(&self.config, self.json).await,
+            Command::Logout(Logout {
+                phone_numbers,
+                terminate_others,
+            }) => logout::process(&self.config, phone_numbers, terminate_others).await,
+            Command::Stop => Ok(daemon::stop(&self.pid_file)?),
+            Command::Doctor => doctor::process(&self.config, self.json).await,
+            Command::Stats(Stats { hours, gift, csv }) => {
+                stats::process(&self.config, hours, gift, csv, self.json).await
+            }
+            Command::ListGifts(ListGifts {
+                limited,
+                available,
+                max_price,
+                max_supply,
+            }) => {
+                let filter = list_gifts::Filter {
+                    limited,
+                    available,
+                    max_price,
+                    max_supply,
+                };
+                list_gifts::process(&self.config, filter, self.json).await
+            }
+            Command::Balance(Balance { phone_numbers }) => {
+                balance::process(&self.config, phone_numbers, self.json).await
+            }
+            Command::SendGift(SendGift {
+                phone_number,
+                recipient,
+                gift_id,
+                msg_id,
+                hide_name,
+                message,
+            }) => {
+                let gift = match (gift_id, msg_id) {
+                    (Some(gift_id), _) => send_gift::Gift::New(gift_id),
+                    (None, Some(msg_id)) => send_gift::Gift::Owned(msg_id),
+                    (None, None) => unreachable!("required by the arg group"),
+                };
+                let gift_options = GiftOptions {
+                    hide_name,
+                    include_upgrade: false,
+                    message,
+                    unsave: false,
+                };
+                send_gift::process(&self.config, &phone_number, &recipient, gift, &gift_options)
+                    .await
+            }
+            Command::UpgradePreview(UpgradePreview { gift_id }) => {
+                upgrade_preview::process(&self.config, gift_id).await
+            }
+            Command::Simulate(Simulate {
+                rules,
+                mut gift_types,
+                ignore_not_limited,
+                buy_limit,
+                accounts,
+                balance,
+                hours,
+            }) => {
+                if ignore_not_limited {
+                    add_unlimited_gift_type(&mut gift_types);
+                }
+                let strategy = simulate::Strategy {
+                    buy_limit,
+                    accounts,
+                    balance,
+                };
+                let rules = rules.as_deref().unwrap_or(&self.config);
+                simulate::process(&self.config, rules, &gift_types, strategy, hours).await
+            }
+            Command::WatchGift(WatchGift {
+                gift_id,
+                available,
+                max_price,
+                max_resale_price,
+                interval_ms,
+                buy,
+            }) => {
+                let condition = watch_gift::Condition {
+                    available,
+                    max_price,
+                    max_resale_price,
+                };
+                watch_gift::process(
+                    &self.config,
+                    gift_id,
+                    condition,
+                    Duration::from_millis(interval_ms),
+                    buy,
+                )
+                .await
+            }
+            Command::Worker(_) => worker::process(&self.config).await,
+            Command::HideGifts(HideGifts {
+                phone_number,
+                msg_ids,
+                bought,
+                show,
+            }) => hide_gifts::process(&self.config, &phone_number, msg_ids, bought, show).await,
+            Command::Config(ConfigCommand::Init(ConfigInit { env_path, force })) => {
+                config_init::process(&self.config, &env_path, force)
             }
-            Command::Login => login::process().await,
         }
     }
 }
@@ -0,0 +1,57 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    cli::ExitError,
+    config,
+    core::{BuyGiftsDestination, GiftOptions, send_gift, transfer_gift},
+    wrapped_client::WrappedClient,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Gift {
+    // bought for the recipient
+    New(i64),
+    // an owned unique gift, by the ID of its service message
+    Owned(i32),
+}
+
+pub async fn process(
+    config_path: &Path,
+    phone_number: &str,
+    recipient: &BuyGiftsDestination,
+    gift: Gift,
+    gift_options: &GiftOptions,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    let account = accounts.get(phone_number)?;
+    let client = WrappedClient::connect(pool, account).await?;
+    if !client.is_authorized().await? {
+        bail!(ExitError::Auth(format!(
+            "{phone_number} isn't logged in, run `login` first"
+        )));
+    }
+
+    let peer = recipient.resolve(&client).await?;
+
+    match gift {
+        Gift::New(gift_id) => send_gift(&client, gift_id, &peer, gift_options).await?,
+        Gift::Owned(msg_id) => transfer_gift(&client, msg_id, &peer).await?,
+    }
+
+    println!("sent");
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::{path::Path, sync::Arc, time::SystemTime};
+
+use anyhow::Result;
+use grammers_client::session::Session;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    config,
+    db::{get_sessions, to_unix_millis},
+    wrapped_client::WrappedClient,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+#[derive(Serialize)]
+struct SessionRow {
+    phone_number: String,
+    // "yes", "no", "revoked" or "error: ..."
+    authorized: String,
+    dc: Option<i32>,
+    // unix millis
+    updated_at: Option<i64>,
+}
+
+pub async fn process(config_path: &Path, json: bool) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    let mut rows = vec![];
+    for stored in get_sessions(&*pool).await? {
+        let dc = Session::load(&stored.session)
+            .ok()
+            .and_then(|session| session.get_user())
+            .map(|user| user.dc);
+
+        let account = accounts.get(&stored.phone_number)?;
+
+        let authorized = match WrappedClient::connect(pool.clone(), account).await {
+            Ok(client) => match client.is_authorized().await {
+                Ok(true) => "yes".to_string(),
+                Ok(false) if stored.deauthorized_at.is_some() => "revoked".to_string(),
+                Ok(false) => "no".to_string(),
+                Err(err) => format!("error: {err}"),
+            },
+            Err(err) => format!("error: {err}"),
+        };
+
+        rows.push(SessionRow {
+            phone_number: stored.phone_number,
+            authorized,
+            dc,
+            updated_at: stored.updated_at,
+        });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<16} {:<14} {:<4} LAST SYNC",
+        "PHONE NUMBER", "AUTHORIZED", "DC"
+    );
+
+    for row in rows {
+        let last_sync = row
+            .updated_at
+            .map(|updated_at| format_age(to_unix_millis(SystemTime::now()) - updated_at))
+            .unwrap_or_else(|| "-".to_string());
+
+        println!(
+            "{:<16} {:<14} {:<4} {}",
+            row.phone_number,
+            row.authorized,
+            row.dc.map_or("-".to_string(), |dc| dc.to_string()),
+            last_sync
+        );
+    }
+
+    Ok(())
+}
+
+fn format_age(millis: i64) -> String {
+    let secs = millis / 1000;
+    match secs {
+        ..60 => format!("{secs}s ago"),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, anyhow, bail};
+use chrono_tz::Tz;
+use grammers_client::grammers_tl_types::{
+    Deserializable,
+    enums::{StarGift, payments::StarGifts},
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    cli::{ExitError, parse_supply_rules},
+    config,
+    core::{
+        Allocation, BuyLimits, BuyOrder, GiftFilter, GiftType, per_user_cap, select_gifts_to_buy,
+    },
+    db::{get_bought_gifts, get_gift_snapshots, to_unix_millis},
+    history::GiftHistory,
+    stats::{STATS, format_time},
+};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+    #[serde(default = "default_timezone")]
+    timezone: Tz,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+// the rules of `start` being tuned
+#[derive(Deserialize)]
+struct Rules {
+    supply_rules: Option<String>,
+    max_supply: Option<i32>,
+    #[serde(default)]
+    buy_filter: GiftFilter,
+    #[serde(default)]
+    buy_order: BuyOrder,
+    #[serde(default)]
+    allocation: Allocation,
+    max_price: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Strategy {
+    pub buy_limit: Option<u64>,
+    pub accounts: u64,
+    // stars of every account, unlimited when unset
+    pub balance: Option<i64>,
+}
+
+// replays recorded catalog snapshots, treating gifts missing from the previous snapshot
+// as a drop, the first snapshot is only the baseline
+pub async fn process(
+    config_path: &Path,
+    rules_path: &Path,
+    gift_types: &[GiftType],
+    strategy: Strategy,
+    hours: u64,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    STATS.set_timezone(config.timezone);
+    let rules: Rules = config::load(rules_path)?;
+    let Some(supply_rules) = parse_supply_rules(rules.supply_rules.as_deref(), rules.max_supply)?
+    else {
+        bail!(ExitError::Config(
+            "supply_rules is required, e.g. supply_rules = \"10000:max\"".to_string()
+        ));
+    };
+
+    let pool = SqlitePool::connect(&config.database_url).await?;
+
+    let since = to_unix_millis(SystemTime::now() - Duration::from_secs(hours * 60 * 60));
+    let snapshots = get_gift_snapshots(&pool, since).await?;
+
+    let mut balances = vec![strategy.balance.unwrap_or(i64::MAX); strategy.accounts as usize];
+    let mut seen_gift_ids = HashSet::new();
+    let mut history = GiftHistory::default();
+    let mut is_baseline = true;
+    // gift_id -> (units, stars)
+    let mut simulated = BTreeMap::<i64, (i64, i64)>::new();
+
+    for snapshot in &snapshots {
+        let StarGifts::Gifts(star_gifts) = StarGifts::from_bytes(&snapshot.data)
+            .map_err(|err| anyhow!("invalid snapshot taken at {}: {err}", snapshot.taken_at))?
+        else {
+            continue;
+        };
+        history.observe(&star_gifts.gifts, snapshot.taken_at);
+
+        let gifts: Vec<_> = star_gifts
+            .gifts
+            .into_iter()
+            .filter_map(|gift| match gift {
+                StarGift::Gift(gift) => Some(gift),
+                StarGift::Unique(_) => None,
+            })
+            .collect();
+
+        let new_gifts: Vec<_> = gifts
+            .into_iter()
+            .filter(|gift| seen_gift_ids.insert(gift.id))
+            .filter(|gift| {
+                gift_types.iter().any(|gift_type| gift_type.matches(gift)) && !gift.sold_out
+            })
+            .filter(|gift| rules.buy_filter.matches(gift))
+            .collect();
+
+        if is_baseline {
+            is_baseline = false;
+            continue;
+        }
+
+        // the same selection `start` makes, with the limits it adds to the rules
+        let (mut gifts, limits) = select_gifts_to_buy(new_gifts, &supply_rules, strategy.buy_limit);
+        let limits = BuyLimits {
+            max_price: rules.max_price,
+            allocation: rules.allocation,
+            ..limits
+        };
+        let sell_out_etas: HashMap<_, _> = gifts
+            .iter()
+            .filter_map(|gift| Some((gift.id, history.sell_out_eta(gift.id)?)))
+            .collect();
+        rules.buy_order.sort(&mut gifts, &sell_out_etas);
+        for gift in gifts {
+            if limits.exceeds_max_price(&gift) {
+                continue;
+            }
+
+            let proportional_balances: Option<Vec<_>> = match limits.allocation {
+                Allocation::BalanceProportional => {
+                    Some(balances.iter().copied().map(Some).collect())
+                }
+                Allocation::PerAccount | Allocation::Once => None,
+            };
+            let mut remains = gift.availability_remains.map_or(i64::MAX, i64::from);
+            if let Some(total) = limits.total(gift.id) {
+                remains = remains.min(i64::try_from(total).unwrap_or(i64::MAX));
+            }
+            let mut units = 0;
+
+            for (i, balance) in balances.iter_mut().enumerate() {
+                let target = limits
+                    .account_target(gift.id, gift.stars, i, proportional_balances.as_deref())
+                    .min(per_user_cap(&gift));
+                let limit = i64::try_from(target).unwrap_or(i64::MAX);
+                let affordable = if gift.stars > 0 {
+                    *balance / gift.stars
+                } else {
+                    limit
+                };
+                let bought = limit.min(affordable).min(remains);
+
+                *balance -= bought * gift.stars;
+                remains -= bought;
+                units += bought;
+            }
+
+            if units > 0 {
+                println!(
+                    "{} gift {}: {units} unit(s) for {} ⭐",
+                    format_time(snapshot.taken_at),
+                    gift.id,
+                    units * gift.stars
+                );
+                simulated.insert(gift.id, (units, units * gift.stars));
+            }
+        }
+    }
+
+    let actual: BTreeMap<_, _> = get_bought_gifts(&pool, since)
+        .await?
+        .into_iter()
+        .map(|(gift_id, units, stars)| (gift_id, (units, stars)))
+        .collect();
+
+    println!(
+        "\n{} snapshot(s) in the last {hours}h\n{:<20} {:>16} {:>16}",
+        snapshots.len(),
+        "GIFT",
+        "SIMULATED",
+        "ACTUAL"
+    );
+
+    let gift_ids: BTreeSet<_> = simulated.keys().chain(actual.keys()).collect();
+    let format = |entry: Option<&(i64, i64)>| {
+        entry.map_or("-".to_string(), |(units, stars)| {
+            format!("{units} / {stars} ⭐")
+        })
+    };
+    for gift_id in gift_ids {
+        println!(
+            "{gift_id:<20} {:>16} {:>16}",
+            format(simulated.get(gift_id)),
+            format(actual.get(gift_id))
+        );
+    }
+
+    let total = |entries: &BTreeMap<i64, (i64, i64)>| -> (i64, i64) {
+        entries.values().fold((0, 0), |(units, stars), entry| {
+            (units + entry.0, stars + entry.1)
+        })
+    };
+    println!(
+        "{:<20} {:>16} {:>16}",
+        "TOTAL",
+        format(Some(&total(&simulated))),
+        format(Some(&total(&actual)))
+    );
+
+    Ok(())
+}
@@ -1,173 +1,938 @@
-use std::{collections::BTreeSet, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::Result;
-use futures::TryFutureExt;
+use anyhow::{Result, bail};
+use chrono_tz::Tz;
+use futures::{
+    FutureExt, TryFutureExt,
+    future::{BoxFuture, Shared},
+};
 use grammers_client::grammers_tl_types::{
+    self, Serializable,
     enums::{StarGift, payments::StarGifts},
-    functions::payments::GetStarGifts,
 };
 use serde::Deserialize;
 use sqlx::SqlitePool;
 use teloxide::Bot;
+use tokio::{sync::Notify, time::Instant};
 
 use crate::{
-    bot::{notify_gifts, run_bot},
-    core::{BuyGiftsDestination, buy_gifts},
-    wrapped_client::WrappedClient,
+    bot::{
+        BotLoginCodes, BotState, alert_chats, notify_gifts, run_bot, spawn_mark_sold_out,
+        spawn_notify_sell_out_eta, watch_clients,
+    },
+    catalog_changes::{CatalogChanges, CollectionLaunches, spawn_notify_catalog_changes},
+    cli::{ExitError, load_daily_limits, parse_supply_rules},
+    config::{self, Account},
+    core::{
+        Allocation, BuyDestinations, BuyLimits, BuyOrder, DEFAULT_BUY_LIMIT, GiftFilter,
+        GiftOptions, GiftType, PurchaseCoordinator, SupplyRules, buy_gifts, select_gifts_to_buy,
+        wait_purchase_tasks,
+    },
+    db::{
+        get_gifts_first_seen_ids, has_gifts_first_seen, insert_gift_snapshot,
+        insert_gifts_first_seen, record_gift_seen, to_unix_millis,
+    },
+    health::run_health_checks,
+    history::{GiftHistory, spawn_record_observations},
+    jobs::spawn_enqueue,
+    lease::{Leadership, spawn_lease},
+    mini_app::{AutoBuy, Balances, Catalog, MiniApp, run_mini_app},
+    polling::{
+        AdaptivePolling, ClientRotation, DailyWindow, PollFailures, PollProgress,
+        race_get_star_gifts, run_stall_watchdog, spawn_poll_alert, wait_next_poll,
+    },
+    preflight::{Budget, run_preflight_checks},
+    resale::run_resale_tracking,
+    script::BuyScript,
+    stats::{DEFAULT_UNDELIVERABLE_AFTER, DELIVERY, STATS},
+    systemd::{self, Watchdog},
+    topup::{AutoTopup, run_balance_checks},
+    transactions::run_transactions_sync,
+    uniques::{UpgradeSettings, run_upgrades},
+    updates::UpdateWatchers,
+    wrapped_client::{self, Clients, LoginCodeSource, WrappedClient},
 };
 
 #[derive(Deserialize)]
 struct Config {
-    api_id: i32,
-    api_hash: String,
-    phone_numbers: Vec<String>,
+    #[serde(deserialize_with = "config::comma_separated")]
     admin_usernames: Vec<String>,
     initial_gifts_hash: i32,
     bot_token: String,
     database_url: String,
-    max_supply: i32,
+    #[serde(default)]
+    login_code_source: LoginCodeSource,
+    // gifts matching no rule are notified about but never bought, required unless
+    // buy_script is set
+    supply_rules: Option<String>,
+    // deprecated, same as supply_rules = "MAX_SUPPLY:max"
+    max_supply: Option<i32>,
+    // which detected gifts are notified about and which are considered for purchases,
+    // independently of each other
+    #[serde(default)]
+    notify_filter: GiftFilter,
+    #[serde(default)]
+    buy_filter: GiftFilter,
+    #[serde(default)]
+    buy_order: BuyOrder,
+    #[serde(default)]
+    allocation: Allocation,
+    // hard ceiling on the per-unit price of automated purchases, applied after the rules
+    // and the buy script
+    max_price: Option<i64>,
+    // purchases of a drop stop this long after it was detected
+    max_buy_duration_secs: Option<u64>,
+    // of burst_windows, daily_reset_hour and the daily stats
+    #[serde(default = "default_timezone")]
+    timezone: Tz,
+    #[serde(default = "default_poll_interval_ms")]
+    poll_interval_ms: u64,
+    #[serde(default = "default_burst_poll_interval_ms")]
+    burst_poll_interval_ms: u64,
+    #[serde(default = "default_burst_duration_secs")]
+    burst_duration_secs: u64,
+    #[serde(default, deserialize_with = "config::comma_separated")]
+    burst_windows: Vec<String>,
+    #[serde(default = "default_poll_jitter_ms")]
+    poll_jitter_ms: u64,
+    // poll intervals without a catalog response before the loop counts as stalled
+    #[serde(default = "default_stall_poll_intervals")]
+    stall_poll_intervals: u32,
+    // drops whatever the loop is stuck on and starts it over on a stall
+    #[serde(default)]
+    restart_on_stall: bool,
+    // failed polls in a row before polling fails over and the chats are alerted, 0 disables
+    #[serde(default = "default_poll_failure_threshold")]
+    poll_failure_threshold: u32,
+    // failed sends in a row before a trusted chat counts as undeliverable
+    #[serde(default = "default_notification_failure_threshold")]
+    notification_failure_threshold: u32,
+    #[serde(default, deserialize_with = "config::comma_separated")]
+    update_trigger_usernames: Vec<String>,
+    #[serde(default = "default_race_clients")]
+    race_clients: usize,
+    #[serde(default = "default_health_check_interval_secs")]
+    health_check_interval_secs: u64,
+    #[serde(default = "default_transactions_sync_interval_secs")]
+    transactions_sync_interval_secs: u64,
+    // admins are alerted with top-up options once a balance drops below it
+    low_balance_threshold: Option<i64>,
+    #[serde(default = "default_balance_check_interval_secs")]
+    balance_check_interval_secs: u64,
+    // opt-in, tops up `auto_topup_stars` (one of the top-up options) with the saved
+    // payment method once a balance drops below it, requires the account's 2FA password
+    auto_topup_threshold: Option<i64>,
+    auto_topup_stars: Option<i64>,
+    // per-unit price the preflight check expects of the next drop, disabled when unset
+    expected_gift_price: Option<i64>,
+    #[serde(default = "default_expected_gifts")]
+    expected_gifts: u64,
+    #[serde(default = "default_preflight_lead_secs")]
+    preflight_lead_secs: u64,
+    #[serde(default = "default_warm_up_media_dcs")]
+    warm_up_media_dcs: bool,
+    #[serde(default = "default_sticker_thumb_size")]
+    sticker_thumb_size: String,
+    // auto-buy only gifts first seen within this window, first-seen times are kept in
+    // the database, so restarts don't make old gifts look new
+    freshness_window_secs: Option<u64>,
+    // stores every changed catalog into `gift_snapshots`, for `simulate` and analytics
+    #[serde(default)]
+    record_snapshots: bool,
+    // opt-in, alerts when gifts leave the catalog, sell out or get resale listings
+    #[serde(default)]
+    notify_catalog_changes: bool,
+    // opt-in, alerts when a gift becomes upgradable or uniques of a new collection are listed
+    #[serde(default)]
+    notify_new_collections: bool,
+    // opt-in, upgrades bought gifts whose upgrade was paid along with the gift
+    #[serde(default)]
+    auto_upgrade: bool,
+    #[serde(default = "default_upgrade_check_interval_secs")]
+    upgrade_check_interval_secs: u64,
+    // admins are alerted about upgrades rolling a combination within this top percent
+    #[serde(default = "default_rare_upgrade_percentile")]
+    rare_upgrade_percentile: f64,
+    // upgrades rolling a combination within this top percent are pinned to the profile
+    pin_upgrade_percentile: Option<f64>,
+    // opt-in, samples the resale floors of gifts the accounts hold uniques of
+    #[serde(default)]
+    track_resale: bool,
+    #[serde(default = "default_resale_check_interval_secs")]
+    resale_check_interval_secs: u64,
+    #[serde(default = "default_resale_floor_change_percent")]
+    resale_floor_change_percent: f64,
+    // bought gifts are hidden from the profile of the account they're bought to
+    #[serde(default)]
+    hide_bought_gifts: bool,
+    // rhai script deciding which detected gifts to buy instead of supply_rules,
+    // see `script::BuyScript`
+    buy_script: Option<PathBuf>,
+    // instances sharing the database with distinct ids elect one to execute purchases,
+    // a single instance buys on its own when unset
+    instance_id: Option<String>,
+    #[serde(default = "default_lease_ttl_secs")]
+    lease_ttl_secs: u64,
+    // public https url of the mini app dashboard, opened from the bot's menu button,
+    // proxied to mini_app_listen, disabled when unset
+    mini_app_url: Option<String>,
+    mini_app_listen: Option<SocketAddr>,
+    // recipients of bought gifts, units are split between several comma-separated ones
+    // by their weights, as `@channel:60,self:40`
+    #[serde(default = "default_buy_destinations")]
+    buy_destinations: String,
     // dest_channel_username: String,
 }
 
-// 1. authorize all clients
-// 2. poll gift updates every 2-3 seconds
+const ACCOUNT_RETRY_BACKOFF_MIN: Duration = Duration::from_secs(60);
+const ACCOUNT_RETRY_BACKOFF_MAX: Duration = Duration::from_secs(30 * 60);
+
+// gift stickers are served from any of the production DCs
+const MEDIA_DC_IDS: [i32; 5] = [1, 2, 3, 4, 5];
+
+fn default_poll_interval_ms() -> u64 {
+    2000
+}
+
+fn default_burst_poll_interval_ms() -> u64 {
+    500
+}
+
+fn default_burst_duration_secs() -> u64 {
+    300
+}
+
+fn default_poll_jitter_ms() -> u64 {
+    250
+}
+
+fn default_stall_poll_intervals() -> u32 {
+    30
+}
+
+fn default_poll_failure_threshold() -> u32 {
+    5
+}
+
+fn default_notification_failure_threshold() -> u32 {
+    DEFAULT_UNDELIVERABLE_AFTER
+}
+
+fn default_race_clients() -> usize {
+    2
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    60
+}
+
+fn default_transactions_sync_interval_secs() -> u64 {
+    300
+}
+
+fn default_balance_check_interval_secs() -> u64 {
+    300
+}
+
+fn default_expected_gifts() -> u64 {
+    1
+}
+
+fn default_preflight_lead_secs() -> u64 {
+    30 * 60
+}
+
+fn default_upgrade_check_interval_secs() -> u64 {
+    300
+}
+
+fn default_rare_upgrade_percentile() -> f64 {
+    1.0
+}
+
+fn default_resale_check_interval_secs() -> u64 {
+    600
+}
+
+fn default_resale_floor_change_percent() -> f64 {
+    10.0
+}
+
+fn default_lease_ttl_secs() -> u64 {
+    15
+}
+
+fn default_buy_destinations() -> String {
+    "self".to_string()
+}
+
+fn default_warm_up_media_dcs() -> bool {
+    true
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn default_sticker_thumb_size() -> String {
+    "m".to_string()
+}
+
+// 1. authorize all clients, retrying the failed ones in the background
+// 2. round-robin over clients and poll gift updates every `poll_interval_ms`,
+//    bursting after catalog changes
 // 3. when new gifts are available:
 //      1. send them to all connected admin chats in bot
-//      2. filter by supply <= max_supply
+//      2. filter and limit by the supply rules
 //      3. for each account:
 //          1. for each gift in sorted by supply:
 //              1. buy to channel
 
-pub async fn process(ignore_not_limited: bool, do_buy: bool, buy_limit: Option<u64>) -> Result<()> {
-    tracing::debug!(ignore_not_limited, do_buy, buy_limit);
+pub async fn process(
+    config_path: &Path,
+    gift_types: &[GiftType],
+    do_buy: bool,
+    buy_limit: Option<u64>,
+    watch_only: bool,
+    // the catalog is polled once, gifts detected by earlier runs don't count as new
+    once: bool,
+) -> Result<()> {
+    tracing::debug!(?gift_types, do_buy, buy_limit, watch_only, once);
+
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
 
-    let config: Config = envy::from_env()?;
+    let supply_rules = match parse_supply_rules(config.supply_rules.as_deref(), config.max_supply)?
+    {
+        Some(supply_rules) => supply_rules,
+        // decided by the script instead
+        None if config.buy_script.is_some() => SupplyRules::default(),
+        None => bail!(ExitError::Config(
+            "supply_rules is required, e.g. supply_rules = \"10000:max\"".to_string()
+        )),
+    };
+    let buy_script = config
+        .buy_script
+        .as_deref()
+        .map(BuyScript::load)
+        .transpose()?;
 
     let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
-    let bot = Arc::new(Bot::new(config.bot_token));
-
-    let mut clients = vec![];
-
-    for phone_number in config.phone_numbers {
-        clients.push(Arc::new(
-            WrappedClient::new(
-                pool.clone(),
-                phone_number,
-                config.api_id,
-                config.api_hash.clone(),
-            )
-            .await?,
+    let bot = Arc::new(Bot::new(config.bot_token.clone()));
+    DELIVERY.set_undeliverable_after(config.notification_failure_threshold);
+    STATS.set_timezone(config.timezone);
+
+    let admin_usernames: Arc<[String]> = config.admin_usernames.into();
+    let login_codes = Arc::new(BotLoginCodes::new(bot.clone(), pool.clone()));
+    // `run_bot` isn't running yet, so `/code` replies have to be received separately
+    let login_polling = matches!(config.login_code_source, LoginCodeSource::Bot)
+        .then(|| tokio::spawn(login_codes.clone().poll(admin_usernames.clone())));
+
+    let clients = Clients::default();
+    let mut failed_accounts = vec![];
+
+    for account in accounts.all()? {
+        match WrappedClient::new(
+            pool.clone(),
+            account.clone(),
+            &config.login_code_source,
+            Some(login_codes.as_ref()),
+        )
+        .await
+        {
+            Ok(client) => clients.add(Arc::new(client)),
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    phone_number = account.phone_number,
+                    "failed to initialize account"
+                );
+                failed_accounts.push((account, err.to_string()));
+            }
+        }
+    }
+
+    if let Some(login_polling) = login_polling {
+        login_polling.abort();
+    }
+
+    if clients.snapshot().is_empty() {
+        bail!(ExitError::Auth(
+            "none of the accounts could be initialized".to_string()
         ));
     }
 
-    let client = clients
-        .first()
-        .cloned()
-        .expect("expected at least one client");
+    if !failed_accounts.is_empty() {
+        let mut text = "⚠️ Some accounts failed to initialize and will be retried:\n".to_string();
+        for (account, err) in &failed_accounts {
+            writeln!(text, "{}: {err}", account.phone_number).unwrap();
+        }
+        if let Err(err) = alert_chats(&bot, &pool, &text).await {
+            tracing::error!(?err, "failed to report failed accounts");
+        }
+
+        tokio::spawn(retry_failed_accounts(
+            bot.clone(),
+            pool.clone(),
+            clients.clone(),
+            failed_accounts
+                .into_iter()
+                .map(|(account, _)| account)
+                .collect(),
+        ));
+    }
+
+    systemd::notify_ready();
 
     // let destination = Arc::new(
     //     MaybeResolvedChannel::Username(config.dest_channel_username)
     //         .as_resolved(&client)
     //         .await?,
     // );
-    let buy_dest = Arc::new(BuyGiftsDestination::PeerSelf);
+    let buy_dest = Arc::new(config.buy_destinations.parse::<BuyDestinations>()?);
+    let gift_options = Arc::new(GiftOptions {
+        unsave: config.hide_bought_gifts,
+        ..Default::default()
+    });
+    let coordinator = Arc::new(
+        PurchaseCoordinator::with_daily_limits(load_daily_limits(config_path)?)
+            .with_cancel_buttons(),
+    );
+    let auto_buy = AutoBuy::new(do_buy);
+    let catalog = Catalog::default();
+
+    match (config.mini_app_url, config.mini_app_listen) {
+        (Some(url), Some(listen)) => {
+            let mini_app = Arc::new(MiniApp {
+                bot_token: config.bot_token,
+                admin_usernames: admin_usernames.clone(),
+                pool: pool.clone(),
+                clients: clients.clone(),
+                catalog: catalog.clone(),
+                auto_buy: auto_buy.clone(),
+                balances: Balances::default(),
+            });
+            let bot = bot.clone();
+            tokio::spawn(async move {
+                run_mini_app(mini_app, bot, &url, listen)
+                    .await
+                    .inspect_err(|err| tracing::error!(?err, "run_mini_app exited with error"))
+            });
+        }
+        (None, None) => {}
+        _ => bail!(ExitError::Config(
+            "mini_app_url and mini_app_listen must be set together".to_string()
+        )),
+    }
+
+    let poll_trigger = Arc::new(Notify::new());
+
+    // accounts added at runtime are watched by the bot once they're logged in
+    let update_watchers = (!config.update_trigger_usernames.is_empty())
+        .then(|| UpdateWatchers::new(config.update_trigger_usernames.into(), poll_trigger.clone()));
+    if let Some(update_watchers) = &update_watchers {
+        for client in clients.snapshot() {
+            update_watchers.spawn(client);
+        }
+    }
+
+    let bot_state = Arc::new(BotState {
+        bot: bot.clone(),
+        pool: pool.clone(),
+        clients: clients.clone(),
+        admin_usernames,
+        buy_limit,
+        buy_dest: buy_dest.clone(),
+        gift_options: gift_options.clone(),
+        coordinator: coordinator.clone(),
+        login_codes,
+        accounts,
+        adding_accounts: Default::default(),
+        update_watchers,
+        catalog: catalog.clone(),
+    });
 
     let _bot_handle = tokio::spawn(
-        run_bot(
+        run_bot(bot_state).inspect_err(|err| tracing::error!(?err, "run_bot exited with error")),
+    );
+
+    tokio::spawn(watch_clients(bot.clone(), pool.clone(), clients.clone()));
+
+    if config.warm_up_media_dcs {
+        for client in clients.snapshot() {
+            for dc_id in MEDIA_DC_IDS {
+                let client = client.clone();
+                tokio::spawn(async move { client.warm_up_dc(dc_id).await });
+            }
+        }
+    }
+    let sticker_thumb_size: Arc<str> = config.sticker_thumb_size.as_str().into();
+
+    tokio::spawn(run_health_checks(
+        bot.clone(),
+        pool.clone(),
+        clients.clone(),
+        Duration::from_secs(config.health_check_interval_secs),
+    ));
+
+    tokio::spawn(run_transactions_sync(
+        bot.clone(),
+        pool.clone(),
+        clients.clone(),
+        Duration::from_secs(config.transactions_sync_interval_secs),
+    ));
+
+    if let Some(gift_price) = config.expected_gift_price {
+        let budget = Budget {
+            gift_price,
+            gifts: config.expected_gifts,
+            limit: buy_limit.unwrap_or(DEFAULT_BUY_LIMIT),
+        };
+        let windows = config
+            .burst_windows
+            .iter()
+            .map(|window| DailyWindow::parse(window))
+            .collect::<Result<_, _>>()?;
+
+        tokio::spawn(run_preflight_checks(
             bot.clone(),
             pool.clone(),
             clients.clone(),
-            config.admin_usernames.into(),
-            buy_limit,
-            buy_dest.clone(),
-        )
-        .inspect_err(|err| tracing::error!(?err, "run_bot exited with error")),
-    );
+            budget,
+            windows,
+            config.timezone,
+            Duration::from_secs(config.preflight_lead_secs),
+        ));
+    }
+
+    let auto_topup = match (config.auto_topup_threshold, config.auto_topup_stars) {
+        (Some(threshold), Some(stars)) => Some(AutoTopup { threshold, stars }),
+        (None, None) => None,
+        _ => bail!(ExitError::Config(
+            "auto_topup_threshold and auto_topup_stars must be set together".to_string()
+        )),
+    };
+
+    if config.low_balance_threshold.is_some() || auto_topup.is_some() {
+        tokio::spawn(run_balance_checks(
+            bot.clone(),
+            pool.clone(),
+            clients.clone(),
+            config.low_balance_threshold,
+            auto_topup,
+            Duration::from_secs(config.balance_check_interval_secs),
+        ));
+    }
+
+    if config.auto_upgrade {
+        tokio::spawn(run_upgrades(
+            bot.clone(),
+            pool.clone(),
+            clients.clone(),
+            UpgradeSettings {
+                alert_percentile: config.rare_upgrade_percentile,
+                pin_percentile: config.pin_upgrade_percentile,
+            },
+            Duration::from_secs(config.upgrade_check_interval_secs),
+        ));
+    }
+
+    if config.track_resale {
+        tokio::spawn(run_resale_tracking(
+            bot.clone(),
+            pool.clone(),
+            clients.clone(),
+            config.resale_floor_change_percent,
+            Duration::from_secs(config.resale_check_interval_secs),
+        ));
+    }
+
+    let leadership = match config.instance_id {
+        Some(instance_id) => spawn_lease(
+            bot.clone(),
+            pool.clone(),
+            instance_id,
+            Duration::from_secs(config.lease_ttl_secs),
+        ),
+        None => Leadership::always(),
+    };
 
     let mut gifts_hash = config.initial_gifts_hash;
-    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    let mut polling = AdaptivePolling::new(
+        Duration::from_millis(config.poll_interval_ms),
+        Duration::from_millis(config.burst_poll_interval_ms),
+        Duration::from_secs(config.burst_duration_secs),
+        &config.burst_windows,
+        Duration::from_millis(config.poll_jitter_ms),
+        config.timezone,
+    )?;
+
+    let mut rotation = ClientRotation::new(clients.clone());
+
+    let mut poll_failures = PollFailures::new(config.poll_failure_threshold);
+    let poll_progress = PollProgress::default();
+    let poll_restart = Arc::new(Notify::new());
+    tokio::spawn(run_stall_watchdog(
+        bot.clone(),
+        pool.clone(),
+        poll_progress.clone(),
+        Duration::from_millis(config.poll_interval_ms) * config.stall_poll_intervals,
+        config.restart_on_stall.then(|| poll_restart.clone()),
+    ));
 
     let mut seen_gift_ids = BTreeSet::new();
+    // every `--once` run starts over, so the gifts detected by the runs before are read
+    // from the first-seen times instead of being notified about and bought again
+    if once {
+        seen_gift_ids.extend(get_gifts_first_seen_ids(&*pool).await?);
+    }
+    // without first-seen times (first run, fresh database) every gift of the catalog would
+    // count as fresh (or new with `--once`), so the first catalog is recorded as seen long
+    // ago instead of bought
+    let mut seed_first_seen =
+        (once || config.freshness_window_secs.is_some()) && !has_gifts_first_seen(&*pool).await?;
+    let mut history = GiftHistory::default();
+    // gifts notified before their sell rate was known, followed up with the sell-out ETA
+    // once the following polls tell it, with the notifications they reply to
+    let mut eta_followups: HashMap<i64, Shared<BoxFuture<'static, ()>>> = HashMap::new();
+    let mut catalog_changes = config.notify_catalog_changes.then(CatalogChanges::default);
+    let mut collection_launches = config
+        .notify_new_collections
+        .then(CollectionLaunches::default);
+    let mut watchdog = Watchdog::from_env();
 
+    // dropping the loop's future on a restart cancels whatever it's stuck on, the state
+    // kept across polls lives outside of it
     loop {
-        let star_gifts = client.invoke(&GetStarGifts { hash: gifts_hash }).await?;
-        tracing::debug!(?star_gifts);
+        let poll_loop = async {
+            loop {
+                watchdog.ping();
 
-        if let StarGifts::Gifts(gifts) = star_gifts {
-            gifts_hash = gifts.hash;
+                let poll_started_at = Instant::now();
+                let mut buy_result = None;
 
-            // gifts can't be unique here
-            let gifts: Vec<_> = gifts
-                .gifts
-                .into_iter()
-                .filter_map(|gift| match gift {
-                    StarGift::Gift(gift) => Some(gift),
-                    StarGift::Unique(_) => None,
-                })
-                .filter(|gift| {
-                    (ignore_not_limited || gift.limited)
-                        && !gift.sold_out
-                        && !seen_gift_ids.contains(&gift.id)
-                })
-                .collect();
-
-            tracing::debug!(?gifts);
-
-            tokio::spawn(
-                notify_gifts(bot.clone(), pool.clone(), client.clone(), gifts.clone()).inspect_err(
-                    |err| tracing::error!(?err, "send_notifications finished with error"),
-                ),
-            );
-
-            let mut gifts: Vec<_> = gifts
-                .into_iter()
-                .filter(|gift| {
-                    gift.availability_total.is_some()
-                        && gift.availability_total.unwrap() <= config.max_supply
-                })
-                .collect();
+                // 0 would leave nobody to poll with
+                let race_clients = if polling.is_bursting() {
+                    config.race_clients.max(1)
+                } else {
+                    1
+                };
 
-            gifts.sort_by_key(|gift| gift.availability_total);
+                let pollers = rotation.next_clients(race_clients);
+                if pollers.is_empty() {
+                    if once {
+                        bail!("no clients available to poll the catalog with");
+                    }
+                    tracing::warn!("no clients available (flood wait or removed), skipping poll");
+                    wait_next_poll(poll_started_at + polling.interval(), &poll_trigger).await;
+                    continue;
+                }
 
-            tracing::debug!(filtered_and_sorted_gifts = ?gifts);
+                let (client, star_gifts) = match race_get_star_gifts(&pollers, gifts_hash).await {
+                    Ok(response) => response,
+                    Err(errors) if once => {
+                        let errors: Vec<_> = errors
+                            .iter()
+                            .map(|(client, class)| {
+                                format!("{}: {} error", client.phone_number(), class.as_str())
+                            })
+                            .collect();
+                        bail!("failed to poll the catalog ({})", errors.join(", "));
+                    }
+                    Err(errors) => {
+                        if let Some(text) = poll_failures.record_failure(&errors) {
+                            rotation.fail_over(&pollers);
+                            spawn_poll_alert(bot.clone(), pool.clone(), text);
+                        }
+                        wait_next_poll(poll_started_at + polling.interval(), &poll_trigger).await;
+                        continue;
+                    }
+                };
+                poll_progress.record_success();
+                if let Some(text) = poll_failures.record_success() {
+                    spawn_poll_alert(bot.clone(), pool.clone(), text);
+                }
+                tracing::debug!(?star_gifts, phone_number = client.phone_number());
 
-            for gift in &gifts {
-                seen_gift_ids.insert(gift.id);
-            }
+                if config.record_snapshots
+                    && let StarGifts::Gifts(gifts) = &star_gifts
+                {
+                    let pool = pool.clone();
+                    let (hash, data) = (gifts.hash, star_gifts.to_bytes());
+                    tokio::spawn(async move {
+                        insert_gift_snapshot(&*pool, to_unix_millis(SystemTime::now()), hash, &data)
+                            .await
+                            .inspect_err(|err| {
+                                tracing::error!(?err, "failed to record gift snapshot")
+                            })
+                    });
+                }
+
+                if let StarGifts::Gifts(gifts) = star_gifts {
+                    if seed_first_seen {
+                        let gift_ids: Vec<_> = gifts
+                            .gifts
+                            .iter()
+                            .filter_map(|gift| match gift {
+                                StarGift::Gift(gift) => Some(gift.id),
+                                StarGift::Unique(_) => None,
+                            })
+                            .collect();
+                        // 0 as in seen before the first-seen times were recorded
+                        if let Err(err) = insert_gifts_first_seen(&*pool, &gift_ids, 0).await {
+                            tracing::error!(?err, "failed to record the catalog as seen");
+                            wait_next_poll(poll_started_at + polling.interval(), &poll_trigger)
+                                .await;
+                            continue;
+                        }
+                        tracing::info!(
+                            gifts = gift_ids.len(),
+                            "recorded the catalog as seen, its gifts aren't auto-bought"
+                        );
+                        seed_first_seen = false;
+                        if once {
+                            seen_gift_ids.extend(gift_ids);
+                        }
+                    }
+
+                    let detected_at = SystemTime::now();
+                    gifts_hash = gifts.hash;
+                    catalog.update(&gifts.gifts);
+
+                    let sold_out = history.sold_out_since_last(&gifts.gifts);
+                    if !sold_out.is_empty() {
+                        for gift_id in &sold_out {
+                            eta_followups.remove(gift_id);
+                        }
+                        spawn_mark_sold_out(bot.clone(), pool.clone(), sold_out);
+                    }
+                    let observations = history.observe(&gifts.gifts, to_unix_millis(detected_at));
+                    spawn_record_observations(pool.clone(), observations);
+                    eta_followups.retain(|&gift_id, notified| {
+                        let Some(eta) = history.sell_out_eta(gift_id) else {
+                            return true;
+                        };
+                        spawn_notify_sell_out_eta(
+                            bot.clone(),
+                            pool.clone(),
+                            gift_id,
+                            eta,
+                            notified.clone(),
+                        );
+                        false
+                    });
+                    if let Some(catalog_changes) = &mut catalog_changes
+                        && let Some(report) = catalog_changes.update(&gifts.gifts)
+                    {
+                        spawn_notify_catalog_changes(bot.clone(), pool.clone(), report);
+                    }
+                    if let Some(collection_launches) = &mut collection_launches
+                        && let Some(report) = collection_launches.update(&gifts.gifts)
+                    {
+                        spawn_notify_catalog_changes(bot.clone(), pool.clone(), report);
+                    }
+                    polling.on_catalog_changed();
+
+                    // uniques are only reported as collection launches, never bought
+                    let gifts: Vec<_> = gifts
+                        .gifts
+                        .into_iter()
+                        .filter_map(|gift| match gift {
+                            StarGift::Gift(gift) => Some(gift),
+                            StarGift::Unique(_) => None,
+                        })
+                        .filter(|gift| {
+                            gift_types.iter().any(|gift_type| gift_type.matches(gift))
+                                && !seen_gift_ids.contains(&gift.id)
+                        })
+                        .collect();
+
+                    tracing::debug!(?gifts);
+                    if !gifts.is_empty() {
+                        STATS.record_detected(gifts.len());
+                    }
+                    if once && !gifts.is_empty() {
+                        let gift_ids: Vec<_> = gifts.iter().map(|gift| gift.id).collect();
+                        if let Err(err) =
+                            insert_gifts_first_seen(&*pool, &gift_ids, to_unix_millis(detected_at))
+                                .await
+                        {
+                            tracing::error!(?err, "failed to record the detected gifts as seen");
+                        }
+                    }
 
-            let gift_ids: Vec<_> = gifts.iter().map(|gift| gift.id).collect();
-            let gift_prices_map = gifts.iter().map(|gift| (gift.id, gift.stars)).collect();
-
-            tracing::debug!(?gift_ids);
-
-            if !gift_ids.is_empty() && do_buy {
-                for i in 0..10 {
-                    let buy_gifts_result = buy_gifts(
-                        &clients,
-                        bot.clone(),
-                        pool.clone(),
-                        gift_ids.clone(),
-                        Some(&gift_prices_map),
-                        buy_limit,
-                        &buy_dest,
+                    let sell_out_etas: HashMap<_, _> = gifts
+                        .iter()
+                        .filter_map(|gift| Some((gift.id, history.sell_out_eta(gift.id)?)))
+                        .collect();
+
+                    let notified_gifts: Vec<_> = gifts
+                        .iter()
+                        .filter(|gift| config.notify_filter.matches(gift))
+                        .cloned()
+                        .collect();
+                    // a new drop has been observed once, its sell rate needs another poll
+                    let without_eta: Vec<_> = notified_gifts
+                        .iter()
+                        .filter(|gift| {
+                            gift.availability_remains.is_some()
+                                && !gift.sold_out
+                                && !sell_out_etas.contains_key(&gift.id)
+                        })
+                        .map(|gift| gift.id)
+                        .collect();
+                    let notify_handle = tokio::spawn(
+                        notify_gifts(
+                            bot.clone(),
+                            pool.clone(),
+                            client.clone(),
+                            notified_gifts,
+                            sell_out_etas.clone(),
+                            buy_limit.unwrap_or(DEFAULT_BUY_LIMIT),
+                            clients
+                                .snapshot()
+                                .iter()
+                                .filter(|client| !client.is_deauthorized())
+                                .count() as u64,
+                            buy_dest.clone(),
+                            sticker_thumb_size.clone(),
+                        )
+                        .inspect_err(|err| {
+                            tracing::error!(?err, "send_notifications finished with error")
+                        }),
                     )
-                    .await;
+                    .map(drop)
+                    .boxed()
+                    .shared();
+                    for gift_id in without_eta {
+                        eta_followups.insert(gift_id, notify_handle.clone());
+                    }
+
+                    // resale gifts can't be bought from the catalog, so they're notified about once
+                    for gift in gifts.iter().filter(|gift| gift.sold_out) {
+                        seen_gift_ids.insert(gift.id);
+                    }
+                    let gifts: Vec<_> = gifts
+                        .into_iter()
+                        .filter(|gift| !gift.sold_out && config.buy_filter.matches(gift))
+                        .collect();
+                    let (mut gifts, buy_limits) = match &buy_script {
+                        Some(buy_script) => {
+                            buy_script
+                                .select_gifts_to_buy(&clients, gifts, buy_limit)
+                                .await
+                        }
+                        None => select_gifts_to_buy(gifts, &supply_rules, buy_limit),
+                    };
+                    let buy_limits = BuyLimits {
+                        max_price: config.max_price,
+                        allocation: config.allocation,
+                        max_buy_duration: config.max_buy_duration_secs.map(Duration::from_secs),
+                        ..buy_limits
+                    };
+                    config.buy_order.sort(&mut gifts, &sell_out_etas);
+
+                    if let Some(freshness_window_secs) = config.freshness_window_secs {
+                        gifts = filter_fresh_gifts(
+                            &pool,
+                            gifts,
+                            detected_at,
+                            Duration::from_secs(freshness_window_secs),
+                        )
+                        .await;
+                    }
+
+                    tracing::debug!(filtered_and_sorted_gifts = ?gifts);
+
+                    for gift in &gifts {
+                        seen_gift_ids.insert(gift.id);
+                    }
 
-                    match buy_gifts_result {
-                        Err(err) => {
-                            tracing::error!(?err, i, "failed to buy gifts");
+                    let gift_ids: Vec<_> = gifts.iter().map(|gift| gift.id).collect();
+                    let gifts_map = gifts.iter().map(|gift| (gift.id, gift.clone())).collect();
+
+                    tracing::debug!(?gift_ids);
+
+                    let do_buy = auto_buy.is_enabled();
+
+                    if !gift_ids.is_empty() && (do_buy || watch_only) && !leadership.is_leader() {
+                        tracing::info!(
+                            ?gift_ids,
+                            "not the leader, leaving purchases to another instance"
+                        );
+                    } else if !gift_ids.is_empty() && watch_only {
+                        spawn_enqueue(pool.clone(), &gifts, &buy_limits, detected_at);
+                    } else if !gift_ids.is_empty() && do_buy {
+                        poll_progress.start_buying();
+                        for i in 0..10 {
+                            let buy_gifts_result = buy_gifts(
+                                &clients.snapshot(),
+                                bot.clone(),
+                                pool.clone(),
+                                &coordinator,
+                                gift_ids.clone(),
+                                Some(&gifts_map),
+                                &buy_limits,
+                                &buy_dest,
+                                &gift_options,
+                                detected_at,
+                            )
+                            .await;
+
+                            let is_ok = buy_gifts_result.is_ok();
+                            if let Err(err) = &buy_gifts_result {
+                                tracing::error!(?err, i, "failed to buy gifts");
+                            }
+                            buy_result = Some(buy_gifts_result);
+                            if is_ok {
+                                break;
+                            }
                         }
-                        Ok(()) => break,
+                        poll_progress.finish_buying();
+                    }
+
+                    if once {
+                        // the notifications would be dropped on exit
+                        notify_handle.await;
                     }
                 }
+
+                if let Err(err) = client.sync_session().await {
+                    tracing::error!(?err, "failed to sync session");
+                }
+
+                if once {
+                    // the purchase records and buy notifications would be dropped on exit
+                    wait_purchase_tasks().await;
+                    match buy_result {
+                        Some(Err(err)) => return Err(err.into()),
+                        Some(Ok(outcome)) if outcome.failed > 0 => {
+                            bail!(ExitError::PartialBuy {
+                                bought: outcome.bought,
+                                failed: outcome.failed,
+                            });
+                        }
+                        _ => {}
+                    }
+                    tracing::info!("poll cycle done, exiting");
+                    return Ok(());
+                }
+
+                wait_next_poll(poll_started_at + polling.interval(), &poll_trigger).await;
             }
-        }
+        };
 
-        if let Err(err) = client.sync_session().await {
-            tracing::error!(?err, "failed to sync session");
+        tokio::select! {
+            result = poll_loop => return result,
+            () = poll_restart.notified() => {}
         }
-
-        interval.tick().await;
     }
 
     #[allow(unreachable_code)]
@@ -176,3 +941,91 @@ pub async fn process(ignore_not_limited: bool, do_buy: bool, buy_limit: Option<u
         Ok(())
     }
 }
+
+async fn filter_fresh_gifts(
+    pool: &SqlitePool,
+    gifts: Vec<grammers_tl_types::types::StarGift>,
+    detected_at: SystemTime,
+    window: Duration,
+) -> Vec<grammers_tl_types::types::StarGift> {
+    let detected_at = to_unix_millis(detected_at);
+    let mut fresh = vec![];
+
+    for gift in gifts {
+        match record_gift_seen(pool, gift.id, detected_at).await {
+            Ok(first_seen_at) if detected_at - first_seen_at <= window.as_millis() as i64 => {
+                fresh.push(gift)
+            }
+            Ok(first_seen_at) => {
+                tracing::info!(
+                    gift_id = gift.id,
+                    first_seen_at,
+                    "gift is too old to auto-buy"
+                )
+            }
+            // not bought, since it can't be told whether it's fresh
+            Err(err) => tracing::error!(?err, gift_id = gift.id, "failed to record gift"),
+        }
+    }
+
+    fresh
+}
+
+// reconnects with exponential backoff until every account is initialized, only stored
+// sessions are reconnected, a background task can't go through an interactive login, so
+// accounts that aren't logged in are alerted about and left to /addaccount
+async fn retry_failed_accounts(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    mut accounts: Vec<Account>,
+) {
+    let mut backoff = ACCOUNT_RETRY_BACKOFF_MIN;
+
+    while !accounts.is_empty() {
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(ACCOUNT_RETRY_BACKOFF_MAX);
+
+        let mut still_failed = vec![];
+
+        for account in accounts {
+            let phone_number = account.phone_number.clone();
+
+            let result = async {
+                let client = WrappedClient::connect(pool.clone(), account.clone()).await?;
+                let is_authorized = client.is_authorized().await?;
+                Ok::<_, wrapped_client::Error>(is_authorized.then_some(client))
+            }
+            .await;
+
+            match result {
+                Ok(Some(client)) => {
+                    clients.add(Arc::new(client));
+                    tracing::info!(phone_number, "account initialized after retry");
+
+                    let text = format!("✅ {phone_number} initialized");
+                    if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                        tracing::error!(?err, "failed to report initialized account");
+                    }
+                }
+                Ok(None) => {
+                    tracing::warn!(phone_number, "account isn't logged in, not retried");
+
+                    let text = format!(
+                        "🔑 {phone_number} isn't logged in, log it in with \
+                        /addaccount {phone_number} or `login`"
+                    );
+                    if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                        tracing::error!(?err, "failed to report logged out account");
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(?err, phone_number, ?backoff, "account retry failed");
+                    still_failed.push(account);
+                }
+            }
+        }
+
+        accounts = still_failed;
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    config,
+    db::{get_gift_observations, to_unix_millis},
+    history::history_report,
+    stats::{STATS, latency_report, latency_stats},
+};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+    #[serde(default = "default_timezone")]
+    timezone: Tz,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+pub async fn process(
+    config_path: &Path,
+    hours: u64,
+    gift_id: Option<i64>,
+    csv: bool,
+    json: bool,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    STATS.set_timezone(config.timezone);
+
+    let pool = SqlitePool::connect(&config.database_url).await?;
+
+    let window = Duration::from_secs(hours * 60 * 60);
+
+    if json {
+        let json = match gift_id {
+            Some(gift_id) => {
+                let since = to_unix_millis(SystemTime::now() - window);
+                serde_json::to_string_pretty(&get_gift_observations(&pool, gift_id, since).await?)?
+            }
+            None => serde_json::to_string_pretty(&latency_stats(&pool, window).await?)?,
+        };
+        println!("{json}");
+        return Ok(());
+    }
+
+    let report = match gift_id {
+        Some(gift_id) => history_report(&pool, gift_id, window, csv).await?,
+        None => latency_report(&pool, window).await?,
+    };
+    print!("{report}");
+
+    Ok(())
+}
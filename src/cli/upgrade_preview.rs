@@ -0,0 +1,87 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use grammers_client::grammers_tl_types::{
+    enums::{
+        StarGift, StarGiftAttribute,
+        payments::{StarGiftUpgradePreview, StarGifts},
+    },
+    functions::payments::{GetStarGiftUpgradePreview, GetStarGifts},
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{cli::connect_first_account, config, uniques::format_permille};
+
+#[derive(Deserialize)]
+struct Config {
+    database_url: String,
+}
+
+pub async fn process(config_path: &Path, gift_id: i64) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+
+    let client = connect_first_account(pool, &accounts).await?;
+
+    let StarGifts::Gifts(star_gifts) = client.invoke(&GetStarGifts { hash: 0 }).await? else {
+        bail!("unexpected not modified");
+    };
+    let Some(gift) = star_gifts.gifts.into_iter().find_map(|gift| match gift {
+        StarGift::Gift(gift) if gift.id == gift_id => Some(gift),
+        _ => None,
+    }) else {
+        bail!("gift {gift_id} not found in the catalog");
+    };
+
+    let Some(upgrade_stars) = gift.upgrade_stars else {
+        bail!("gift {gift_id} can't be upgraded");
+    };
+
+    let StarGiftUpgradePreview::Preview(preview) = client
+        .invoke(&GetStarGiftUpgradePreview { gift_id })
+        .await?;
+
+    println!(
+        "Gift {gift_id}: {} ⭐, upgrade {upgrade_stars} ⭐",
+        gift.stars
+    );
+
+    let mut models = vec![];
+    let mut patterns = vec![];
+    let mut backdrops = vec![];
+    for attribute in preview.sample_attributes {
+        match attribute {
+            StarGiftAttribute::Model(model) => models.push((model.name, model.rarity_permille)),
+            StarGiftAttribute::Pattern(pattern) => {
+                patterns.push((pattern.name, pattern.rarity_permille))
+            }
+            StarGiftAttribute::Backdrop(backdrop) => {
+                backdrops.push((backdrop.name, backdrop.rarity_permille))
+            }
+            StarGiftAttribute::OriginalDetails(_) => {}
+        }
+    }
+
+    for (title, attributes) in [
+        ("Models", models),
+        ("Symbols", patterns),
+        ("Backdrops", backdrops),
+    ] {
+        print_attributes(title, attributes);
+    }
+
+    Ok(())
+}
+
+// rarest first
+fn print_attributes(title: &str, mut attributes: Vec<(String, i32)>) {
+    attributes.sort_by_key(|&(_, rarity_permille)| rarity_permille);
+
+    println!("\n{title} ({}):", attributes.len());
+    for (name, rarity_permille) in attributes {
+        println!("  {name:<32} {}", format_permille(rarity_permille));
+    }
+}
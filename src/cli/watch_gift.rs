@@ -0,0 +1,172 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use grammers_client::grammers_tl_types::{
+    enums::{StarGift, payments::StarGifts},
+    functions::payments::GetStarGifts,
+    types,
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    cli::{ExitError, buy_gifts::login_clients, connect_first_account, load_daily_limits},
+    config,
+    core::{
+        BuyGiftsDestination, BuyLimits, GiftOptions, PurchaseCoordinator, buy_gifts,
+        wait_purchase_tasks,
+    },
+    wrapped_client::LoginCodeSource,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    bot_token: String,
+    #[serde(default, deserialize_with = "config::comma_separated")]
+    admin_usernames: Vec<String>,
+    database_url: String,
+    #[serde(default)]
+    login_code_source: LoginCodeSource,
+    // the global ceiling of `start`, buying with --buy is automated as well
+    max_price: Option<i64>,
+}
+
+// met once every set bound holds
+#[derive(Debug, Clone, Copy)]
+pub struct Condition {
+    pub available: bool,
+    pub max_price: Option<i64>,
+    pub max_resale_price: Option<i64>,
+}
+
+impl Condition {
+    fn is_met(&self, gift: &types::StarGift) -> bool {
+        (!self.available || !gift.sold_out)
+            && self
+                .max_price
+                .is_none_or(|max_price| gift.stars <= max_price)
+            && self.max_resale_price.is_none_or(|max_resale_price| {
+                gift.resell_min_stars
+                    .is_some_and(|resale_floor| resale_floor <= max_resale_price)
+            })
+    }
+}
+
+// polls only the catalog, so `interval` can be much shorter than in `start`,
+// exits after alerting (and buying) once the condition is met
+pub async fn process(
+    config_path: &Path,
+    gift_id: i64,
+    condition: Condition,
+    interval: Duration,
+    buy_limit: Option<u64>,
+) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+    let coordinator = Arc::new(PurchaseCoordinator::with_daily_limits(load_daily_limits(
+        config_path,
+    )?));
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+    let bot = Arc::new(Bot::new(config.bot_token));
+
+    // every account is logged in upfront only when it's going to buy
+    let clients = match buy_limit {
+        Some(_) => {
+            login_clients(
+                pool.clone(),
+                bot.clone(),
+                &accounts,
+                &config.login_code_source,
+                config.admin_usernames,
+            )
+            .await?
+        }
+        None => vec![Arc::new(
+            connect_first_account(pool.clone(), &accounts).await?,
+        )],
+    };
+    let Some(client) = clients.first() else {
+        bail!(ExitError::Auth(
+            "none of the accounts could be logged in".to_string()
+        ));
+    };
+
+    let mut hash = 0;
+    let gift = loop {
+        match client.invoke(&GetStarGifts { hash }).await {
+            Ok(StarGifts::Gifts(star_gifts)) => {
+                hash = star_gifts.hash;
+
+                let gift = star_gifts.gifts.into_iter().find_map(|gift| match gift {
+                    StarGift::Gift(gift) if gift.id == gift_id => Some(gift),
+                    _ => None,
+                });
+
+                match gift {
+                    Some(gift) if condition.is_met(&gift) => break gift,
+                    Some(gift) => tracing::debug!(
+                        stars = gift.stars,
+                        sold_out = gift.sold_out,
+                        remains = gift.availability_remains,
+                        resale_floor = gift.resell_min_stars,
+                        "condition not met"
+                    ),
+                    None => tracing::debug!(gift_id, "gift not in the catalog"),
+                }
+            }
+            Ok(StarGifts::NotModified) => {}
+            Err(err) => tracing::error!(?err, "failed to get star gifts"),
+        }
+
+        tokio::time::sleep(interval).await;
+    };
+
+    let text = format!(
+        "👀 Gift {gift_id} matched: {} ⭐, remains {:?}, resale from {:?} ⭐",
+        gift.stars, gift.availability_remains, gift.resell_min_stars
+    );
+    println!("{text}");
+    if let Err(err) = alert_chats(&bot, &pool, &text).await {
+        tracing::error!(?err, "failed to send watch alert");
+    }
+
+    if let Some(buy_limit) = buy_limit {
+        let limits = BuyLimits {
+            max_price: config.max_price,
+            ..Some(buy_limit).into()
+        };
+        let gifts_map = BTreeMap::from([(gift.id, gift)]);
+
+        let outcome = buy_gifts(
+            &clients,
+            bot.clone(),
+            pool.clone(),
+            &coordinator,
+            vec![gift_id],
+            Some(&gifts_map),
+            &limits,
+            &BuyGiftsDestination::PeerSelf.into(),
+            &GiftOptions::default(),
+            SystemTime::now(),
+        )
+        .await?;
+        wait_purchase_tasks().await;
+
+        if outcome.failed > 0 {
+            bail!(ExitError::PartialBuy {
+                bought: outcome.bought,
+                failed: outcome.failed,
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    cli::{buy_gifts::login_clients, load_daily_limits},
+    config,
+    core::{BuyGiftsDestination, GiftOptions, PurchaseCoordinator, buy_gifts},
+    db::{claim_buy_job, finish_buy_job, prune_buy_jobs, to_unix_millis},
+    jobs::{Job, JobStatus},
+    wrapped_client::LoginCodeSource,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    bot_token: String,
+    #[serde(default, deserialize_with = "config::comma_separated")]
+    admin_usernames: Vec<String>,
+    database_url: String,
+    #[serde(default)]
+    login_code_source: LoginCodeSource,
+    #[serde(default = "default_job_poll_interval_ms")]
+    job_poll_interval_ms: u64,
+    // jobs queued longer ago than this are skipped, the gifts are likely sold out by then
+    #[serde(default = "default_job_max_age_secs")]
+    job_max_age_secs: u64,
+    #[serde(default)]
+    hide_bought_gifts: bool,
+}
+
+// finished jobs are kept this long for inspection
+const JOB_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+const JOB_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+fn default_job_poll_interval_ms() -> u64 {
+    100
+}
+
+fn default_job_max_age_secs() -> u64 {
+    60
+}
+
+// buys the jobs queued by `start --watch-only` with the accounts of this config, each job
+// is claimed by one of the running workers, so workers can share the queue
+pub async fn process(config_path: &Path) -> Result<()> {
+    let config: Config = config::load(config_path)?;
+    let accounts: config::Accounts = config::load(config_path)?;
+    let coordinator = Arc::new(PurchaseCoordinator::with_daily_limits(load_daily_limits(
+        config_path,
+    )?));
+
+    let pool = Arc::new(SqlitePool::connect(&config.database_url).await?);
+    let bot = Arc::new(Bot::new(config.bot_token));
+
+    let clients = login_clients(
+        pool.clone(),
+        bot.clone(),
+        &accounts,
+        &config.login_code_source,
+        config.admin_usernames,
+    )
+    .await?;
+
+    let gift_options = Arc::new(GiftOptions {
+        unsave: config.hide_bought_gifts,
+        ..Default::default()
+    });
+    let interval = Duration::from_millis(config.job_poll_interval_ms);
+    let max_age = Duration::from_secs(config.job_max_age_secs);
+
+    tracing::info!("waiting for buy jobs");
+
+    let mut pruned_at = None::<Instant>;
+    loop {
+        if pruned_at.is_none_or(|pruned_at| pruned_at.elapsed() >= JOB_PRUNE_INTERVAL) {
+            let before = to_unix_millis(SystemTime::now() - JOB_RETENTION);
+            if let Err(err) = prune_buy_jobs(&*pool, before).await {
+                tracing::error!(?err, "failed to prune buy jobs");
+            }
+            pruned_at = Some(Instant::now());
+        }
+
+        // claims jobs until the queue is empty
+        loop {
+            let job = match claim_buy_job(&*pool).await {
+                Ok(Some(t)) => t,
+                Ok(None) => break,
+                Err(err) => {
+                    tracing::error!(?err, "failed to claim buy job");
+                    break;
+                }
+            };
+
+            let age = to_unix_millis(SystemTime::now()) - job.created_at;
+            if age > max_age.as_millis() as i64 {
+                tracing::warn!(id = job.id, age_ms = age, "buy job too old, skipping");
+                finish_job(&pool, job.id, JobStatus::Skipped).await;
+                continue;
+            }
+
+            let id = job.id;
+            let job = match Job::decode(job) {
+                Ok(t) => t,
+                Err(err) => {
+                    tracing::error!(?err, "failed to decode buy job");
+                    finish_job(&pool, id, JobStatus::Skipped).await;
+                    continue;
+                }
+            };
+            tracing::info!(id = job.id, gifts = job.gifts.len(), "buy job received");
+
+            let clients = clients.clone();
+            let bot = bot.clone();
+            let pool = pool.clone();
+            let coordinator = coordinator.clone();
+            let gift_options = gift_options.clone();
+
+            // a running job doesn't hold up the next one, the coordinator keeps them
+            // from exceeding the limits together
+            tokio::spawn(async move {
+                let gift_ids = job.gifts.iter().map(|gift| gift.id).collect();
+                let gifts_map: BTreeMap<_, _> =
+                    job.gifts.into_iter().map(|gift| (gift.id, gift)).collect();
+
+                let result = buy_gifts(
+                    &clients,
+                    bot,
+                    pool.clone(),
+                    &coordinator,
+                    gift_ids,
+                    Some(&gifts_map),
+                    &job.limits,
+                    &BuyGiftsDestination::PeerSelf.into(),
+                    &gift_options,
+                    job.detected_at,
+                )
+                .await;
+                let status = match result {
+                    Ok(_) => JobStatus::Done,
+                    Err(err) => {
+                        tracing::error!(?err, id = job.id, "failed to buy job");
+                        JobStatus::Failed
+                    }
+                };
+                finish_job(&pool, job.id, status).await;
+            });
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn finish_job(pool: &SqlitePool, id: i64, status: JobStatus) {
+    let finished_at = to_unix_millis(SystemTime::now());
+    if let Err(err) = finish_buy_job(pool, id, status.as_str(), finished_at).await {
+        tracing::error!(?err, id, "failed to finish buy job");
+    }
+}
@@ -0,0 +1,290 @@
+use std::{collections::BTreeMap, env, path::Path, time::Duration};
+
+use figment::{
+    Figment,
+    providers::{Format, Serialized, Toml},
+};
+use serde::{Deserialize, Deserializer, de::DeserializeOwned};
+
+pub type Result<T, E = figment::Error> = std::result::Result<T, E>;
+
+// env vars take precedence over the file, which may be absent entirely, lossy extraction
+// doesn't reach into `#[serde(flatten)]` fields, so shared keys like `Accounts` are
+// extracted in a pass of their own instead of being flattened into the command's config
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    load_with_env(
+        path,
+        env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?))),
+    )
+}
+
+fn load_with_env<T: DeserializeOwned>(
+    path: &Path,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> Result<T> {
+    // passed as plain strings (unlike `figment::providers::Env`), so values like
+    // phone numbers aren't parsed into integers; lossy extraction converts them back
+    // into numbers/bools where the target field requires it
+    let env: BTreeMap<_, _> = env
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect();
+
+    Figment::new()
+        .merge(Toml::file(path))
+        .merge(Serialized::defaults(env))
+        .extract_lossy()
+}
+
+// accepts both a TOML array and a comma separated string (as used in env vars)
+pub fn comma_separated<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrVec {
+        String(String),
+        Vec(Vec<String>),
+    }
+
+    Ok(match StringOrVec::deserialize(deserializer)? {
+        StringOrVec::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect(),
+        StringOrVec::Vec(v) => v,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AccountConfig {
+    phone_number: String,
+    // shown next to the phone number by /accounts
+    label: Option<String>,
+    api_id: Option<i32>,
+    api_hash: Option<String>,
+    password: Option<String>,
+    proxy: Option<Proxy>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Proxy {
+    // socks5://[user:password@]host:port
+    Socks5(String),
+    // rejected when connecting until the client library supports the obfuscated transport
+    MtProto {
+        server: String,
+        port: u16,
+        #[allow(dead_code)]
+        secret: String,
+    },
+}
+
+impl Proxy {
+    // without the credentials, for reports
+    pub fn address(&self) -> String {
+        match self {
+            Self::Socks5(url) => match url.split_once("://") {
+                Some((scheme, rest)) => {
+                    let host = rest.rsplit_once('@').map_or(rest, |(_, host)| host);
+                    format!("{scheme}://{host}")
+                }
+                None => url
+                    .rsplit_once('@')
+                    .map_or(url, |(_, host)| host)
+                    .to_string(),
+            },
+            Self::MtProto { server, port, .. } => format!("mtproto://{server}:{port}"),
+        }
+    }
+}
+
+// `phone_numbers` share the global `api_id`/`api_hash`, entries of `accounts`
+// may bring their own to spread per-app limits, falling back to the global pair
+#[derive(Debug, Clone, Deserialize)]
+pub struct Accounts {
+    api_id: Option<i32>,
+    api_hash: Option<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    phone_numbers: Vec<String>,
+    #[serde(default)]
+    accounts: Vec<AccountConfig>,
+    // 2FA passwords by phone number, prompted for when missing
+    #[serde(default)]
+    passwords: BTreeMap<String, String>,
+    // used by accounts without their own proxy
+    proxy: Option<Proxy>,
+    #[serde(default = "default_retry_max_attempts")]
+    retry_max_attempts: u32,
+    #[serde(default = "default_retry_backoff_ms")]
+    retry_backoff_ms: u64,
+    #[serde(default = "default_retry_max_backoff_ms")]
+    retry_max_backoff_ms: u64,
+    // payment requests per second of every account, unlimited when unset
+    payment_rate_limit: Option<f64>,
+    #[serde(default = "default_payment_rate_limit_burst")]
+    payment_rate_limit_burst: u32,
+}
+
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    100
+}
+
+fn default_retry_max_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_payment_rate_limit_burst() -> u32 {
+    5
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+// applied to transient errors (timeouts, internal server errors) of every call
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // including the first attempt
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub phone_number: String,
+    pub label: Option<String>,
+    pub api_id: i32,
+    pub api_hash: String,
+    pub password: Option<String>,
+    pub proxy: Option<Proxy>,
+    pub retry_policy: RetryPolicy,
+    pub payment_rate_limit: Option<RateLimit>,
+}
+
+impl Accounts {
+    // `phone_numbers` first, then `accounts`
+    pub fn all(&self) -> Result<Vec<Account>> {
+        self.phone_numbers
+            .iter()
+            .map(|phone_number| phone_number.as_str())
+            .chain(
+                self.accounts
+                    .iter()
+                    .map(|account| account.phone_number.as_str()),
+            )
+            .map(|phone_number| self.get(phone_number))
+            .collect()
+    }
+
+    // also resolves phone numbers missing from the config (e.g. added at runtime)
+    // using the global credentials
+    pub fn get(&self, phone_number: &str) -> Result<Account> {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.phone_number == phone_number);
+
+        let api_id = account.and_then(|account| account.api_id).or(self.api_id);
+        let api_hash = account
+            .and_then(|account| account.api_hash.clone())
+            .or_else(|| self.api_hash.clone());
+
+        let (Some(api_id), Some(api_hash)) = (api_id, api_hash) else {
+            return Err(figment::Error::from(format!(
+                "api_id and api_hash are required for {phone_number}"
+            )));
+        };
+
+        Ok(Account {
+            phone_number: phone_number.to_string(),
+            label: account.and_then(|account| account.label.clone()),
+            api_id,
+            api_hash,
+            password: account
+                .and_then(|account| account.password.clone())
+                .or_else(|| self.passwords.get(phone_number).cloned()),
+            proxy: account
+                .and_then(|account| account.proxy.clone())
+                .or_else(|| self.proxy.clone()),
+            retry_policy: RetryPolicy {
+                max_attempts: self.retry_max_attempts.max(1),
+                backoff: Duration::from_millis(self.retry_backoff_ms),
+                max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+            },
+            payment_rate_limit: self
+                .payment_rate_limit
+                .filter(|per_second| *per_second > 0.0)
+                .map(|per_second| RateLimit {
+                    per_second,
+                    burst: self.payment_rate_limit_burst,
+                }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct CommandConfig {
+        database_url: String,
+        poll_interval_ms: u64,
+    }
+
+    #[test]
+    fn accounts_from_env_only() {
+        let vars = env(&[
+            ("API_ID", "12345"),
+            ("API_HASH", "hash"),
+            ("PHONE_NUMBERS", "+10000000001, +10000000002"),
+            ("RETRY_MAX_ATTEMPTS", "5"),
+            ("PAYMENT_RATE_LIMIT", "2.5"),
+            ("PAYMENT_RATE_LIMIT_BURST", "3"),
+            ("DATABASE_URL", "sqlite://test.db"),
+            ("POLL_INTERVAL_MS", "750"),
+        ]);
+        let path = Path::new("does-not-exist.toml");
+
+        let accounts: Accounts = load_with_env(path, vars.clone()).unwrap();
+        let accounts = accounts.all().unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].phone_number, "+10000000001");
+        assert_eq!(accounts[1].phone_number, "+10000000002");
+        assert_eq!(accounts[0].api_id, 12345);
+        assert_eq!(accounts[0].api_hash, "hash");
+        assert_eq!(accounts[0].retry_policy.max_attempts, 5);
+        let rate_limit = accounts[0].payment_rate_limit.unwrap();
+        assert_eq!(rate_limit.per_second, 2.5);
+        assert_eq!(rate_limit.burst, 3);
+
+        let config: CommandConfig = load_with_env(path, vars).unwrap();
+        assert_eq!(config.database_url, "sqlite://test.db");
+        assert_eq!(config.poll_interval_ms, 750);
+    }
+
+    #[test]
+    fn missing_api_credentials() {
+        let vars = env(&[("PHONE_NUMBERS", "+10000000001")]);
+        let accounts: Accounts = load_with_env(Path::new("does-not-exist.toml"), vars).unwrap();
+        assert!(accounts.all().is_err());
+    }
+}
@@ -1,23 +1,60 @@
-use std::{borrow::Cow, collections::BTreeMap, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    fmt::Write,
+    str::FromStr,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
 
-use futures::{TryFutureExt, future::join_all};
+use chrono::{NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures::{
+    TryFutureExt,
+    future::{join_all, try_join_all},
+};
 use grammers_client::{
+    InvocationError,
     grammers_tl_types::{
         enums::{
-            InputInvoice, InputPeer, StarGift, StarsAmount,
-            payments::{StarGifts, StarsStatus},
+            InputInvoice, InputPeer, InputSavedStarGift, Message, MessageAction, StarGift,
+            StarsAmount, StarsTransaction, TextWithEntities, Update, Updates,
+            payments::{PaymentResult, StarGifts, StarsStatus},
+        },
+        functions::payments::{
+            GetPaymentForm, GetStarGifts, GetStarsStatus, GetStarsTransactions, SaveStarGift,
+            SendStarsForm, TransferStarGift,
+        },
+        types::{
+            self, InputInvoiceStarGift, InputInvoiceStarGiftTransfer, InputPeerChannel,
+            InputPeerUser, InputSavedStarGiftUser,
         },
-        functions::payments::{GetPaymentForm, GetStarGifts, GetStarsStatus, SendStarsForm},
-        types::{InputInvoiceStarGift, InputPeerChannel},
     },
-    types::Chat,
+    types::{Chat, User},
 };
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use teloxide::Bot;
+use tokio::task::JoinHandle;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use crate::{
-    bot::{self, GiftBuyStatus, notify_gift_buy_status},
-    wrapped_client::WrappedClient,
+    bot::{
+        self, BuyProgress, GiftBuyStatus, alert_chats, alert_chats_localized,
+        notify_gift_buy_status,
+    },
+    db::{
+        self, NewPurchase, get_spent_by_account, insert_purchase, set_purchase_transaction_id,
+        to_unix_millis,
+    },
+    i18n::{Language, Text},
+    stats::STATS,
+    telegram_api::TelegramApi,
+    wrapped_client::{PAYMENT_FORM_TTL, WrappedClient},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -25,9 +62,11 @@ pub enum Error {
     #[error(transparent)]
     Bot(#[from] bot::Error),
     #[error(transparent)]
+    Db(#[from] db::Error),
+    #[error(transparent)]
     GrammersInvocation(#[from] grammers_client::InvocationError),
-    #[error("gift price not found (gift_id = {0})")]
-    GiftPriceNotFound(i64),
+    #[error("gift not found (gift_id = {0})")]
+    GiftNotFound(i64),
     #[error("unexpected not modified")]
     UnexpectedNotModified,
     #[error("chat not found (username = {0})")]
@@ -36,185 +75,2094 @@ pub enum Error {
     ChatIsNotChannel,
     #[error("channel not accesible (channel_id = {0})")]
     ChannelNotAccessible(i64),
+    #[error("user not accesible (user_id = {0})")]
+    UserNotAccessible(i64),
+    #[error("user not found among chats (user_id = {0})")]
+    UserNotFound(i64),
+    #[error("gifts can't be sent to groups (username = {0})")]
+    UnsupportedDestination(String),
+    #[error("weight must be a positive integer (destination = {0})")]
+    InvalidDestinationWeight(String),
+    #[error("empty destination (destinations = {0})")]
+    EmptyDestination(String),
+    #[error("expected MAX_SUPPLY:LIMIT with LIMIT a number, `max` or `total:N` (rule = {0})")]
+    InvalidSupplyRule(String),
+    #[error("expected `limited`, `unlimited` or `resale` (gift type = {0})")]
+    InvalidGiftType(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// units of each gift per account, when not limited explicitly
+pub const DEFAULT_BUY_LIMIT: u64 = 100;
+
+// transactions fetched to find the receipts of an account's run, at least the minimum
+// and twice the units, other spending may have happened meanwhile
+const MIN_RECEIPT_TRANSACTIONS: i32 = 20;
+const MAX_RECEIPT_TRANSACTIONS: i32 = 100;
+
+// how often an account waiting on units in flight at the fleet limit checks them again
+const RESERVATION_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// scheduled gifts opening later than this are skipped, so the poll loop isn't held up
+const MAX_SCHEDULED_GIFT_WAIT: Duration = Duration::from_secs(10 * 60);
+
+// prefetched forms are fetched again before they expire, see `PAYMENT_FORM_TTL`
+const PAYMENT_FORM_REFRESH_INTERVAL: Duration =
+    Duration::from_secs(PAYMENT_FORM_TTL.as_secs() * 3 / 4);
+
+// records, receipts and notifications of purchases, spawned so they don't hold up the next
+// unit, commands exiting after buying wait for them with `wait_purchase_tasks`
+pub static PURCHASE_TASKS: LazyLock<TaskTracker> = LazyLock::new(TaskTracker::new);
+
+// units of each gift per account
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuyLimits {
+    pub default: Option<u64>,
+    pub per_gift: HashMap<i64, u64>,
+    // units across all accounts, for these gifts the accounts race for the total
+    // instead of buying their own limits
+    #[serde(default)]
+    pub totals: HashMap<i64, u64>,
+    // gifts costing more per unit are never bought, whatever selected them
+    #[serde(default)]
+    pub max_price: Option<i64>,
+    #[serde(default)]
+    pub allocation: Allocation,
+    // no units are attempted this long after detection, by then the gifts are usually
+    // sold out and further attempts only pile up errors and flood waits
+    #[serde(default)]
+    pub max_buy_duration: Option<Duration>,
+}
+
+// how the units of a gift are split between the accounts
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Allocation {
+    // every account buys up to the limit
+    #[default]
+    PerAccount,
+    // the limit times the accounts is split in proportion to their balances, so the
+    // richest accounts buy the most and poor ones don't waste time on failing units,
+    // costs fetching every balance before buying
+    BalanceProportional,
+    // a single unit of each gift, bought by whichever account gets to it first,
+    // the other accounts move on to the next gift
+    Once,
+}
+
+impl BuyLimits {
+    pub fn get(&self, gift_id: i64) -> u64 {
+        self.per_gift
+            .get(&gift_id)
+            .copied()
+            .or(self.default)
+            .unwrap_or(DEFAULT_BUY_LIMIT)
+    }
+
+    pub fn total(&self, gift_id: i64) -> Option<u64> {
+        match self.allocation {
+            Allocation::Once => Some(1),
+            _ => self.totals.get(&gift_id).copied(),
+        }
+    }
+
+    // compares the price of the gift itself, an upgrade paid along isn't part of it
+    pub fn exceeds_max_price(&self, gift: &types::StarGift) -> bool {
+        self.max_price
+            .is_some_and(|max_price| gift.stars > max_price)
+    }
+
+    // units the `account`-th account aims for, `balances` are those of every account with
+    // `Allocation::BalanceProportional`, accounts that can't afford a unit get no share
+    pub fn account_target(
+        &self,
+        gift_id: i64,
+        unit_price: i64,
+        account: usize,
+        balances: Option<&[Option<i64>]>,
+    ) -> u64 {
+        let affordable = balances.map(|balances| {
+            balances
+                .iter()
+                .map(|balance| balance.filter(|&balance| balance >= unit_price))
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<_>>()
+        });
+        match (self.total(gift_id), affordable) {
+            (Some(total), Some(affordable)) => proportional_shares(total, &affordable)[account],
+            (Some(total), None) => total,
+            (None, Some(affordable)) => {
+                proportional_shares(self.get(gift_id) * affordable.len() as u64, &affordable)
+                    [account]
+            }
+            (None, None) => self.get(gift_id),
+        }
+    }
+}
+
+impl From<Option<u64>> for BuyLimits {
+    fn from(default: Option<u64>) -> Self {
+        Self {
+            default,
+            per_gift: HashMap::new(),
+            totals: HashMap::new(),
+            max_price: None,
+            allocation: Allocation::default(),
+            max_buy_duration: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BuyGiftsDestination {
+    PeerSelf,
+    Channel(MaybeResolvedChannel),
+    // a user or a channel
+    Username(String),
+    // a user without a username, found among the account's chats
+    UserId(i64),
+}
+
+impl BuyGiftsDestination {
+    // shown on the buttons opening `link`
+    pub fn label(&self) -> String {
+        match self {
+            Self::PeerSelf => "self".to_string(),
+            Self::Channel(MaybeResolvedChannel::Username(username)) | Self::Username(username) => {
+                format!("@{username}")
+            }
+            Self::Channel(MaybeResolvedChannel::Peer(channel)) => {
+                format!("channel {}", channel.channel_id)
+            }
+            Self::UserId(user_id) => format!("user {user_id}"),
+        }
+    }
+
+    // the profile or channel showing gifts bought to it, only public usernames have links
+    // that open for anyone, the others depend on privacy settings and memberships
+    pub fn link(&self) -> Option<String> {
+        match self {
+            Self::Channel(MaybeResolvedChannel::Username(username)) | Self::Username(username) => {
+                Some(format!("https://t.me/{username}"))
+            }
+            Self::PeerSelf | Self::Channel(MaybeResolvedChannel::Peer(_)) | Self::UserId(_) => None,
+        }
+    }
+
+    // the post announcing a gift bought to a public channel, see `channel_gift_message_id`
+    pub fn message_link(&self, msg_id: i32) -> Option<String> {
+        match self {
+            Self::Channel(MaybeResolvedChannel::Username(username)) => {
+                Some(format!("https://t.me/{username}/{msg_id}"))
+            }
+            _ => None,
+        }
+    }
+
+    // access hashes differ between accounts, so every client resolves the peer itself
+    pub async fn resolve(&self, client: &WrappedClient) -> Result<InputPeer> {
+        Ok(match self {
+            Self::PeerSelf => InputPeer::PeerSelf,
+            Self::Channel(channel) => InputPeer::Channel(channel.resolve(&client.client()).await?),
+            Self::Username(username) => {
+                let chat = client
+                    .client()
+                    .resolve_username(username)
+                    .await?
+                    .ok_or_else(|| Error::ChatNotFound(username.to_string()))?;
+
+                match chat {
+                    Chat::User(user) => user_peer(&user)?,
+                    Chat::Channel(channel) => InputPeer::Channel(InputPeerChannel {
+                        channel_id: channel.raw.id,
+                        access_hash: channel
+                            .raw
+                            .access_hash
+                            .ok_or(Error::ChannelNotAccessible(channel.raw.id))?,
+                    }),
+                    Chat::Group(_) => return Err(Error::UnsupportedDestination(username.clone())),
+                }
+            }
+            Self::UserId(user_id) => {
+                let client = client.client();
+                let mut dialogs = client.iter_dialogs();
+                loop {
+                    let Some(dialog) = dialogs.next().await? else {
+                        return Err(Error::UserNotFound(*user_id));
+                    };
+                    if let Chat::User(user) = dialog.chat()
+                        && user.raw.id == *user_id
+                    {
+                        break user_peer(user)?;
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn user_peer(user: &User) -> Result<InputPeer> {
+    Ok(InputPeer::User(InputPeerUser {
+        user_id: user.raw.id,
+        access_hash: user
+            .raw
+            .access_hash
+            .ok_or(Error::UserNotAccessible(user.raw.id))?,
+    }))
+}
+
+// `self`, a user ID, or a @username of a user or channel
+impl FromStr for BuyGiftsDestination {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "self" => Self::PeerSelf,
+            s => match s.parse() {
+                Ok(user_id) => Self::UserId(user_id),
+                Err(_) => Self::Username(s.trim_start_matches('@').to_string()),
+            },
+        })
+    }
+}
+
+// recipients of the units bought of each gift in proportion to their weights,
+// e.g. `@channel:60,self:40`
+#[derive(Debug, Clone)]
+pub struct BuyDestinations(Vec<(BuyGiftsDestination, u32)>);
+
+impl BuyDestinations {
+    pub fn destinations(&self) -> impl Iterator<Item = &BuyGiftsDestination> {
+        self.0.iter().map(|(dest, _)| dest)
+    }
+
+    // endless indices of the destinations of consecutive units, interleaved by smooth
+    // weighted round-robin instead of in runs, every sum of the weights units match them
+    // exactly
+    fn rotation(&self) -> impl Iterator<Item = usize> {
+        let total: i64 = self.0.iter().map(|(_, weight)| i64::from(*weight)).sum();
+        let mut current = vec![0; self.0.len()];
+
+        std::iter::from_fn(move || {
+            for (current, (_, weight)) in current.iter_mut().zip(&self.0) {
+                *current += i64::from(*weight);
+            }
+            let i = (0..current.len())
+                .reduce(|best, i| if current[i] > current[best] { i } else { best })?;
+            current[i] -= total;
+            Some(i)
+        })
+    }
+}
+
+impl From<BuyGiftsDestination> for BuyDestinations {
+    fn from(dest: BuyGiftsDestination) -> Self {
+        Self(vec![(dest, 1)])
+    }
+}
+
+// comma-separated destinations, each optionally with its weight as DESTINATION:WEIGHT
+impl FromStr for BuyDestinations {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (dest, weight) = match part.rsplit_once(':') {
+                    Some((dest, weight)) => (
+                        dest,
+                        weight
+                            .parse()
+                            .ok()
+                            .filter(|&weight| weight > 0)
+                            .ok_or_else(|| Error::InvalidDestinationWeight(part.to_string()))?,
+                    ),
+                    None => (part, 1),
+                };
+                if dest.is_empty() {
+                    return Err(Error::EmptyDestination(s.to_string()));
+                }
+                Ok((dest.parse().unwrap(), weight))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GiftOptions {
+    // hides the sender from the recipient
+    pub hide_name: bool,
+    // pays for the upgrade to a unique gift along with the gift
+    pub include_upgrade: bool,
+    pub message: Option<String>,
+    // hides gifts bought to the account itself from its profile
+    pub unsave: bool,
+}
+
+pub async fn wait_purchase_tasks() {
+    PURCHASE_TASKS.close();
+    PURCHASE_TASKS.wait().await;
+}
+
+// expects `gift_ids` to be sorted by priority,
+// `detected_at` is when the gifts were first seen and is used for latency stats
+#[allow(clippy::too_many_arguments)]
+pub async fn buy_gifts<C: TelegramApi + 'static>(
+    clients: &[Arc<C>],
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    coordinator: &Arc<PurchaseCoordinator>,
+    gift_ids: Vec<i64>,
+    gifts_map: Option<&BTreeMap<i64, types::StarGift>>,
+    limits: &BuyLimits,
+    dest: &BuyDestinations,
+    gift_options: &GiftOptions,
+    detected_at: SystemTime,
+) -> Result<BuyOutcome> {
+    let deadline = limits
+        .max_buy_duration
+        .map(|max_buy_duration| detected_at + max_buy_duration);
+    let detected_at = to_unix_millis(detected_at);
+    // forms are prefetched for the destination of each gift's first unit
+    let first_dest = dest.rotation().next().unwrap_or_default();
+
+    // revoked sessions fail every call
+    let clients: Vec<_> = clients
+        .iter()
+        .filter(|client| !client.is_deauthorized())
+        .collect();
+
+    let Some(&first_client) = clients.first() else {
+        tracing::warn!("no authorized clients to buy gifts with");
+        return Ok(BuyOutcome::default());
+    };
+
+    let gift_ids: Arc<[_]> = gift_ids.into();
+    let gifts = get_gifts(&**first_client, &gift_ids, gifts_map).await?;
+
+    let balances = match limits.allocation {
+        Allocation::PerAccount | Allocation::Once => None,
+        Allocation::BalanceProportional => Some(get_balances(&clients).await),
+    };
+    let balances = balances.as_deref();
+
+    coordinator.load_daily_spend(&pool).await?;
+    let job = coordinator.join(&gift_ids);
+    let progress = BuyProgress::spawn(
+        bot.clone(),
+        pool.clone(),
+        coordinator.cancel_buttons.then_some(job.id()),
+        gift_ids.len(),
+    );
+
+    tracing::debug!(?gift_ids, "buy_gifts");
+
+    let results = join_all(clients.iter().enumerate().map(|(i, &client)| {
+        let bot = bot.clone();
+        let pool = pool.clone();
+        let gift_ids = gift_ids.clone();
+        let gifts = gifts.clone();
+        let job = &job;
+        let progress = &progress;
+
+        async move {
+            let _purchases = client.begin_purchases().await;
+
+            let dest_peers = try_join_all(
+                dest.destinations()
+                    .map(|dest| client.resolve_destination(dest)),
+            )
+            .await?;
+
+            let prefetch =
+                prefetch_payment_forms(&**client, &gift_ids, &dest_peers[first_dest], gift_options);
+            // the balances fetched for the allocation are recent enough
+            let mut balance = match balances.and_then(|balances| balances[i]) {
+                Some(balance) => {
+                    prefetch.await;
+                    balance
+                }
+                None => {
+                    let (status, ()) = tokio::join!(
+                        client.invoke(&GetStarsStatus {
+                            peer: InputPeer::PeerSelf,
+                        }),
+                        prefetch,
+                    );
+                    let StarsStatus::Status(status) = status?;
+                    tracing::debug!(?status, phone_number = client.phone_number());
+                    let StarsAmount::Amount(amount) = status.balance;
+                    amount.amount
+                }
+            };
+            let mut spend = AccountSpend {
+                balance,
+                ..Default::default()
+            };
+            let mut receipts = vec![];
+
+            let is_premium = client.is_premium();
+
+            // index of the gift the account buys next, the forms of the gifts from there on
+            // would expire while the earlier ones are bought, so they're kept fresh
+            let next_gift = AtomicUsize::new(0);
+            let refresh_forms = async {
+                loop {
+                    tokio::time::sleep(PAYMENT_FORM_REFRESH_INTERVAL).await;
+                    if purchase_flood_wait(&**client).is_some() {
+                        continue;
+                    }
+                    let remaining = &gift_ids[next_gift.load(Ordering::Relaxed)..];
+                    prefetch_payment_forms(
+                        &**client,
+                        remaining,
+                        &dest_peers[first_dest],
+                        gift_options,
+                    )
+                    .await;
+                }
+            };
+
+            let buy = async {
+                // units of each gift bought when the account last started on it
+                let mut visits = HashMap::new();
+                let mut order = 0..gifts.len();
+                loop {
+                    // after the last gift, the gifts other accounts handed units over of
+                    // meanwhile are gone through again, nobody would take the units otherwise,
+                    // as long as the previous visit bought something
+                    let Some(index) = order.next().or_else(|| {
+                        gifts.iter().position(|gift| {
+                            job.has_handed_over(gift.id)
+                                && visits
+                                    .get(&gift.id)
+                                    .is_some_and(|&units| spend.units_of(gift.id) > units)
+                        })
+                    }) else {
+                        break;
+                    };
+                    let gift = &gifts[index];
+                    next_gift.store(index, Ordering::Relaxed);
+                    let gift_id = gift.id;
+                    let is_revisit = visits.insert(gift_id, spend.units_of(gift_id)).is_some();
+                    // what a unit takes from the balance, the upgrade is paid with the gift
+                    let gift_price = unit_price(gift, gift_options);
+
+                    // scheduled gifts opening soon are waited for, with their forms kept
+                    // fresh meanwhile, so the purchase at the opening is only SendStarsForm
+                    if let Some(opens_at) = opening_time(gift)
+                        && let Ok(wait) = opens_at.duration_since(SystemTime::now())
+                        && wait <= MAX_SCHEDULED_GIFT_WAIT
+                        && deadline.is_none_or(|deadline| opens_at < deadline)
+                    {
+                        tracing::info!(
+                            gift_id,
+                            ?wait,
+                            phone_number = client.phone_number(),
+                            "waiting for scheduled gift to open"
+                        );
+                        tokio::select! {
+                            () = tokio::time::sleep(wait) => {}
+                            () = job.cancel.cancelled() => return Ok(spend),
+                        }
+                    }
+                    next_gift.store(index + 1, Ordering::Relaxed);
+
+                    // SendStarsForm is guaranteed to fail for these
+                    if let Some(reason) = unavailable_reason(gift, is_premium) {
+                        tracing::info!(
+                            gift_id,
+                            phone_number = client.phone_number(),
+                            reason,
+                            "gift unavailable for account, skipping"
+                        );
+                        continue;
+                    }
+
+                    if limits.exceeds_max_price(gift) {
+                        tracing::warn!(
+                            gift_id,
+                            gift_price = gift.stars,
+                            max_price = limits.max_price,
+                            "gift costs more than max_price, skipping"
+                        );
+                        continue;
+                    }
+
+                    // with a total, every account may buy all of it, the reservations stop
+                    // the fleet at the total however the accounts progress
+                    let target = limits.account_target(gift_id, gift_price, i, balances);
+                    let fleet_limit = limits.total(gift_id).unwrap_or(u64::MAX);
+                    // units over the cap are handed over to the other accounts, a revisit only
+                    // takes handed-over units
+                    let cap = per_user_cap(gift);
+                    let limit = if is_revisit { 0 } else { target.min(cap) };
+                    if !is_revisit {
+                        job.hand_over(gift_id, target - limit);
+                    }
+                    let mut rotation = dest.rotation().peekable();
+
+                    let mut count = 0;
+                    loop {
+                        count += 1;
+
+                        if balance < gift_price {
+                            break;
+                        }
+
+                        // every further attempt would fail the same way until the wait passes
+                        if let Some(flood_wait) = purchase_flood_wait(&**client) {
+                            tracing::warn!(
+                                ?flood_wait,
+                                phone_number = client.phone_number(),
+                                "client in flood wait, skipping purchases"
+                            );
+                            return Ok(spend);
+                        }
+
+                        if job.is_cancelled() {
+                            tracing::info!(
+                                phone_number = client.phone_number(),
+                                "buying cancelled"
+                            );
+                            return Ok(spend);
+                        }
+
+                        if let Some(deadline) = deadline
+                            && SystemTime::now() >= deadline
+                        {
+                            tracing::warn!(
+                                phone_number = client.phone_number(),
+                                "max_buy_duration passed, stopping purchases"
+                            );
+                            return Ok(spend);
+                        }
+
+                        let phone_number = client.phone_number().to_string();
+
+                        // once its own units are bought, the account takes over the units handed
+                        // over by accounts still buying the gift, up to the per-user limit
+                        let is_handed_over = count > limit;
+                        if is_handed_over && (count > cap || !job.take_handed_over(gift_id)) {
+                            break;
+                        }
+
+                        if let Err(cap) = job.reserve_spend(&phone_number, gift_price) {
+                            tracing::warn!(
+                                phone_number,
+                                "daily spend limit reached, stopping purchases"
+                            );
+                            if let Some(cap) = cap {
+                                let bot = bot.clone();
+                                let pool = pool.clone();
+                                let account = client.display_name().to_string();
+                                PURCHASE_TASKS.spawn(async move {
+                                    let text = |language: Language| {
+                                        language.daily_spend_limit_reached(
+                                            cap.per_account.then_some(account.as_str()),
+                                            cap.spent,
+                                            cap.limit,
+                                        )
+                                    };
+                                    if let Err(err) = alert_chats_localized(&bot, &pool, text).await
+                                    {
+                                        tracing::error!(?err, "failed to alert daily spend limit");
+                                    }
+                                });
+                            }
+                            if is_handed_over {
+                                job.hand_over(gift_id, 1);
+                            }
+                            return Ok(spend);
+                        }
+
+                        // another running job may have bought the limit for this account already
+                        let reserve_limit = if is_handed_over { cap } else { limit };
+                        let reservation = loop {
+                            match job.reserve(gift_id, &phone_number, reserve_limit, fleet_limit) {
+                                // the fleet limit is reached with units of other accounts still
+                                // in flight, one is given back if its purchase fails
+                                Reservation::Pending
+                                    if !job.is_cancelled()
+                                        && deadline.is_none_or(|deadline| {
+                                            SystemTime::now() < deadline
+                                        }) =>
+                                {
+                                    tokio::time::sleep(RESERVATION_RETRY_INTERVAL).await;
+                                }
+                                reservation => break reservation,
+                            }
+                        };
+                        if reservation != Reservation::Claimed {
+                            job.release_spend(&phone_number, gift_price);
+                            if is_handed_over {
+                                job.hand_over(gift_id, 1);
+                            }
+                            break;
+                        }
+
+                        let dest_index = rotation.next().unwrap_or_default();
+                        let dest_peer = &dest_peers[dest_index];
+                        let dest_link = dest
+                            .destinations()
+                            .nth(dest_index)
+                            .and_then(|dest| dest.link());
+
+                        // let span = tracing::info_span!(
+                        //     "buy_gift",
+                        //     gift_id,
+                        //     count,
+                        //     phone_number = client.phone_number(),
+                        // );
+                        // let _guard = span.enter();
+
+                        let invoice = gift_invoice(gift_id, dest_peer, gift_options);
+
+                        let get_payment_form_result = match client.take_payment_form(&invoice) {
+                            Some(payment_form) => Ok(payment_form),
+                            None => {
+                                client
+                                    .invoke(&GetPaymentForm {
+                                        invoice: invoice.clone(),
+                                        theme_params: None,
+                                    })
+                                    .await
+                            }
+                        };
+                        tracing::debug!(?get_payment_form_result);
+
+                        let payment_form = match get_payment_form_result {
+                            Ok(t) => t,
+                            Err(err) => {
+                                tracing::error!(?err, "failed to get payment form");
+                                job.release(gift_id, &phone_number);
+                                job.release_spend(&phone_number, gift_price);
+                                STATS.record_failed_buy();
+                                progress.record(false);
+                                let status = GiftBuyStatus::PaymentFormError(err);
+                                let limit_reached = is_per_user_limit_reached(&status);
+                                spawn_record_purchase(
+                                    pool.clone(),
+                                    NewPurchase {
+                                        phone_number: phone_number.clone(),
+                                        gift_id,
+                                        stars: gift_price,
+                                        status: status.kind(),
+                                        error: status.error(),
+                                        detected_at,
+                                        payment_form_at: None,
+                                        sent_at: None,
+                                        form_id: None,
+                                        msg_id: None,
+                                        transaction_id: None,
+                                        to_self: matches!(dest_peer, InputPeer::PeerSelf),
+                                    },
+                                );
+                                PURCHASE_TASKS.spawn(
+                                    notify_gift_buy_status(
+                                        bot.clone(),
+                                        pool.clone(),
+                                        count,
+                                        client.phone_number().to_string(),
+                                        balance,
+                                        gift_id,
+                                        status,
+                                        dest_link,
+                                        None,
+                                    )
+                                    .inspect_err(move |err| {
+                                        tracing::error!(
+                                            ?err,
+                                            gift_id,
+                                            count,
+                                            phone_number,
+                                            "failed to notify gift buy status"
+                                        )
+                                    }),
+                                );
+                                if limit_reached {
+                                    hand_over_remaining(job, gift_id, count, limit);
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+
+                        let payment_form_at = to_unix_millis(SystemTime::now());
+
+                        // the next unit's form is fetched while this one is being sent, assuming
+                        // this purchase succeeds, an unused form just expires
+                        let fetch_next_form = count < limit && balance - gift_price >= gift_price;
+
+                        let ((send_stars_form_result, form_id, sent_at), ()) = tokio::join!(
+                            async {
+                                let (result, form_id) =
+                                    send_stars_form(&**client, payment_form.form_id(), invoice)
+                                        .await;
+                                (result, form_id, to_unix_millis(SystemTime::now()))
+                            },
+                            async {
+                                if fetch_next_form {
+                                    prefetch_payment_forms(
+                                        &**client,
+                                        &[gift_id],
+                                        &dest_peers[rotation.peek().copied().unwrap_or_default()],
+                                        gift_options,
+                                    )
+                                    .await;
+                                }
+                            },
+                        );
+                        tracing::debug!(?send_stars_form_result);
+
+                        let msg_id = send_stars_form_result
+                            .as_ref()
+                            .ok()
+                            .and_then(|result| gift_message_id(result, gift_id));
+                        let gift_link = send_stars_form_result
+                            .as_ref()
+                            .ok()
+                            .and_then(|result| channel_gift_message_id(result, gift_id))
+                            .and_then(|msg_id| {
+                                dest.destinations().nth(dest_index)?.message_link(msg_id)
+                            });
+
+                        let status = match send_stars_form_result {
+                            Ok(_) => {
+                                job.confirm(gift_id);
+                                balance -= gift_price;
+                                spend.record(gift_id, gift_price, balance);
+                                STATS.record_buy(gift_price);
+                                progress.record(true);
+                                tracing::debug!(balance, "success");
+                                GiftBuyStatus::Success
+                            }
+                            Err(err) => {
+                                tracing::error!(
+                                    ?err,
+                                    gift_id,
+                                    count,
+                                    phone_number,
+                                    "failed to send stars form"
+                                );
+                                job.release(gift_id, &phone_number);
+                                job.release_spend(&phone_number, gift_price);
+                                STATS.record_failed_buy();
+                                progress.record(false);
+                                GiftBuyStatus::SendStarsFormError(err)
+                            }
+                        };
+
+                        let purchase = NewPurchase {
+                            phone_number: phone_number.clone(),
+                            gift_id,
+                            stars: gift_price,
+                            status: status.kind(),
+                            error: status.error(),
+                            detected_at,
+                            payment_form_at: Some(payment_form_at),
+                            sent_at: matches!(status, GiftBuyStatus::Success).then_some(sent_at),
+                            form_id: Some(form_id),
+                            msg_id,
+                            transaction_id: None,
+                            to_self: matches!(dest_peer, InputPeer::PeerSelf),
+                        };
+                        if gift_options.unsave
+                            && matches!(dest_peer, InputPeer::PeerSelf)
+                            && let Some(msg_id) = msg_id
+                        {
+                            spawn_unsave_gift(Arc::clone(client), msg_id);
+                        }
+
+                        if matches!(status, GiftBuyStatus::Success) {
+                            let recorded = spawn_record_bought(
+                                pool.clone(),
+                                job.coordinator.clone(),
+                                purchase,
+                            );
+                            receipts.push((
+                                Receipt {
+                                    gift_id,
+                                    stars: gift_price,
+                                    form_id,
+                                    msg_id,
+                                },
+                                recorded,
+                            ));
+                        } else {
+                            spawn_record_purchase(pool.clone(), purchase);
+                        }
+
+                        let limit_reached = is_per_user_limit_reached(&status);
+                        let balance_too_low = matches!(
+                            &status,
+                            GiftBuyStatus::SendStarsFormError(InvocationError::Rpc(err))
+                                if err.name == "BALANCE_TOO_LOW"
+                        );
+
+                        PURCHASE_TASKS.spawn(
+                            notify_gift_buy_status(
+                                bot.clone(),
+                                pool.clone(),
+                                count,
+                                client.phone_number().to_string(),
+                                balance,
+                                gift_id,
+                                status,
+                                dest_link,
+                                gift_link,
+                            )
+                            .inspect_err(move |err| {
+                                tracing::error!(
+                                    ?err,
+                                    gift_id,
+                                    count,
+                                    phone_number,
+                                    "failed to notify gift buy status"
+                                )
+                            }),
+                        );
+
+                        if limit_reached {
+                            hand_over_remaining(job, gift_id, count, limit);
+                            break;
+                        }
+
+                        // the server's balance is the truth, the tracked one missed some spending,
+                        // so the account is done for this drop and its remaining gifts are skipped
+                        if balance_too_low {
+                            tracing::info!(
+                                phone_number = client.phone_number(),
+                                tracked_balance = balance,
+                                "balance too low, skipping remaining gifts"
+                            );
+                            return Ok(spend);
+                        }
+                    }
+                }
+
+                Result::<_, Error>::Ok(spend)
+            };
+
+            let result = tokio::select! {
+                result = buy => result,
+                _ = refresh_forms => unreachable!("forms are refreshed until the purchases finish"),
+            };
+            if !receipts.is_empty() {
+                spawn_record_receipts(client.clone(), pool.clone(), receipts);
+            }
+            result
+        }
+    }))
+    .await;
+
+    progress.finish(job.is_cancelled());
+
+    // every client buys independently, a failing one is only reported
+    let mut failures = String::new();
+    let mut failed_clients = 0;
+    let mut summaries = vec![];
+    for (client, result) in clients.iter().zip(results) {
+        let phone_number = client.phone_number();
+        match result {
+            Ok(spend) => {
+                tracing::info!(
+                    phone_number,
+                    bought = spend.units(),
+                    stars = spend.stars(),
+                    "client finished buying"
+                );
+                if !spend.gifts.is_empty() {
+                    summaries.push((client.display_name().to_string(), spend));
+                }
+            }
+            Err(err) => {
+                tracing::error!(?err, phone_number, "client failed to buy gifts");
+                writeln!(failures, "{phone_number}: {err}").unwrap();
+                failed_clients += 1;
+            }
+        }
+    }
+    let (bought, failed) = progress.counts();
+
+    if !summaries.is_empty() {
+        let bot = bot.clone();
+        let pool = pool.clone();
+        PURCHASE_TASKS.spawn(async move {
+            for (account, spend) in summaries {
+                let text = |language: Language| spend.summary(language, &account);
+                if let Err(err) = alert_chats_localized(&bot, &pool, text).await {
+                    tracing::error!(?err, "failed to send buy summary");
+                }
+            }
+        });
+    }
+
+    if !failures.is_empty() {
+        PURCHASE_TASKS.spawn(async move {
+            let text = format!("❌ Some accounts failed to buy gifts:\n{failures}");
+            alert_chats(&bot, &pool, &text)
+                .await
+                .inspect_err(|err| tracing::error!(?err, "failed to report buy failures"))
+        });
+    }
+
+    Ok(BuyOutcome {
+        bought,
+        failed: failed + failed_clients,
+    })
+}
+
+// what an account bought in one `buy_gifts` run
+#[derive(Debug, Default)]
+struct AccountSpend {
+    // units and stars spent by gift ID
+    gifts: BTreeMap<i64, (u64, i64)>,
+    // the tracked balance after the last purchase
+    balance: i64,
+}
+
+impl AccountSpend {
+    fn record(&mut self, gift_id: i64, stars: i64, balance: i64) {
+        let (units, spent) = self.gifts.entry(gift_id).or_default();
+        *units += 1;
+        *spent += stars;
+        self.balance = balance;
+    }
+
+    fn units(&self) -> u64 {
+        self.gifts.values().map(|&(units, _)| units).sum()
+    }
+
+    fn units_of(&self, gift_id: i64) -> u64 {
+        self.gifts.get(&gift_id).map_or(0, |&(units, _)| units)
+    }
+
+    fn stars(&self) -> i64 {
+        self.gifts.values().map(|&(_, stars)| stars).sum()
+    }
+
+    // `account` as `TelegramApi::display_name` names it
+    fn summary(&self, language: Language, account: &str) -> String {
+        let mut summary = language.account_bought(account, self.units(), self.stars());
+        summary.push('\n');
+        for (gift_id, (units, stars)) in &self.gifts {
+            writeln!(summary, "{gift_id}: {units} × {} ⭐", stars / *units as i64).unwrap();
+        }
+        write!(
+            summary,
+            "{}: {} ⭐",
+            language.text(Text::Balance),
+            self.balance
+        )
+        .unwrap();
+
+        summary
+    }
+}
+
+// failed counts the failed purchases and the accounts that failed to buy altogether
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuyOutcome {
+    pub bought: u64,
+    pub failed: u64,
+}
+
+// caps on the stars spent per day, a safety net against rules misbehaving all day long,
+// read from the config keys of the same names by every command that buys
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct DailySpendLimits {
+    #[serde(rename = "daily_spend_limit")]
+    pub per_account: Option<i64>,
+    #[serde(rename = "daily_fleet_spend_limit")]
+    pub fleet: Option<i64>,
+    // hour the spend resets at in `timezone`
+    #[serde(rename = "daily_reset_hour")]
+    pub reset_hour: u32,
+    pub timezone: Tz,
+}
+
+impl Default for DailySpendLimits {
+    fn default() -> Self {
+        Self {
+            per_account: None,
+            fleet: None,
+            reset_hour: 0,
+            timezone: Tz::UTC,
+        }
+    }
+}
+
+impl DailySpendLimits {
+    fn is_enabled(&self) -> bool {
+        self.per_account.is_some() || self.fleet.is_some()
+    }
+
+    // unix millis of the last reset at or before `now`, a reset hour skipped by a DST
+    // change resets an hour later
+    fn period_start(&self, now: i64) -> i64 {
+        let now = Utc
+            .timestamp_millis_opt(now)
+            .unwrap()
+            .with_timezone(&self.timezone);
+        let reset_on = |date: NaiveDate| {
+            (self.reset_hour..self.reset_hour + 2)
+                .filter_map(|hour| date.and_hms_opt(hour, 0, 0))
+                .find_map(|reset| self.timezone.from_local_datetime(&reset).earliest())
+                .map_or(i64::MIN, |reset| reset.timestamp_millis())
+        };
+
+        let today = now.date_naive();
+        let reset = reset_on(today);
+        if reset <= now.timestamp_millis() {
+            reset
+        } else {
+            today.pred_opt().map_or(i64::MIN, reset_on)
+        }
+    }
+}
+
+// stars spent since the last reset, the recorded purchases of every process are read again
+// before each job, the purchases of this process not recorded yet are tracked on top
+#[derive(Default)]
+struct DailySpend {
+    // 0 until loaded
+    period_start: i64,
+    // by phone number, as last read from the purchases
+    recorded: HashMap<String, i64>,
+    // by phone number, reserved by the jobs of this process and not recorded yet
+    unrecorded: HashMap<String, i64>,
+    // phone numbers, and "" for the fleet, already alerted about in this period
+    alerted: HashSet<String>,
+}
+
+impl DailySpend {
+    fn account(&self, phone_number: &str) -> i64 {
+        self.recorded.get(phone_number).copied().unwrap_or_default()
+            + self
+                .unrecorded
+                .get(phone_number)
+                .copied()
+                .unwrap_or_default()
+    }
+
+    fn fleet(&self) -> i64 {
+        self.recorded.values().sum::<i64>() + self.unrecorded.values().sum::<i64>()
+    }
+
+    // purchases still unrecorded at a reset count towards the new period until recorded,
+    // those sent before it drop out then
+    fn roll_over(&mut self, period_start: i64) {
+        if self.period_start != period_start {
+            self.period_start = period_start;
+            self.recorded.clear();
+            self.alerted.clear();
+        }
+    }
+}
+
+// a daily cap `reserve_spend` stopped an account at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpendCapReached {
+    // false for the fleet cap
+    per_account: bool,
+    spent: i64,
+    limit: i64,
+}
+
+// shared by every path that buys gifts (the poll loop, Buy buttons), so concurrent jobs
+// for the same gift share one per-account limit instead of each buying up to it
+#[derive(Default)]
+pub struct PurchaseCoordinator {
+    gifts: Mutex<HashMap<i64, GiftPurchases>>,
+    daily_limits: DailySpendLimits,
+    daily_spend: Mutex<DailySpend>,
+    // whether the jobs' progress messages get a Cancel button, only a process running the
+    // bot's update handler (`start`) can act on them
+    cancel_buttons: bool,
+    // parent of every running job's token, replaced after `cancel_all`
+    cancel: Mutex<CancellationToken>,
+    // tokens of the running jobs by job ID, for cancelling a single job
+    jobs: Mutex<HashMap<u64, CancellationToken>>,
+    next_job_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct GiftPurchases {
+    // running jobs for the gift, the counts are dropped once the last one finishes
+    jobs: usize,
+    // bought and in-flight units by phone number
+    units: HashMap<String, u64>,
+    // units of `units` whose purchases haven't finished yet
+    in_flight: u64,
+    // units accounts couldn't buy over the gift's per-user limit, left to other accounts
+    handed_over: u64,
+}
+
+impl PurchaseCoordinator {
+    pub fn with_daily_limits(daily_limits: DailySpendLimits) -> Self {
+        Self {
+            daily_limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_cancel_buttons(self) -> Self {
+        Self {
+            cancel_buttons: true,
+            ..self
+        }
+    }
+
+    // reads what was spent since the last reset, including the purchases of other
+    // processes sharing the database (workers, other instances), so the caps hold across
+    // them and restarts don't reset them
+    pub async fn load_daily_spend(&self, pool: &SqlitePool) -> Result<()> {
+        if !self.daily_limits.is_enabled() {
+            return Ok(());
+        }
+        let period_start = self
+            .daily_limits
+            .period_start(to_unix_millis(SystemTime::now()));
+
+        let recorded = get_spent_by_account(pool, period_start)
+            .await?
+            .into_iter()
+            .collect();
+        let mut daily_spend = self.daily_spend.lock().unwrap();
+        daily_spend.roll_over(period_start);
+        daily_spend.recorded = recorded;
+
+        Ok(())
+    }
+
+    // moves the stars of a successful purchase from the unrecorded spend to the recorded
+    // one once its row was written
+    fn record_spend(&self, phone_number: &str, stars: i64) {
+        if !self.daily_limits.is_enabled() {
+            return;
+        }
+        let mut daily_spend = self.daily_spend.lock().unwrap();
+        if let Some(unrecorded) = daily_spend.unrecorded.get_mut(phone_number) {
+            *unrecorded = (*unrecorded - stars).max(0);
+        }
+        *daily_spend
+            .recorded
+            .entry(phone_number.to_string())
+            .or_default() += stars;
+    }
+
+    pub fn join(self: &Arc<Self>, gift_ids: &[i64]) -> PurchaseJob {
+        let mut gifts = self.gifts.lock().unwrap();
+        for &gift_id in gift_ids {
+            gifts.entry(gift_id).or_default().jobs += 1;
+        }
+
+        let id = self.next_job_id.fetch_add(1, Ordering::Relaxed);
+        let cancel = self.cancel.lock().unwrap().child_token();
+        self.jobs.lock().unwrap().insert(id, cancel.clone());
+
+        PurchaseJob {
+            coordinator: self.clone(),
+            id,
+            gift_ids: gift_ids.to_vec(),
+            cancel,
+        }
+    }
+
+    // stops the job like `cancel_all`, false if it isn't running
+    pub fn cancel(&self, job_id: u64) -> bool {
+        match self.jobs.lock().unwrap().get(&job_id) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // stops every running job before its next unit, units already being sent aren't
+    // interrupted since they may be charged anyway, false if nothing was running
+    pub fn cancel_all(&self) -> bool {
+        let is_running = !self.gifts.lock().unwrap().is_empty();
+        std::mem::take(&mut *self.cancel.lock().unwrap()).cancel();
+        is_running
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reservation {
+    Claimed,
+    // the limit is reached
+    Full,
+    // the fleet limit is reached, but some of the units are still in flight
+    Pending,
+}
+
+pub struct PurchaseJob {
+    coordinator: Arc<PurchaseCoordinator>,
+    id: u64,
+    gift_ids: Vec<i64>,
+    cancel: CancellationToken,
+}
+
+impl PurchaseJob {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    // claims one unit of `gift_id` for the account, until `limit` units of the account or
+    // `fleet_limit` units of all accounts are bought or in flight across all jobs
+    fn reserve(
+        &self,
+        gift_id: i64,
+        phone_number: &str,
+        limit: u64,
+        fleet_limit: u64,
+    ) -> Reservation {
+        let mut gifts = self.coordinator.gifts.lock().unwrap();
+        let gift = gifts.entry(gift_id).or_default();
+        if gift.units.values().sum::<u64>() >= fleet_limit {
+            return if gift.in_flight > 0 {
+                Reservation::Pending
+            } else {
+                Reservation::Full
+            };
+        }
+        let units = gift.units.entry(phone_number.to_string()).or_default();
+
+        if *units >= limit {
+            return Reservation::Full;
+        }
+        *units += 1;
+        gift.in_flight += 1;
+        Reservation::Claimed
+    }
+
+    // marks a unit claimed by `reserve` as bought
+    fn confirm(&self, gift_id: i64) {
+        if let Some(gift) = self.coordinator.gifts.lock().unwrap().get_mut(&gift_id) {
+            gift.in_flight = gift.in_flight.saturating_sub(1);
+        }
+    }
+
+    fn hand_over(&self, gift_id: i64, units: u64) {
+        if units > 0 {
+            let mut gifts = self.coordinator.gifts.lock().unwrap();
+            gifts.entry(gift_id).or_default().handed_over += units;
+        }
+    }
+
+    fn has_handed_over(&self, gift_id: i64) -> bool {
+        self.coordinator
+            .gifts
+            .lock()
+            .unwrap()
+            .get(&gift_id)
+            .is_some_and(|gift| gift.handed_over > 0)
+    }
+
+    // claims one unit handed over by another account
+    fn take_handed_over(&self, gift_id: i64) -> bool {
+        let mut gifts = self.coordinator.gifts.lock().unwrap();
+        match gifts.get_mut(&gift_id) {
+            Some(gift) if gift.handed_over > 0 => {
+                gift.handed_over -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // claims `stars` of the account's daily spend, `Err` when that would go over a daily
+    // cap, with the cap to alert about the first time it's hit in the period
+    fn reserve_spend(&self, phone_number: &str, stars: i64) -> Result<(), Option<SpendCapReached>> {
+        let limits = self.coordinator.daily_limits;
+        if !limits.is_enabled() {
+            return Ok(());
+        }
+
+        let mut daily_spend = self.coordinator.daily_spend.lock().unwrap();
+        let period_start = limits.period_start(to_unix_millis(SystemTime::now()));
+        if daily_spend.period_start < period_start {
+            daily_spend.roll_over(period_start);
+        }
+
+        let account_spent = daily_spend.account(phone_number);
+        let fleet_spent = daily_spend.fleet();
+        let capped = if let Some(limit) = limits.per_account
+            && account_spent + stars > limit
+        {
+            Some((
+                phone_number,
+                SpendCapReached {
+                    per_account: true,
+                    spent: account_spent,
+                    limit,
+                },
+            ))
+        } else if let Some(limit) = limits.fleet
+            && fleet_spent + stars > limit
+        {
+            Some((
+                "",
+                SpendCapReached {
+                    per_account: false,
+                    spent: fleet_spent,
+                    limit,
+                },
+            ))
+        } else {
+            None
+        };
+
+        match capped {
+            Some((key, cap)) => Err(daily_spend.alerted.insert(key.to_string()).then_some(cap)),
+            None => {
+                *daily_spend
+                    .unrecorded
+                    .entry(phone_number.to_string())
+                    .or_default() += stars;
+                Ok(())
+            }
+        }
+    }
+
+    // gives back stars claimed by `reserve_spend` for a purchase that failed
+    fn release_spend(&self, phone_number: &str, stars: i64) {
+        if !self.coordinator.daily_limits.is_enabled() {
+            return;
+        }
+        let mut daily_spend = self.coordinator.daily_spend.lock().unwrap();
+        if let Some(unrecorded) = daily_spend.unrecorded.get_mut(phone_number) {
+            *unrecorded = (*unrecorded - stars).max(0);
+        }
+    }
+
+    // gives back a unit claimed by `reserve` whose purchase failed
+    fn release(&self, gift_id: i64, phone_number: &str) {
+        let mut gifts = self.coordinator.gifts.lock().unwrap();
+        if let Some(gift) = gifts.get_mut(&gift_id) {
+            gift.in_flight = gift.in_flight.saturating_sub(1);
+            if let Some(units) = gift.units.get_mut(phone_number) {
+                *units = units.saturating_sub(1);
+            }
+        }
+    }
+}
+
+impl Drop for PurchaseJob {
+    fn drop(&mut self) {
+        self.coordinator.jobs.lock().unwrap().remove(&self.id);
+
+        let mut gifts = self.coordinator.gifts.lock().unwrap();
+        for gift_id in &self.gift_ids {
+            if let Some(gift) = gifts.get_mut(gift_id) {
+                gift.jobs -= 1;
+                if gift.jobs == 0 {
+                    gifts.remove(gift_id);
+                }
+            }
+        }
+    }
+}
+
+// per-user limits are counted per account, so only the total is known here, the remains
+// of the gift object belong to the account that fetched it
+pub fn per_user_cap(gift: &types::StarGift) -> u64 {
+    match gift.per_user_total {
+        Some(per_user_total) if gift.limited_per_user => per_user_total.max(0) as u64,
+        _ => u64::MAX,
+    }
+}
+
+// the stars a unit of `gift` costs with `options`, the upgrade of gifts that can't be
+// upgraded costs nothing
+fn unit_price(gift: &types::StarGift, options: &GiftOptions) -> i64 {
+    let upgrade_stars = match options.include_upgrade {
+        true => gift.upgrade_stars.unwrap_or_default(),
+        false => 0,
+    };
+    gift.stars + upgrade_stars
+}
+
+// stars of every client, `None` when it can't be fetched
+async fn get_balances<C: TelegramApi>(clients: &[&Arc<C>]) -> Vec<Option<i64>> {
+    join_all(clients.iter().map(|client| async move {
+        match client
+            .invoke(&GetStarsStatus {
+                peer: InputPeer::PeerSelf,
+            })
+            .await
+        {
+            Ok(StarsStatus::Status(status)) => {
+                let StarsAmount::Amount(amount) = status.balance;
+                Some(amount.amount)
+            }
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    phone_number = client.phone_number(),
+                    "failed to get stars balance"
+                );
+                None
+            }
+        }
+    }))
+    .await
+}
+
+// `total` split in proportion to `balances`, the units left by rounding down go to the
+// largest remainders
+fn proportional_shares(total: u64, balances: &[i64]) -> Vec<u64> {
+    let balances: Vec<u128> = balances
+        .iter()
+        .map(|&balance| balance.max(0) as u128)
+        .collect();
+    let sum: u128 = balances.iter().sum();
+    if sum == 0 {
+        return vec![0; balances.len()];
+    }
+
+    let mut shares: Vec<u64> = balances
+        .iter()
+        .map(|balance| (u128::from(total) * balance / sum) as u64)
+        .collect();
+
+    let mut by_remainder: Vec<usize> = (0..balances.len()).collect();
+    by_remainder.sort_by_key(|&i| std::cmp::Reverse(u128::from(total) * balances[i] % sum));
+
+    let left = total - shares.iter().sum::<u64>();
+    for &i in by_remainder.iter().take(left as usize) {
+        shares[i] += 1;
+    }
+
+    shares
+}
+
+// the account already holds as many units as one buyer may, so every further attempt
+// would fail the same way
+fn is_per_user_limit_reached(status: &GiftBuyStatus) -> bool {
+    match status {
+        GiftBuyStatus::PaymentFormError(InvocationError::Rpc(err))
+        | GiftBuyStatus::SendStarsFormError(InvocationError::Rpc(err)) => {
+            err.name == "STARGIFT_USER_USAGE_LIMITED"
+        }
+        _ => false,
+    }
 }
 
-pub type Result<T, E = Error> = std::result::Result<T, E>;
+// after the account hit the per-user limit at unit `count`, the failed unit and the rest
+// of its own `limit` are left to other accounts
+fn hand_over_remaining(job: &PurchaseJob, gift_id: i64, count: u64, limit: u64) {
+    let remaining = if count <= limit { limit - count + 1 } else { 1 };
+    job.hand_over(gift_id, remaining);
+}
 
-#[derive(Debug, Clone)]
-pub enum BuyGiftsDestination {
-    PeerSelf,
-    Channel(MaybeResolvedChannel),
+fn spawn_record_purchase(pool: Arc<SqlitePool>, purchase: NewPurchase) {
+    PURCHASE_TASKS.spawn(async move {
+        insert_purchase(&*pool, &purchase)
+            .await
+            .inspect_err(|err| tracing::error!(?err, ?purchase, "failed to record purchase"))
+    });
 }
 
-// expects `gift_ids` to be sorted by priority
-pub async fn buy_gifts(
-    clients: &[Arc<WrappedClient>],
-    bot: Arc<Bot>,
+// counts the stars towards the daily spend once recorded, the transaction is added by
+// `spawn_record_receipts`
+fn spawn_record_bought(
     pool: Arc<SqlitePool>,
-    gift_ids: Vec<i64>,
-    gift_prices_map: Option<&BTreeMap<i64, i64>>,
-    limit: Option<u64>,
-    dest: &BuyGiftsDestination,
-) -> Result<()> {
-    let limit = limit.unwrap_or(100);
+    coordinator: Arc<PurchaseCoordinator>,
+    purchase: NewPurchase,
+) -> JoinHandle<db::Result<()>> {
+    PURCHASE_TASKS.spawn(async move {
+        let result = insert_purchase(&*pool, &purchase)
+            .await
+            .inspect_err(|err| tracing::error!(?err, ?purchase, "failed to record purchase"));
+        if result.is_ok() {
+            coordinator.record_spend(&purchase.phone_number, purchase.stars);
+        }
+        result
+    })
+}
+
+// a unit bought in a run, matched with its stars transaction once the account is done
+#[derive(Debug)]
+struct Receipt {
+    gift_id: i64,
+    stars: i64,
+    form_id: i64,
+    msg_id: Option<i32>,
+}
 
-    let first_client = clients.first().expect("expected at least one client");
+// looks up the stars transactions of every unit an account bought with one request, so
+// they can be reconciled with GetStarsTransactions later, each receipt comes with the
+// insert of its purchase, the transaction is added to the recorded row
+fn spawn_record_receipts<C: TelegramApi + 'static>(
+    client: Arc<C>,
+    pool: Arc<SqlitePool>,
+    receipts: Vec<(Receipt, JoinHandle<db::Result<()>>)>,
+) {
+    PURCHASE_TASKS.spawn(async move {
+        let phone_number = client.phone_number();
+        let limit = (receipts.len() as i32)
+            .saturating_mul(2)
+            .clamp(MIN_RECEIPT_TRANSACTIONS, MAX_RECEIPT_TRANSACTIONS);
+        let transactions = match get_gift_transactions(&*client, limit).await {
+            Ok(t) => t,
+            Err(err) => {
+                tracing::error!(?err, phone_number, "failed to get gift transactions");
+                return;
+            }
+        };
+        let (receipts, recorded): (Vec<_>, Vec<_>) = receipts.into_iter().unzip();
+        let transaction_ids = match_receipts(&receipts, &transactions);
 
-    let _dest_peer = match dest {
-        BuyGiftsDestination::PeerSelf => InputPeer::PeerSelf,
-        BuyGiftsDestination::Channel(channel) => {
-            InputPeer::Channel(channel.resolve(first_client).await?)
+        for ((receipt, recorded), transaction_id) in
+            receipts.iter().zip(recorded).zip(transaction_ids)
+        {
+            let Some(transaction_id) = transaction_id else {
+                tracing::warn!(
+                    phone_number,
+                    gift_id = receipt.gift_id,
+                    form_id = receipt.form_id,
+                    "gift transaction not found"
+                );
+                continue;
+            };
+            if !matches!(recorded.await, Ok(Ok(()))) {
+                continue;
+            }
+            if let Err(err) =
+                set_purchase_transaction_id(&*pool, phone_number, receipt.form_id, transaction_id)
+                    .await
+            {
+                tracing::error!(?err, phone_number, "failed to record gift transaction");
+            }
         }
-    };
+    });
+}
 
-    let gift_ids: Arc<[_]> = gift_ids.into();
-    let gift_prices = get_gift_prices(first_client, &gift_ids, gift_prices_map).await?;
+fn spawn_unsave_gift<C: TelegramApi + 'static>(client: Arc<C>, msg_id: i32) {
+    PURCHASE_TASKS.spawn(async move {
+        set_gift_saved(&*client, msg_id, false)
+            .await
+            .inspect_err(|err| tracing::error!(?err, msg_id, "failed to unsave gift"))
+    });
+}
+
+// shows (`saved`) or hides an owned gift (`msg_id` of its service message) on the profile
+pub async fn set_gift_saved<C: TelegramApi>(client: &C, msg_id: i32, saved: bool) -> Result<()> {
+    client
+        .invoke(&SaveStarGift {
+            unsave: !saved,
+            stargift: InputSavedStarGift::User(InputSavedStarGiftUser { msg_id }),
+        })
+        .await?;
+    Ok(())
+}
 
-    tracing::debug!(?gift_ids, ?gift_prices, "buy_gifts");
+// a form expired since it was fetched (prefetched ones may wait a while) is fetched again
+// and sent once more instead of failing the unit, returns the id of the form sent last
+async fn send_stars_form<C: TelegramApi>(
+    client: &C,
+    form_id: i64,
+    invoice: InputInvoice,
+) -> (Result<PaymentResult, InvocationError>, i64) {
+    let result = client
+        .invoke(&SendStarsForm {
+            form_id,
+            invoice: invoice.clone(),
+        })
+        .await;
 
-    let results = join_all(clients.iter().map(|client| {
-        let bot = bot.clone();
-        let pool = pool.clone();
-        let gift_ids = gift_ids.clone();
-        let gift_prices = gift_prices.clone();
-        // let dest_peer = dest_peer.clone();
+    match result {
+        Err(InvocationError::Rpc(err)) if err.name == "FORM_EXPIRED" => {
+            tracing::warn!(
+                form_id,
+                phone_number = client.phone_number(),
+                "payment form expired, fetching it again"
+            );
 
-        async move {
-            let StarsStatus::Status(status) = client
-                .invoke(&GetStarsStatus {
-                    peer: InputPeer::PeerSelf,
+            let payment_form = match client
+                .invoke(&GetPaymentForm {
+                    invoice: invoice.clone(),
+                    theme_params: None,
                 })
-                .await?;
-            tracing::debug!(?status, phone_number = client.phone_number());
+                .await
+            {
+                Ok(t) => t,
+                Err(err) => return (Err(err), form_id),
+            };
+            let form_id = payment_form.form_id();
 
-            let StarsAmount::Amount(mut stars_amount) = status.balance;
+            let result = client.invoke(&SendStarsForm { form_id, invoice }).await;
+            (result, form_id)
+        }
+        result => (result, form_id),
+    }
+}
 
-            for (&gift_id, &gift_price) in gift_ids.iter().zip(gift_prices.iter()) {
-                for count in 1..=limit {
-                    if stars_amount.amount < gift_price {
-                        break;
-                    }
+// the service message announcing the gift, part of the SendStarsForm updates
+fn gift_message_id(result: &PaymentResult, gift_id: i64) -> Option<i32> {
+    gift_service_message_id(result, gift_id, false)
+}
 
-                    let phone_number = client.phone_number().to_string();
-
-                    // let span = tracing::info_span!(
-                    //     "buy_gift",
-                    //     gift_id,
-                    //     count,
-                    //     phone_number = client.phone_number(),
-                    // );
-                    // let _guard = span.enter();
-
-                    let invoice = InputInvoice::StarGift(InputInvoiceStarGift {
-                        hide_name: false,
-                        include_upgrade: false,
-                        // peer: InputPeer::Channel(dest_peer.clone()), // TODO: channel
-                        peer: InputPeer::PeerSelf,
-                        gift_id,
-                        message: None,
-                    });
-
-                    let get_payment_form_result = client
-                        .invoke(&GetPaymentForm {
-                            invoice: invoice.clone(),
-                            theme_params: None,
-                        })
-                        .await;
-                    tracing::debug!(?get_payment_form_result);
-
-                    let payment_form = match get_payment_form_result {
-                        Ok(t) => t,
-                        Err(err) => {
-                            tracing::error!(?err, "failed to get payment form");
-                            tokio::spawn(
-                                notify_gift_buy_status(
-                                    bot.clone(),
-                                    pool.clone(),
-                                    count,
-                                    client.phone_number().to_string(),
-                                    stars_amount.amount,
-                                    gift_id,
-                                    GiftBuyStatus::PaymentFormError(err),
-                                )
-                                .inspect_err(move |err| {
-                                    tracing::error!(
-                                        ?err,
-                                        gift_id,
-                                        count,
-                                        phone_number,
-                                        "failed to notify gift buy status"
-                                    )
-                                }),
-                            );
-                            continue;
-                        }
-                    };
+// gifts bought to a channel are announced there with a post instead
+fn channel_gift_message_id(result: &PaymentResult, gift_id: i64) -> Option<i32> {
+    gift_service_message_id(result, gift_id, true)
+}
 
-                    let send_stars_form_result = client
-                        .invoke(&SendStarsForm {
-                            form_id: payment_form.form_id(),
-                            invoice,
-                        })
-                        .await;
-                    tracing::debug!(?send_stars_form_result);
-
-                    let status = match send_stars_form_result {
-                        Ok(_) => {
-                            stars_amount.amount -= gift_price;
-                            tracing::debug!(balance = stars_amount.amount, "success");
-                            GiftBuyStatus::Success
-                        }
-                        Err(err) => {
-                            tracing::error!(
-                                ?err,
-                                gift_id,
-                                count,
-                                phone_number,
-                                "failed to send stars form"
-                            );
-                            GiftBuyStatus::SendStarsFormError(err)
+fn gift_service_message_id(result: &PaymentResult, gift_id: i64, channel: bool) -> Option<i32> {
+    let PaymentResult::Result(result) = result else {
+        return None;
+    };
+
+    let updates = match &result.updates {
+        Updates::Updates(updates) => &updates.updates,
+        Updates::Combined(updates) => &updates.updates,
+        _ => return None,
+    };
+
+    updates.iter().find_map(|update| {
+        let message = match update {
+            Update::NewMessage(update) if !channel => &update.message,
+            Update::NewChannelMessage(update) if channel => &update.message,
+            _ => return None,
+        };
+        let Message::Service(message) = message else {
+            return None;
+        };
+        match &message.action {
+            MessageAction::StarGift(action)
+                if matches!(&action.gift, StarGift::Gift(gift) if gift.id == gift_id) =>
+            {
+                Some(message.id)
+            }
+            _ => None,
+        }
+    })
+}
+
+// an outgoing stars transaction paying for a gift
+#[derive(Debug)]
+struct GiftTransaction {
+    id: String,
+    gift_id: i64,
+    stars: i64,
+    msg_id: Option<i32>,
+}
+
+// only the most recent outgoing transactions are checked, the purchases have just been made
+async fn get_gift_transactions<C: TelegramApi>(
+    client: &C,
+    limit: i32,
+) -> Result<Vec<GiftTransaction>> {
+    let StarsStatus::Status(status) = client
+        .invoke(&GetStarsTransactions {
+            subscription_id: None,
+            inbound: false,
+            outbound: true,
+            ascending: false,
+            peer: InputPeer::PeerSelf,
+            offset: String::new(),
+            limit,
+        })
+        .await?;
+
+    Ok(status
+        .history
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|transaction| {
+            let StarsTransaction::Transaction(transaction) = transaction;
+            let Some(StarGift::Gift(gift)) = &transaction.stargift else {
+                return None;
+            };
+            let StarsAmount::Amount(stars) = transaction.stars;
+            Some(GiftTransaction {
+                gift_id: gift.id,
+                // outgoing amounts are negative
+                stars: stars.amount.abs(),
+                msg_id: transaction.msg_id,
+                id: transaction.id,
+            })
+        })
+        .collect())
+}
+
+// the transaction of each receipt, each transaction is used once, matched by the service
+// message of the gift where it's known and by the gift and its price otherwise
+fn match_receipts<'a>(
+    receipts: &[Receipt],
+    transactions: &'a [GiftTransaction],
+) -> Vec<Option<&'a str>> {
+    let mut used = vec![false; transactions.len()];
+    let mut matched = vec![None; receipts.len()];
+
+    // message IDs are exact, so those receipts pick first
+    for by_message in [true, false] {
+        for (receipt, matched) in receipts.iter().zip(&mut matched) {
+            if matched.is_some() || (by_message && receipt.msg_id.is_none()) {
+                continue;
+            }
+            let index = transactions
+                .iter()
+                .zip(&used)
+                .position(|(transaction, &used)| {
+                    !used
+                        && transaction.gift_id == receipt.gift_id
+                        && if by_message {
+                            transaction.msg_id == receipt.msg_id
+                        } else {
+                            transaction.stars == receipt.stars
                         }
-                    };
+                });
+            if let Some(index) = index {
+                used[index] = true;
+                *matched = Some(transactions[index].id.as_str());
+            }
+        }
+    }
 
-                    tokio::spawn(
-                        notify_gift_buy_status(
-                            bot.clone(),
-                            pool.clone(),
-                            count,
-                            client.phone_number().to_string(),
-                            stars_amount.amount,
-                            gift_id,
-                            status,
-                        )
-                        .inspect_err(move |err| {
-                            tracing::error!(
-                                ?err,
-                                gift_id,
-                                count,
-                                phone_number,
-                                "failed to notify gift buy status"
-                            )
-                        }),
-                    );
-                }
+    matched
+}
+
+// units per account bought of gifts with a total supply of at most `max_supply`,
+// `None` buys the default limit
+#[derive(Debug, Clone, Copy)]
+pub struct SupplyRule {
+    pub max_supply: i32,
+    pub limit: Option<u64>,
+    // units across all accounts, replaces `limit`
+    pub total: Option<u64>,
+}
+
+// supply bands, e.g. `3000:max,20000:2,50000:total:100`, the first matching rule applies,
+// gifts matching none are only notified about
+#[derive(Debug, Clone, Default)]
+pub struct SupplyRules(Vec<SupplyRule>);
+
+impl SupplyRules {
+    fn get(&self, supply: i32) -> Option<&SupplyRule> {
+        self.0.iter().find(|rule| supply <= rule.max_supply)
+    }
+}
+
+// comma-separated rules as MAX_SUPPLY:LIMIT or MAX_SUPPLY:total:TOTAL
+impl FromStr for SupplyRules {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let invalid = || Error::InvalidSupplyRule(rule.to_string());
+                let (max_supply, limit) = rule.split_once(':').ok_or_else(invalid)?;
+                let max_supply = max_supply.trim().parse().map_err(|_| invalid())?;
+                let (limit, total) = match limit.trim() {
+                    "max" => (None, None),
+                    limit => match limit.strip_prefix("total:") {
+                        Some(total) => (None, Some(total.trim().parse().map_err(|_| invalid())?)),
+                        None => (Some(limit.parse().map_err(|_| invalid())?), None),
+                    },
+                };
+                Ok(SupplyRule {
+                    max_supply,
+                    limit,
+                    total,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+// priority of the selected gifts, `buy_gifts` buys them in this order
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuyOrder {
+    // rarest first, the ones about to sell out ahead of them
+    #[default]
+    Supply,
+    // most units for the stars, for fleets short of stars
+    Price,
+    // fewest units left first
+    Scarcity,
+}
+
+impl BuyOrder {
+    // stable, so tied gifts keep their order
+    pub fn sort(self, gifts: &mut [types::StarGift], sell_out_etas: &HashMap<i64, Duration>) {
+        match self {
+            Self::Supply => {
+                gifts.sort_by_key(|gift| gift.availability_total);
+                gifts.sort_by_key(|gift| {
+                    sell_out_etas
+                        .get(&gift.id)
+                        .copied()
+                        .unwrap_or(Duration::MAX)
+                });
+            }
+            Self::Price => gifts.sort_by_key(|gift| gift.stars),
+            Self::Scarcity => {
+                gifts.sort_by_key(|gift| gift.availability_remains.unwrap_or(i32::MAX))
             }
+        }
+    }
+}
+
+// gifts matching a supply rule with their limits, rarest first, as `buy_gifts` expects them,
+// rules buying the default limit buy `default_limit`
+pub fn select_gifts_to_buy(
+    gifts: Vec<types::StarGift>,
+    rules: &SupplyRules,
+    default_limit: Option<u64>,
+) -> (Vec<types::StarGift>, BuyLimits) {
+    let mut limits = BuyLimits::from(default_limit);
+
+    let mut gifts: Vec<_> = gifts
+        .into_iter()
+        .filter(|gift| {
+            let Some(rule) = gift.availability_total.and_then(|supply| rules.get(supply)) else {
+                return false;
+            };
+            if let Some(total) = rule.total {
+                limits.totals.insert(gift.id, total);
+                return total > 0;
+            }
+            let limit = rule.limit.or(default_limit).unwrap_or(DEFAULT_BUY_LIMIT);
+            limits.per_gift.insert(gift.id, limit);
+            limit > 0
+        })
+        .collect();
+
+    gifts.sort_by_key(|gift| gift.availability_total);
+
+    (gifts, limits)
+}
+
+// kinds of catalog gifts `start` detects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GiftType {
+    // limited gifts still on sale
+    Limited,
+    Unlimited,
+    // sold out limited gifts listed on the resale market, only notified about
+    Resale,
+}
+
+impl GiftType {
+    pub fn matches(self, gift: &types::StarGift) -> bool {
+        match self {
+            Self::Limited => gift.limited && !gift.sold_out,
+            Self::Unlimited => !gift.limited,
+            Self::Resale => gift.sold_out && gift.resell_min_stars.is_some(),
+        }
+    }
+}
+
+impl FromStr for GiftType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "limited" => Ok(Self::Limited),
+            "unlimited" => Ok(Self::Unlimited),
+            "resale" => Ok(Self::Resale),
+            _ => Err(Error::InvalidGiftType(s.to_string())),
+        }
+    }
+}
+
+// criteria of detected gifts, `start` has one for notifications and one for purchases,
+// applied before the supply rules or the buy script, unset criteria match every gift
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GiftFilter {
+    pub limited_only: bool,
+    pub min_price: Option<i64>,
+    pub max_price: Option<i64>,
+    // unlimited gifts have no supply, so they never match a supply bound
+    pub min_supply: Option<i32>,
+    pub max_supply: Option<i32>,
+}
 
-            Result::<_, Error>::Ok(())
+impl GiftFilter {
+    pub fn matches(&self, gift: &types::StarGift) -> bool {
+        let supply_matches = |bound: Option<i32>, ok: fn(i32, i32) -> bool| {
+            bound.is_none_or(|bound| {
+                gift.availability_total
+                    .is_some_and(|supply| ok(supply, bound))
+            })
+        };
+
+        (!self.limited_only || gift.limited)
+            && self
+                .min_price
+                .is_none_or(|min_price| gift.stars >= min_price)
+            && self
+                .max_price
+                .is_none_or(|max_price| gift.stars <= max_price)
+            && supply_matches(self.min_supply, |supply, min| supply >= min)
+            && supply_matches(self.max_supply, |supply, max| supply <= max)
+    }
+}
+
+// buys a single gift outside of any job, e.g. to fulfill an order
+pub async fn send_gift(
+    client: &WrappedClient,
+    gift_id: i64,
+    peer: &InputPeer,
+    options: &GiftOptions,
+) -> Result<()> {
+    let invoice = gift_invoice(gift_id, peer, options);
+
+    let payment_form = client
+        .invoke(&GetPaymentForm {
+            invoice: invoice.clone(),
+            theme_params: None,
+        })
+        .await?;
+
+    client
+        .invoke(&SendStarsForm {
+            form_id: payment_form.form_id(),
+            invoice,
+        })
+        .await?;
+
+    Ok(())
+}
+
+// transfers an owned unique gift (`msg_id` of its service message), paying the transfer
+// fee when there is one
+pub async fn transfer_gift(client: &WrappedClient, msg_id: i32, peer: &InputPeer) -> Result<()> {
+    let stargift = InputSavedStarGift::User(InputSavedStarGiftUser { msg_id });
+    let invoice = InputInvoice::StarGiftTransfer(InputInvoiceStarGiftTransfer {
+        stargift: stargift.clone(),
+        to_id: peer.clone(),
+    });
+
+    let result = client
+        .invoke(&GetPaymentForm {
+            invoice: invoice.clone(),
+            theme_params: None,
+        })
+        .await;
+
+    match result {
+        Ok(payment_form) => {
+            client
+                .invoke(&SendStarsForm {
+                    form_id: payment_form.form_id(),
+                    invoice,
+                })
+                .await?;
+        }
+        Err(InvocationError::Rpc(err)) if err.name == "NO_PAYMENT_NEEDED" => {
+            client
+                .invoke(&TransferStarGift {
+                    stargift,
+                    to_id: peer.clone(),
+                })
+                .await?;
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(())
+}
+
+fn gift_invoice(gift_id: i64, peer: &InputPeer, options: &GiftOptions) -> InputInvoice {
+    InputInvoice::StarGift(InputInvoiceStarGift {
+        hide_name: options.hide_name,
+        include_upgrade: options.include_upgrade,
+        peer: peer.clone(),
+        gift_id,
+        message: options.message.as_ref().map(|message| {
+            TextWithEntities::Entities(types::TextWithEntities {
+                text: message.clone(),
+                entities: vec![],
+            })
+        }),
+    })
+}
+
+// fetched while the balance is requested, so the first purchase of each gift only needs
+// SendStarsForm, failures are retried (and reported) by the purchase itself
+async fn prefetch_payment_forms<C: TelegramApi>(
+    client: &C,
+    gift_ids: &[i64],
+    peer: &InputPeer,
+    options: &GiftOptions,
+) {
+    join_all(gift_ids.iter().map(|&gift_id| async move {
+        let invoice = gift_invoice(gift_id, peer, options);
+        let result = client
+            .invoke(&GetPaymentForm {
+                invoice: invoice.clone(),
+                theme_params: None,
+            })
+            .await;
+
+        match result {
+            Ok(payment_form) => client.put_payment_form(&invoice, payment_form),
+            Err(err) => tracing::warn!(
+                ?err,
+                gift_id,
+                phone_number = client.phone_number(),
+                "failed to prefetch payment form"
+            ),
         }
     }))
     .await;
+}
 
-    tracing::debug!(?results, "send_gifts");
+fn purchase_flood_wait<C: TelegramApi>(client: &C) -> Option<Duration> {
+    client
+        .flood_wait::<GetPaymentForm>()
+        .or_else(|| client.flood_wait::<SendStarsForm>())
+}
 
-    Ok(())
+// when a scheduled gift opens for purchase, None once it's open
+fn opening_time(gift: &types::StarGift) -> Option<SystemTime> {
+    let locked_until = u64::try_from(gift.locked_until_date?).ok()?;
+    let opens_at = SystemTime::UNIX_EPOCH + Duration::from_secs(locked_until);
+    (opens_at > SystemTime::now()).then_some(opens_at)
+}
+
+fn unavailable_reason(gift: &types::StarGift, is_premium: bool) -> Option<&'static str> {
+    let now = to_unix_millis(SystemTime::now()) / 1000;
+
+    if gift.require_premium && !is_premium {
+        Some("requires premium")
+    } else if gift
+        .locked_until_date
+        .is_some_and(|locked_until| i64::from(locked_until) > now)
+    {
+        Some("locked")
+    } else if gift.sold_out {
+        Some("sold out")
+    } else {
+        None
+    }
 }
 
-async fn get_gift_prices(
-    first_client: &WrappedClient,
+async fn get_gifts<C: TelegramApi>(
+    first_client: &C,
     gift_ids: &[i64],
-    gift_prices_map: Option<&BTreeMap<i64, i64>>,
-) -> Result<Arc<[i64]>> {
-    let gift_prices_map = match gift_prices_map {
+    gifts_map: Option<&BTreeMap<i64, types::StarGift>>,
+) -> Result<Arc<[types::StarGift]>> {
+    let gifts_map = match gifts_map {
         Some(t) => Cow::Borrowed(t),
         None => {
             let result = first_client.invoke(&GetStarGifts { hash: 0 }).await?;
@@ -229,7 +2177,7 @@ async fn get_gift_prices(
                     .gifts
                     .into_iter()
                     .filter_map(|gift| match gift {
-                        StarGift::Gift(gift) => Some((gift.id, gift.stars)),
+                        StarGift::Gift(gift) => Some((gift.id, gift)),
                         _ => None,
                     })
                     .collect(),
@@ -240,10 +2188,10 @@ async fn get_gift_prices(
     gift_ids
         .iter()
         .map(|gift_id| {
-            gift_prices_map
+            gifts_map
                 .get(gift_id)
-                .copied()
-                .ok_or(Error::GiftPriceNotFound(*gift_id))
+                .cloned()
+                .ok_or(Error::GiftNotFound(*gift_id))
         })
         .collect::<Result<Arc<[_]>, _>>()
 }
@@ -288,3 +2236,500 @@ impl MaybeResolvedChannel {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use grammers_client::grammers_tl_types::enums::{Document, Invoice, payments::PaymentForm};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::telegram_api::mock::{MockClient, rpc_error};
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, min: u32) -> i64 {
+        Utc.with_ymd_and_hms(year, month, day, hour, min, 0)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn limits(reset_hour: u32, timezone: Tz) -> DailySpendLimits {
+        DailySpendLimits {
+            reset_hour,
+            timezone,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn period_start_boundaries() {
+        let limits = limits(6, Tz::UTC);
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 10, 12, 0)),
+            utc(2026, 3, 10, 6, 0)
+        );
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 10, 6, 0)),
+            utc(2026, 3, 10, 6, 0)
+        );
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 10, 5, 59)),
+            utc(2026, 3, 9, 6, 0)
+        );
+        // across a month
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 1, 0, 0)),
+            utc(2026, 2, 28, 6, 0)
+        );
+    }
+
+    #[test]
+    fn period_start_in_timezone() {
+        let limits = limits(0, chrono_tz::Europe::Moscow);
+        // midnight in Moscow is 21:00 UTC the day before
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 10, 20, 59)),
+            utc(2026, 3, 9, 21, 0)
+        );
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 10, 21, 0)),
+            utc(2026, 3, 10, 21, 0)
+        );
+    }
+
+    #[test]
+    fn period_start_skipped_reset_hour() {
+        // 02:00 doesn't exist in Berlin on 2026-03-29, clocks jump to 03:00 CEST (01:00 UTC)
+        let limits = limits(2, chrono_tz::Europe::Berlin);
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 29, 1, 30)),
+            utc(2026, 3, 29, 1, 0)
+        );
+        // before the jump the period of the day before still runs, 02:00 CET
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 29, 0, 30)),
+            utc(2026, 3, 28, 1, 0)
+        );
+        // the day after resets at 02:00 CEST again
+        assert_eq!(
+            limits.period_start(utc(2026, 3, 30, 12, 0)),
+            utc(2026, 3, 30, 0, 0)
+        );
+    }
+
+    #[test]
+    fn period_start_repeated_reset_hour() {
+        // 02:00 happens twice in Berlin on 2026-10-25, the period starts at the first one
+        let limits = limits(2, chrono_tz::Europe::Berlin);
+        assert_eq!(
+            limits.period_start(utc(2026, 10, 25, 0, 30)),
+            utc(2026, 10, 25, 0, 0)
+        );
+        assert_eq!(
+            limits.period_start(utc(2026, 10, 25, 12, 0)),
+            utc(2026, 10, 25, 0, 0)
+        );
+    }
+
+    fn job(per_account: Option<i64>, fleet: Option<i64>) -> PurchaseJob {
+        Arc::new(PurchaseCoordinator::with_daily_limits(DailySpendLimits {
+            per_account,
+            fleet,
+            ..Default::default()
+        }))
+        .join(&[1])
+    }
+
+    #[test]
+    fn reserve_spend_per_account() {
+        let job = job(Some(100), None);
+        assert_eq!(job.reserve_spend("a", 60), Ok(()));
+        assert_eq!(
+            job.reserve_spend("a", 60),
+            Err(Some(SpendCapReached {
+                per_account: true,
+                spent: 60,
+                limit: 100,
+            }))
+        );
+        // alerted once per period
+        assert_eq!(job.reserve_spend("a", 60), Err(None));
+        // other accounts have their own cap
+        assert_eq!(job.reserve_spend("b", 100), Ok(()));
+
+        job.release_spend("a", 60);
+        assert_eq!(job.reserve_spend("a", 100), Ok(()));
+    }
+
+    #[test]
+    fn reserve_spend_fleet() {
+        let job = job(None, Some(100));
+        assert_eq!(job.reserve_spend("a", 60), Ok(()));
+        assert_eq!(
+            job.reserve_spend("b", 60),
+            Err(Some(SpendCapReached {
+                per_account: false,
+                spent: 60,
+                limit: 100,
+            }))
+        );
+        assert_eq!(job.reserve_spend("b", 40), Ok(()));
+    }
+
+    #[test]
+    fn recorded_spend_is_not_released() {
+        let job = job(Some(100), None);
+        assert_eq!(job.reserve_spend("a", 60), Ok(()));
+        job.coordinator.record_spend("a", 60);
+        // a stray release after the purchase was recorded gives nothing back
+        job.release_spend("a", 60);
+        assert!(job.reserve_spend("a", 60).is_err());
+        assert_eq!(job.reserve_spend("a", 40), Ok(()));
+    }
+
+    #[test]
+    fn reserve_spend_unlimited() {
+        let job = job(None, None);
+        assert_eq!(job.reserve_spend("a", i64::MAX), Ok(()));
+        assert_eq!(job.reserve_spend("a", i64::MAX), Ok(()));
+    }
+
+    #[test]
+    fn unit_price_includes_the_upgrade() {
+        let upgradable = types::StarGift {
+            upgrade_stars: Some(50),
+            ..gift(1, 100)
+        };
+        let upgrade = GiftOptions {
+            include_upgrade: true,
+            ..Default::default()
+        };
+        assert_eq!(unit_price(&upgradable, &upgrade), 150);
+        assert_eq!(unit_price(&upgradable, &GiftOptions::default()), 100);
+        assert_eq!(unit_price(&gift(2, 100), &upgrade), 100);
+    }
+
+    fn gift(id: i64, stars: i64) -> types::StarGift {
+        types::StarGift {
+            limited: false,
+            sold_out: false,
+            birthday: false,
+            require_premium: false,
+            limited_per_user: false,
+            peer_color_available: false,
+            id,
+            sticker: Document::Empty(types::DocumentEmpty { id: 0 }),
+            stars,
+            availability_remains: None,
+            availability_total: None,
+            availability_resale: None,
+            convert_stars: 0,
+            first_sale_date: None,
+            last_sale_date: None,
+            upgrade_stars: None,
+            resell_min_stars: None,
+            title: None,
+            released_by: None,
+            per_user_total: None,
+            per_user_remains: None,
+            locked_until_date: None,
+        }
+    }
+
+    fn stars_status(balance: i64) -> StarsStatus {
+        StarsStatus::Status(types::payments::StarsStatus {
+            balance: StarsAmount::Amount(types::StarsAmount {
+                amount: balance,
+                nanos: 0,
+            }),
+            subscriptions: None,
+            subscriptions_next_offset: None,
+            subscriptions_missing_balance: None,
+            history: None,
+            next_offset: None,
+            chats: vec![],
+            users: vec![],
+        })
+    }
+
+    fn payment_form() -> PaymentForm {
+        PaymentForm::StarGift(types::payments::PaymentFormStarGift {
+            form_id: 1,
+            invoice: Invoice::Invoice(types::Invoice {
+                test: false,
+                name_requested: false,
+                phone_requested: false,
+                email_requested: false,
+                shipping_address_requested: false,
+                flexible: false,
+                phone_to_provider: false,
+                email_to_provider: false,
+                recurring: false,
+                currency: "XTR".to_string(),
+                prices: vec![],
+                max_tip_amount: None,
+                suggested_tip_amounts: None,
+                terms_url: None,
+                subscription_period: None,
+            }),
+        })
+    }
+
+    // an account with `balance` stars whose purchases go through
+    fn account(phone_number: &str, balance: i64) -> MockClient {
+        MockClient::new(phone_number)
+            .on::<GetStarsStatus>(move || Ok(stars_status(balance)))
+            .on::<GetPaymentForm>(|| Ok(payment_form()))
+            .on::<SendStarsForm>(|| {
+                Ok(PaymentResult::Result(types::payments::PaymentResult {
+                    updates: Updates::TooLong,
+                }))
+            })
+    }
+
+    async fn buy(
+        clients: &[Arc<MockClient>],
+        coordinator: PurchaseCoordinator,
+        limits: BuyLimits,
+        gifts: &[types::StarGift],
+    ) -> BuyOutcome {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        db::MIGRATOR.run(&pool).await.unwrap();
+
+        buy_gifts(
+            clients,
+            Arc::new(Bot::new("0:test")),
+            Arc::new(pool),
+            &Arc::new(coordinator),
+            gifts.iter().map(|gift| gift.id).collect(),
+            Some(&gifts.iter().map(|gift| (gift.id, gift.clone())).collect()),
+            &limits,
+            &BuyGiftsDestination::PeerSelf.into(),
+            &GiftOptions::default(),
+            SystemTime::now(),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn limit(units: u64) -> BuyLimits {
+        BuyLimits {
+            default: Some(units),
+            ..Default::default()
+        }
+    }
+
+    // (bought, failed, sends) of a single account with `balance` stars buying a 100 stars gift
+    async fn buy_alone(
+        balance: i64,
+        coordinator: PurchaseCoordinator,
+        limits: BuyLimits,
+    ) -> (u64, u64, usize) {
+        let client = Arc::new(account("a", balance));
+        let outcome = buy(&[client.clone()], coordinator, limits, &[gift(1, 100)]).await;
+        (
+            outcome.bought,
+            outcome.failed,
+            client.calls::<SendStarsForm>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn buys_up_to_the_limit() {
+        assert_eq!(
+            buy_alone(1000, Default::default(), limit(3)).await,
+            (3, 0, 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_when_the_balance_runs_out() {
+        assert_eq!(
+            buy_alone(250, Default::default(), limit(5)).await,
+            (2, 0, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_gifts_over_max_price() {
+        let limits = BuyLimits {
+            max_price: Some(50),
+            ..limit(1)
+        };
+        assert_eq!(buy_alone(1000, Default::default(), limits).await, (0, 0, 0));
+    }
+
+    #[test]
+    fn reserve_waits_for_units_in_flight() {
+        let job = Arc::new(PurchaseCoordinator::default()).join(&[1]);
+        assert_eq!(job.reserve(1, "a", 2, 2), Reservation::Claimed);
+        assert_eq!(job.reserve(1, "b", 2, 2), Reservation::Claimed);
+        assert_eq!(job.reserve(1, "b", 2, 2), Reservation::Pending);
+
+        job.release(1, "a");
+        assert_eq!(job.reserve(1, "b", 2, 2), Reservation::Claimed);
+        job.confirm(1);
+        job.confirm(1);
+        assert_eq!(job.reserve(1, "a", 2, 2), Reservation::Full);
+        // the account's own limit doesn't wait
+        assert_eq!(job.reserve(1, "b", 2, 3), Reservation::Full);
+    }
+
+    #[tokio::test]
+    async fn accounts_share_a_total() {
+        let clients = [Arc::new(account("a", 1000)), Arc::new(account("b", 1000))];
+        let limits = BuyLimits {
+            totals: HashMap::from([(1, 3)]),
+            ..Default::default()
+        };
+        let outcome = buy(&clients, Default::default(), limits, &[gift(1, 100)]).await;
+        assert_eq!((outcome.bought, outcome.failed), (3, 0));
+    }
+
+    #[tokio::test]
+    async fn failed_units_are_left_to_other_accounts() {
+        let clients = [
+            Arc::new(account("a", 1000).on::<SendStarsForm>(|| Err(rpc_error("INTERNAL")))),
+            Arc::new(account("b", 1000)),
+        ];
+        let limits = BuyLimits {
+            totals: HashMap::from([(1, 2)]),
+            ..Default::default()
+        };
+        let outcome = buy(&clients, Default::default(), limits, &[gift(1, 100)]).await;
+        assert_eq!(outcome.bought, 2);
+        assert!(outcome.failed > 0);
+        assert_eq!(clients[1].calls::<SendStarsForm>(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_daily_spend_limit() {
+        let coordinator = PurchaseCoordinator::with_daily_limits(DailySpendLimits {
+            per_account: Some(250),
+            ..Default::default()
+        });
+        assert_eq!(buy_alone(1000, coordinator, limit(5)).await, (2, 0, 2));
+    }
+
+    fn receipt(gift_id: i64, msg_id: Option<i32>) -> Receipt {
+        Receipt {
+            gift_id,
+            stars: 100,
+            form_id: 0,
+            msg_id,
+        }
+    }
+
+    fn transaction(id: &str, gift_id: i64, msg_id: Option<i32>) -> GiftTransaction {
+        GiftTransaction {
+            id: id.to_string(),
+            gift_id,
+            stars: 100,
+            msg_id,
+        }
+    }
+
+    #[test]
+    fn receipts_match_each_transaction_once() {
+        let receipts = [receipt(1, None), receipt(1, None), receipt(1, None)];
+        let transactions = [transaction("a", 1, None), transaction("b", 1, None)];
+        assert_eq!(
+            match_receipts(&receipts, &transactions),
+            [Some("a"), Some("b"), None]
+        );
+    }
+
+    #[test]
+    fn receipts_match_by_message_first() {
+        // the receipt without a message would take "b" if it went first
+        let receipts = [receipt(1, None), receipt(1, Some(7))];
+        let transactions = [transaction("b", 1, Some(7)), transaction("a", 1, Some(5))];
+        assert_eq!(
+            match_receipts(&receipts, &transactions),
+            [Some("a"), Some("b")]
+        );
+    }
+
+    #[test]
+    fn receipts_match_gift_and_price() {
+        let receipts = [receipt(1, None), receipt(2, None)];
+        let transactions = [
+            GiftTransaction {
+                stars: 50,
+                ..transaction("a", 2, None)
+            },
+            transaction("b", 2, None),
+        ];
+        assert_eq!(match_receipts(&receipts, &transactions), [None, Some("b")]);
+    }
+
+    #[test]
+    fn proportional_shares_by_balance() {
+        // the unit left by rounding down goes to the first of the tied remainders
+        assert_eq!(proportional_shares(10, &[1, 1, 2]), [3, 2, 5]);
+        assert_eq!(proportional_shares(4, &[-5, 100, 100]), [0, 2, 2]);
+        assert_eq!(proportional_shares(4, &[0, 0]), [0, 0]);
+        assert_eq!(proportional_shares(0, &[1, 2]), [0, 0]);
+
+        // accounts short of a unit leave their share to the others
+        let balances = [Some(50), Some(300), None];
+        let target = |account| limit(2).account_target(1, 100, account, Some(&balances));
+        assert_eq!([target(0), target(1), target(2)], [0, 6, 0]);
+    }
+
+    #[test]
+    fn destinations_rotate_by_weight() {
+        let dest: BuyDestinations = "self:2,@gifts".parse().unwrap();
+        let indices: Vec<_> = dest.rotation().take(6).collect();
+        assert_eq!(indices, [0, 1, 0, 0, 1, 0]);
+
+        let dest: BuyDestinations = "self".parse().unwrap();
+        assert!(dest.rotation().take(3).all(|i| i == 0));
+
+        assert!("self:0".parse::<BuyDestinations>().is_err());
+        assert!("self,".parse::<BuyDestinations>().is_err());
+    }
+
+    #[test]
+    fn supply_rules_parse() {
+        let rules: SupplyRules = "3000:max, 20000:2,50000:total:100".parse().unwrap();
+        let rule = |supply| rules.get(supply).map(|rule| (rule.limit, rule.total));
+        assert_eq!(rule(3000), Some((None, None)));
+        assert_eq!(rule(3001), Some((Some(2), None)));
+        assert_eq!(rule(50000), Some((None, Some(100))));
+        assert_eq!(rule(50001), None);
+
+        for invalid in ["3000", "max:3000", "3000:two", "3000:total:"] {
+            assert!(invalid.parse::<SupplyRules>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn selects_gifts_by_supply_rules() {
+        let limited = |id, supply| types::StarGift {
+            limited: true,
+            availability_total: Some(supply),
+            availability_remains: Some(supply),
+            ..gift(id, 100)
+        };
+        let rules: SupplyRules = "3000:max,20000:2,30000:0,50000:total:100".parse().unwrap();
+        let gifts = vec![
+            limited(1, 40000),
+            limited(2, 10000),
+            limited(3, 2000),
+            limited(4, 25000),
+            limited(5, 60000),
+            gift(6, 100),
+        ];
+
+        let (gifts, limits) = select_gifts_to_buy(gifts, &rules, Some(5));
+        let gift_ids: Vec<_> = gifts.iter().map(|gift| gift.id).collect();
+        assert_eq!(gift_ids, [3, 2, 1]);
+        assert_eq!(limits.get(3), 5);
+        assert_eq!(limits.get(2), 2);
+        assert_eq!(limits.total(1), Some(100));
+        assert_eq!(limits.total(2), None);
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+};
+
+use daemonize::Daemonize;
+use nix::{
+    sys::signal::{Signal, kill},
+    unistd::Pid,
+};
+use tokio::signal::unix::{SignalKind, signal};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Daemonize(#[from] daemonize::Error),
+    #[error(transparent)]
+    Nix(#[from] nix::Error),
+    #[error("invalid pid file (path = {0})")]
+    InvalidPidFile(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// must be called before the tokio runtime and tracing writer threads are started,
+// since only the calling thread survives fork
+pub fn daemonize(pid_file: &Path, log_file: &Path) -> Result<()> {
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let open_log = || OpenOptions::new().create(true).append(true).open(log_file);
+
+    Daemonize::new()
+        .pid_file(pid_file)
+        // keep relative paths (logs/, sqlite database, .env) working
+        .working_directory(std::env::current_dir()?)
+        .stdout(open_log()?)
+        .stderr(open_log()?)
+        .start()?;
+
+    Ok(())
+}
+
+pub fn stop(pid_file: &Path) -> Result<()> {
+    let pid: i32 = fs::read_to_string(pid_file)?
+        .trim()
+        .parse()
+        .map_err(|_| Error::InvalidPidFile(pid_file.display().to_string()))?;
+
+    // the daemon removes its pid file itself once it exits, see `terminated`
+    kill(Pid::from_raw(pid), Signal::SIGTERM)?;
+    tracing::info!(pid, "sent SIGTERM");
+
+    Ok(())
+}
+
+// resolves on SIGTERM, so the daemon can remove its pid file before exiting instead of
+// being killed
+pub async fn terminated() -> Result<()> {
+    signal(SignalKind::terminate())?.recv().await;
+    Ok(())
+}
@@ -1,5 +1,7 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use grammers_client::session::Session;
-use sqlx::SqliteExecutor;
+use sqlx::{SqliteExecutor, migrate::Migrator};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -11,16 +13,29 @@ pub enum Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub async fn get_applied_migrations<'a, E: SqliteExecutor<'a>>(executor: E) -> Result<Vec<i64>> {
+    Ok(sqlx::query_scalar(
+        "SELECT version FROM _sqlx_migrations WHERE success = TRUE ORDER BY version",
+    )
+    .fetch_all(executor)
+    .await?)
+}
+
 pub async fn insert_or_replace_session<'a, E: SqliteExecutor<'a>>(
     executor: E,
     phone_number: &str,
     session: &Session,
 ) -> Result<()> {
-    sqlx::query("INSERT OR REPLACE INTO sessions (phone_number, session) VALUES ($1, $2)")
-        .bind(phone_number)
-        .bind(session.save())
-        .execute(executor)
-        .await?;
+    sqlx::query(
+        "INSERT OR REPLACE INTO sessions (phone_number, session, updated_at) VALUES ($1, $2, $3)",
+    )
+    .bind(phone_number)
+    .bind(session.save())
+    .bind(to_unix_millis(SystemTime::now()))
+    .execute(executor)
+    .await?;
     Ok(())
 }
 
@@ -40,6 +55,48 @@ pub async fn get_session<'a, E: SqliteExecutor<'a>>(
     })
 }
 
+#[derive(sqlx::FromRow)]
+pub struct StoredSession {
+    pub phone_number: String,
+    pub session: Vec<u8>,
+    pub updated_at: Option<i64>,
+    pub deauthorized_at: Option<i64>,
+}
+
+pub async fn get_sessions<'a, E: SqliteExecutor<'a>>(executor: E) -> Result<Vec<StoredSession>> {
+    Ok(sqlx::query_as(
+        "SELECT phone_number, session, updated_at, deauthorized_at FROM sessions \
+        ORDER BY phone_number",
+    )
+    .fetch_all(executor)
+    .await?)
+}
+
+// cleared by `insert_or_replace_session` once the account logs in again
+pub async fn mark_session_deauthorized<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+    deauthorized_at: i64,
+) -> Result<()> {
+    sqlx::query("UPDATE sessions SET deauthorized_at = $1 WHERE phone_number = $2")
+        .bind(deauthorized_at)
+        .bind(phone_number)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_session<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+) -> Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE phone_number = $1")
+        .bind(phone_number)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
 pub async fn insert_chat<'a, E: SqliteExecutor<'a>>(executor: E, chat_id: i64) -> Result<()> {
     sqlx::query("INSERT INTO chats(chat_id) VALUES ($1)")
         .bind(chat_id)
@@ -54,6 +111,783 @@ pub async fn get_chats<'a, E: SqliteExecutor<'a>>(executor: E) -> Result<Vec<i64
         .await?)
 }
 
+// every chat with its language code, `None` when it was never chosen
+pub async fn get_chats_with_language<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+) -> Result<Vec<(i64, Option<String>)>> {
+    Ok(sqlx::query_as(
+        "SELECT chats.chat_id, chat_settings.language FROM chats \
+        LEFT JOIN chat_settings ON chat_settings.chat_id = chats.chat_id",
+    )
+    .fetch_all(executor)
+    .await?)
+}
+
+pub async fn get_chat_language<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    chat_id: i64,
+) -> Result<Option<String>> {
+    Ok(
+        sqlx::query_scalar("SELECT language FROM chat_settings WHERE chat_id = $1")
+            .bind(chat_id)
+            .fetch_optional(executor)
+            .await?,
+    )
+}
+
+pub async fn set_chat_language<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    chat_id: i64,
+    language: &str,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO chat_settings(chat_id, language) VALUES ($1, $2) \
+        ON CONFLICT(chat_id) DO UPDATE SET language = excluded.language",
+    )
+    .bind(chat_id)
+    .bind(language)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_admin_destination<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    username: &str,
+    destination: &str,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO admin_destinations(username, destination) VALUES ($1, $2) \
+        ON CONFLICT(username) DO UPDATE SET destination = excluded.destination",
+    )
+    .bind(username)
+    .bind(destination)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_admin_destination<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    username: &str,
+) -> Result<Option<String>> {
+    Ok(
+        sqlx::query_scalar("SELECT destination FROM admin_destinations WHERE username = $1")
+            .bind(username)
+            .fetch_optional(executor)
+            .await?,
+    )
+}
+
+pub async fn delete_admin_destination<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    username: &str,
+) -> Result<()> {
+    sqlx::query("DELETE FROM admin_destinations WHERE username = $1")
+        .bind(username)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_topup<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+    stars: i64,
+    attempted_at: i64,
+) -> Result<()> {
+    sqlx::query("INSERT INTO topups(phone_number, stars, attempted_at) VALUES ($1, $2, $3)")
+        .bind(phone_number)
+        .bind(stars)
+        .bind(attempted_at)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_last_topup_at<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+) -> Result<Option<i64>> {
+    Ok(
+        sqlx::query_scalar("SELECT MAX(attempted_at) FROM topups WHERE phone_number = $1")
+            .bind(phone_number)
+            .fetch_one(executor)
+            .await?,
+    )
+}
+
+// a /wizard purchase being put together by an admin
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct WizardState {
+    pub username: String,
+    // `wizard::Step`
+    pub step: String,
+    pub gift_id: Option<i64>,
+    pub quantity: Option<i64>,
+    pub destination: Option<String>,
+    pub updated_at: i64,
+}
+
+pub async fn set_wizard_state<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    wizard: &WizardState,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO wizard_states(username, step, gift_id, quantity, destination, updated_at) \
+        VALUES ($1, $2, $3, $4, $5, $6) \
+        ON CONFLICT(username) DO UPDATE SET step = excluded.step, gift_id = excluded.gift_id, \
+        quantity = excluded.quantity, destination = excluded.destination, \
+        updated_at = excluded.updated_at",
+    )
+    .bind(&wizard.username)
+    .bind(&wizard.step)
+    .bind(wizard.gift_id)
+    .bind(wizard.quantity)
+    .bind(&wizard.destination)
+    .bind(wizard.updated_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// states last updated before `updated_since` are left unfinished and not returned
+pub async fn get_wizard_state<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    username: &str,
+    updated_since: i64,
+) -> Result<Option<WizardState>> {
+    Ok(sqlx::query_as(
+        "SELECT username, step, gift_id, quantity, destination, updated_at FROM wizard_states \
+        WHERE username = $1 AND updated_at >= $2",
+    )
+    .bind(username)
+    .bind(updated_since)
+    .fetch_optional(executor)
+    .await?)
+}
+
+pub async fn delete_wizard_state<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    username: &str,
+) -> Result<()> {
+    sqlx::query("DELETE FROM wizard_states WHERE username = $1")
+        .bind(username)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub fn to_unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[derive(Debug)]
+pub struct NewPurchase {
+    pub phone_number: String,
+    pub gift_id: i64,
+    pub stars: i64,
+    pub status: &'static str,
+    pub error: Option<String>,
+    pub detected_at: i64,
+    pub payment_form_at: Option<i64>,
+    pub sent_at: Option<i64>,
+    pub form_id: Option<i64>,
+    // the service message of the bought gift
+    pub msg_id: Option<i32>,
+    pub transaction_id: Option<String>,
+    // bought to the buying account rather than to another recipient
+    pub to_self: bool,
+}
+
+pub async fn insert_purchase<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    purchase: &NewPurchase,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO purchases(phone_number, gift_id, stars, status, error, detected_at, payment_form_at, sent_at, form_id, msg_id, transaction_id, to_self) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+    )
+    .bind(&purchase.phone_number)
+    .bind(purchase.gift_id)
+    .bind(purchase.stars)
+    .bind(purchase.status)
+    .bind(&purchase.error)
+    .bind(purchase.detected_at)
+    .bind(purchase.payment_form_at)
+    .bind(purchase.sent_at)
+    .bind(purchase.form_id)
+    .bind(purchase.msg_id)
+    .bind(&purchase.transaction_id)
+    .bind(purchase.to_self)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// `form_id` identifies the purchase among the account's
+pub async fn set_purchase_transaction_id<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+    form_id: i64,
+    transaction_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE purchases SET transaction_id = $1 WHERE phone_number = $2 AND form_id = $3",
+    )
+    .bind(transaction_id)
+    .bind(phone_number)
+    .bind(form_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Purchase {
+    pub phone_number: String,
+    pub gift_id: i64,
+    pub stars: i64,
+    pub status: String,
+    pub error: Option<String>,
+    pub detected_at: i64,
+}
+
+// newest first
+pub async fn get_recent_purchases<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    limit: i64,
+) -> Result<Vec<Purchase>> {
+    Ok(sqlx::query_as(
+        "SELECT phone_number, gift_id, stars, status, error, detected_at FROM purchases \
+        ORDER BY id DESC LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(executor)
+    .await?)
+}
+
+// units of a gift one account attempted for one detection with the same result
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PurchaseSummary {
+    pub gift_id: i64,
+    pub phone_number: String,
+    pub status: String,
+    pub count: i64,
+    pub stars: i64,
+    pub detected_at: i64,
+}
+
+// newest first
+pub async fn get_purchase_history<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PurchaseSummary>> {
+    Ok(sqlx::query_as(
+        "SELECT gift_id, phone_number, status, COUNT(*) AS count, SUM(stars) AS stars, \
+        detected_at FROM purchases GROUP BY detected_at, gift_id, phone_number, status \
+        ORDER BY MAX(id) DESC LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(executor)
+    .await?)
+}
+
+// returns when the gift was first seen, which is `seen_at` unless it was recorded before
+pub async fn record_gift_seen<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift_id: i64,
+    seen_at: i64,
+) -> Result<i64> {
+    Ok(sqlx::query_scalar(
+        "INSERT INTO gifts_first_seen(gift_id, first_seen_at) VALUES ($1, $2) \
+        ON CONFLICT(gift_id) DO UPDATE SET first_seen_at = first_seen_at \
+        RETURNING first_seen_at",
+    )
+    .bind(gift_id)
+    .bind(seen_at)
+    .fetch_one(executor)
+    .await?)
+}
+
+pub async fn get_gifts_first_seen_ids<'a, E: SqliteExecutor<'a>>(executor: E) -> Result<Vec<i64>> {
+    Ok(sqlx::query_scalar("SELECT gift_id FROM gifts_first_seen")
+        .fetch_all(executor)
+        .await?)
+}
+
+pub async fn has_gifts_first_seen<'a, E: SqliteExecutor<'a>>(executor: E) -> Result<bool> {
+    Ok(
+        sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM gifts_first_seen)")
+            .fetch_one(executor)
+            .await?,
+    )
+}
+
+// records the gifts as first seen at `seen_at`, except those recorded before
+pub async fn insert_gifts_first_seen<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift_ids: &[i64],
+    seen_at: i64,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO gifts_first_seen(gift_id, first_seen_at) \
+        SELECT value, $2 FROM json_each($1) WHERE TRUE \
+        ON CONFLICT(gift_id) DO NOTHING",
+    )
+    .bind(serde_json::to_string(gift_ids).expect("gift IDs serialize"))
+    .bind(seen_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct NewStarsTransaction {
+    pub phone_number: String,
+    pub transaction_id: String,
+    pub stars: i64,
+    pub date: i64,
+    pub gift_id: Option<i64>,
+    pub msg_id: Option<i32>,
+    pub refund: bool,
+    pub pending: bool,
+    pub failed: bool,
+}
+
+// returns whether the transaction wasn't stored before
+pub async fn insert_stars_transaction<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    transaction: &NewStarsTransaction,
+) -> Result<bool> {
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO stars_transactions(phone_number, transaction_id, stars, date, gift_id, msg_id, refund, pending, failed) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(&transaction.phone_number)
+    .bind(&transaction.transaction_id)
+    .bind(transaction.stars)
+    .bind(transaction.date)
+    .bind(transaction.gift_id)
+    .bind(transaction.msg_id)
+    .bind(transaction.refund)
+    .bind(transaction.pending)
+    .bind(transaction.failed)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn has_stars_transactions<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+) -> Result<bool> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM stars_transactions WHERE phone_number = $1)",
+    )
+    .bind(phone_number)
+    .fetch_one(executor)
+    .await?)
+}
+
+pub async fn is_purchase_transaction<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    transaction_id: &str,
+) -> Result<bool> {
+    Ok(
+        sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM purchases WHERE transaction_id = $1)")
+            .bind(transaction_id)
+            .fetch_one(executor)
+            .await?,
+    )
+}
+
+#[derive(sqlx::FromRow)]
+pub struct GiftSnapshot {
+    pub taken_at: i64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct GiftObservation {
+    pub gift_id: i64,
+    pub observed_at: i64,
+    pub stars: i64,
+    pub availability_remains: Option<i32>,
+    pub sold_out: bool,
+}
+
+pub async fn insert_gift_observation<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    observation: &GiftObservation,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO gift_observations(gift_id, observed_at, stars, availability_remains, sold_out) \
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(observation.gift_id)
+    .bind(observation.observed_at)
+    .bind(observation.stars)
+    .bind(observation.availability_remains)
+    .bind(observation.sold_out)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// oldest first
+pub async fn get_gift_observations<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift_id: i64,
+    since: i64,
+) -> Result<Vec<GiftObservation>> {
+    Ok(sqlx::query_as(
+        "SELECT gift_id, observed_at, stars, availability_remains, sold_out FROM gift_observations \
+        WHERE gift_id = $1 AND observed_at >= $2 ORDER BY observed_at",
+    )
+    .bind(gift_id)
+    .bind(since)
+    .fetch_all(executor)
+    .await?)
+}
+
+pub async fn insert_gift_snapshot<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    taken_at: i64,
+    hash: i32,
+    data: &[u8],
+) -> Result<()> {
+    sqlx::query("INSERT INTO gift_snapshots(taken_at, hash, data) VALUES ($1, $2, $3)")
+        .bind(taken_at)
+        .bind(hash)
+        .bind(data)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_gift_snapshots<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    since: i64,
+) -> Result<Vec<GiftSnapshot>> {
+    Ok(sqlx::query_as(
+        "SELECT taken_at, data FROM gift_snapshots WHERE taken_at >= $1 ORDER BY taken_at",
+    )
+    .bind(since)
+    .fetch_all(executor)
+    .await?)
+}
+
+// whether `msg_id` is the service message of a gift bought by this bot
+pub async fn is_purchase_message<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+    msg_id: i32,
+) -> Result<bool> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM purchases \
+        WHERE phone_number = $1 AND msg_id = $2 AND status = 'success')",
+    )
+    .bind(phone_number)
+    .bind(msg_id)
+    .fetch_one(executor)
+    .await?)
+}
+
+// service messages of the gifts the account bought to itself, the gifts bought to others
+// are on their profiles
+pub async fn get_purchase_message_ids<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+) -> Result<Vec<i32>> {
+    Ok(sqlx::query_scalar(
+        "SELECT msg_id FROM purchases \
+        WHERE phone_number = $1 AND status = 'success' AND to_self = 1 AND msg_id IS NOT NULL",
+    )
+    .bind(phone_number)
+    .fetch_all(executor)
+    .await?)
+}
+
+#[derive(Debug)]
+pub struct NewUniqueGift {
+    pub unique_id: i64,
+    pub phone_number: String,
+    pub gift_id: i64,
+    pub msg_id: i32,
+    pub slug: String,
+    pub num: i32,
+    pub model: (String, i32),
+    pub pattern: (String, i32),
+    pub backdrop: (String, i32),
+    pub percentile: f64,
+    pub upgraded_at: i64,
+}
+
+pub async fn insert_unique_gift<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift: &NewUniqueGift,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO unique_gifts(unique_id, phone_number, gift_id, msg_id, slug, num, model, model_permille, pattern, pattern_permille, backdrop, backdrop_permille, percentile, upgraded_at) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+    )
+    .bind(gift.unique_id)
+    .bind(&gift.phone_number)
+    .bind(gift.gift_id)
+    .bind(gift.msg_id)
+    .bind(&gift.slug)
+    .bind(gift.num)
+    .bind(&gift.model.0)
+    .bind(gift.model.1)
+    .bind(&gift.pattern.0)
+    .bind(gift.pattern.1)
+    .bind(&gift.backdrop.0)
+    .bind(gift.backdrop.1)
+    .bind(gift.percentile)
+    .bind(gift.upgraded_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// of a unique gift upgraded by this bot, by the service message of the unique
+pub async fn get_unique_gift_percentile<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    phone_number: &str,
+    msg_id: i32,
+) -> Result<Option<f64>> {
+    Ok(sqlx::query_scalar(
+        "SELECT percentile FROM unique_gifts WHERE phone_number = $1 AND msg_id = $2",
+    )
+    .bind(phone_number)
+    .bind(msg_id)
+    .fetch_optional(executor)
+    .await?)
+}
+
+pub async fn insert_resale_floor<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift_id: i64,
+    sampled_at: i64,
+    floor_stars: Option<i64>,
+    listings: usize,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO resale_floors(gift_id, sampled_at, floor_stars, listings) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(gift_id)
+    .bind(sampled_at)
+    .bind(floor_stars)
+    .bind(listings as i64)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// takes the lease when it's free or expired, or renews it when `holder` has it already,
+// returns whether `holder` has it now
+pub async fn try_acquire_lease<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    name: &str,
+    holder: &str,
+    now: i64,
+    expires_at: i64,
+) -> Result<bool> {
+    let result = sqlx::query(
+        "INSERT INTO leases(name, holder, expires_at) VALUES ($1, $2, $3) \
+        ON CONFLICT(name) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at \
+        WHERE leases.holder = excluded.holder OR leases.expires_at < $4",
+    )
+    .bind(name)
+    .bind(holder)
+    .bind(expires_at)
+    .bind(now)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(sqlx::FromRow)]
+pub struct BuyJob {
+    pub id: i64,
+    pub created_at: i64,
+    pub detected_at: i64,
+    // TL-serialized vector of StarGift
+    pub gifts: Vec<u8>,
+    // JSON of BuyLimits
+    pub limits: String,
+}
+
+pub async fn insert_buy_job<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    created_at: i64,
+    detected_at: i64,
+    gifts: &[u8],
+    limits: &str,
+) -> Result<i64> {
+    Ok(sqlx::query_scalar(
+        "INSERT INTO buy_jobs(created_at, detected_at, gifts, limits) VALUES ($1, $2, $3, $4) \
+        RETURNING id",
+    )
+    .bind(created_at)
+    .bind(detected_at)
+    .bind(gifts)
+    .bind(limits)
+    .fetch_one(executor)
+    .await?)
+}
+
+// marks the oldest queued job as running and returns it, a single statement, so two
+// workers never claim the same job
+pub async fn claim_buy_job<'a, E: SqliteExecutor<'a>>(executor: E) -> Result<Option<BuyJob>> {
+    Ok(sqlx::query_as(
+        "UPDATE buy_jobs SET status = 'running' \
+        WHERE id = (SELECT id FROM buy_jobs WHERE status = 'queued' ORDER BY id LIMIT 1) \
+        AND status = 'queued' \
+        RETURNING id, created_at, detected_at, gifts, limits",
+    )
+    .fetch_optional(executor)
+    .await?)
+}
+
+pub async fn finish_buy_job<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    id: i64,
+    status: &str,
+    finished_at: i64,
+) -> Result<()> {
+    sqlx::query("UPDATE buy_jobs SET status = $1, finished_at = $2 WHERE id = $3")
+        .bind(status)
+        .bind(finished_at)
+        .bind(id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// drops the jobs finished before `before`
+pub async fn prune_buy_jobs<'a, E: SqliteExecutor<'a>>(executor: E, before: i64) -> Result<()> {
+    sqlx::query("DELETE FROM buy_jobs WHERE finished_at < $1")
+        .bind(before)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct NotificationMessage {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub gift_id: i64,
+    // `bot::NotificationKind`
+    pub kind: String,
+    pub sent_at: i64,
+}
+
+pub async fn insert_notification_message<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    notification: &NotificationMessage,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO notification_messages(chat_id, message_id, gift_id, kind, sent_at) \
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(notification.chat_id)
+    .bind(notification.message_id)
+    .bind(notification.gift_id)
+    .bind(&notification.kind)
+    .bind(notification.sent_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// oldest first
+pub async fn get_notification_messages<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift_id: i64,
+    kind: &str,
+) -> Result<Vec<NotificationMessage>> {
+    Ok(sqlx::query_as(
+        "SELECT chat_id, message_id, gift_id, kind, sent_at FROM notification_messages \
+        WHERE gift_id = $1 AND kind = $2 ORDER BY sent_at",
+    )
+    .bind(gift_id)
+    .bind(kind)
+    .fetch_all(executor)
+    .await?)
+}
+
+pub async fn delete_notification_messages<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    gift_id: i64,
+) -> Result<()> {
+    sqlx::query("DELETE FROM notification_messages WHERE gift_id = $1")
+        .bind(gift_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// drops the messages sent before `before`
+pub async fn prune_notification_messages<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    before: i64,
+) -> Result<()> {
+    sqlx::query("DELETE FROM notification_messages WHERE sent_at < $1")
+        .bind(before)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// (gift_id, units, stars) of successful purchases
+pub async fn get_bought_gifts<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    since: i64,
+) -> Result<Vec<(i64, i64, i64)>> {
+    Ok(sqlx::query_as(
+        "SELECT gift_id, COUNT(*), SUM(stars) FROM purchases \
+        WHERE status = 'success' AND detected_at >= $1 GROUP BY gift_id",
+    )
+    .bind(since)
+    .fetch_all(executor)
+    .await?)
+}
+
+// (phone_number, stars) of successful purchases sent since `since`
+pub async fn get_spent_by_account<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    since: i64,
+) -> Result<Vec<(String, i64)>> {
+    Ok(sqlx::query_as(
+        "SELECT phone_number, SUM(stars) FROM purchases \
+        WHERE status = 'success' AND sent_at >= $1 GROUP BY phone_number",
+    )
+    .bind(since)
+    .fetch_all(executor)
+    .await?)
+}
+
+// (detection -> payment form, detection -> sent) in millis of successful purchases
+pub async fn get_purchase_latencies<'a, E: SqliteExecutor<'a>>(
+    executor: E,
+    since: i64,
+) -> Result<Vec<(i64, i64)>> {
+    Ok(sqlx::query_as(
+        "SELECT payment_form_at - detected_at, sent_at - detected_at FROM purchases \
+        WHERE status = 'success' AND detected_at >= $1",
+    )
+    .bind(since)
+    .fetch_all(executor)
+    .await?)
+}
+
 // pub async fn insert_peer<'a, E: SqliteExecutor<'a>>(
 //     executor: E,
 //     username: &str,
@@ -0,0 +1,204 @@
+use std::{
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::future::join_all;
+use grammers_client::grammers_tl_types::functions::{payments::GetStarGifts, updates::GetState};
+
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_deauthorized,
+    config::Account,
+    wrapped_client::{Clients, WrappedClient},
+};
+
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub checked_at: SystemTime,
+    // round trip of updates.getState, or the error it failed with
+    pub result: Result<Duration, String>,
+}
+
+// pings every account on `interval`, so connections that died silently are reconnected
+// (or revoked sessions quarantined and alerted about) before the next drop instead of
+// during it
+pub async fn run_health_checks(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    interval: Duration,
+) {
+    loop {
+        for client in clients.snapshot() {
+            if client.is_deauthorized() {
+                continue;
+            }
+
+            let bot = bot.clone();
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                check(&client).await;
+                alert_deauthorized(&bot, &pool, &client).await;
+                // picks up subscriptions started or lapsed since startup
+                client.refresh_premium().await;
+            });
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn check(client: &WrappedClient) {
+    let started_at = Instant::now();
+
+    let result = match client.invoke(&GetState {}).await {
+        Ok(_) => {
+            let latency = started_at.elapsed();
+            tracing::debug!(
+                phone_number = client.phone_number(),
+                latency_ms = latency.as_millis(),
+                "health check"
+            );
+            Ok(latency)
+        }
+        Err(err) => {
+            tracing::warn!(
+                phone_number = client.phone_number(),
+                ?err,
+                "health check failed"
+            );
+            Err(err.to_string())
+        }
+    };
+
+    client.set_health(Health {
+        checked_at: SystemTime::now(),
+        result,
+    });
+}
+
+pub fn accounts_report(clients: &[Arc<WrappedClient>]) -> String {
+    let mut report = format!("Accounts: {}\n", clients.len());
+
+    for client in clients {
+        let phone_number = client.phone_number();
+
+        let status = if client.is_deauthorized() {
+            "🔒 logged out".to_string()
+        } else if let Some(flood_wait) = client.flood_wait::<GetStarGifts>() {
+            format!("⏳ flood wait {}s", flood_wait.as_secs())
+        } else {
+            match client.health() {
+                Some(Health {
+                    checked_at,
+                    result: Ok(latency),
+                }) => format!(
+                    "✅ {} ms, checked {}s ago",
+                    latency.as_millis(),
+                    checked_at.elapsed().unwrap_or_default().as_secs()
+                ),
+                Some(Health {
+                    checked_at,
+                    result: Err(err),
+                }) => format!(
+                    "❌ {err}, checked {}s ago",
+                    checked_at.elapsed().unwrap_or_default().as_secs()
+                ),
+                None => "❔ not checked yet".to_string(),
+            }
+        };
+
+        let purchases = match (client.is_purchasing(), client.queued_purchases()) {
+            (false, _) => String::new(),
+            (true, 0) => ", buying".to_string(),
+            (true, queued) => format!(", buying ({queued} queued)"),
+        };
+
+        writeln!(report, "{phone_number}: {status}{purchases}").unwrap();
+    }
+
+    report
+}
+
+// every configured account, along with the ones added at runtime, with its authorization
+// state, balance, flood waits and proxy
+pub async fn fleet_report(accounts: &[Account], clients: &[Arc<WrappedClient>]) -> String {
+    let find_client = |phone_number: &str| {
+        clients
+            .iter()
+            .find(|client| client.phone_number() == phone_number)
+            .cloned()
+    };
+    let runtime_accounts = clients
+        .iter()
+        .filter(|client| {
+            !accounts
+                .iter()
+                .any(|account| account.phone_number == client.phone_number())
+        })
+        .map(|client| (client.account(), Some(client.clone())));
+    let entries: Vec<_> = accounts
+        .iter()
+        .map(|account| (account, find_client(&account.phone_number)))
+        .chain(runtime_accounts)
+        .collect();
+
+    let lines = join_all(entries.iter().map(|(account, client)| async move {
+        let status = match client {
+            None => "⚪ not logged in".to_string(),
+            Some(client) if client.is_deauthorized() => "🔒 logged out".to_string(),
+            Some(client) => {
+                let balance = match client.get_stars_balance().await {
+                    Ok(balance) => format!("{balance} ⭐"),
+                    Err(err) => {
+                        tracing::error!(
+                            ?err,
+                            phone_number = client.phone_number(),
+                            "failed to get stars balance"
+                        );
+                        "balance unknown".to_string()
+                    }
+                };
+                format!("✅ authorized, {balance}")
+            }
+        };
+
+        let mut line = match &account.label {
+            Some(label) => format!("{label} ({}): {status}", account.phone_number),
+            None => format!("{}: {status}", account.phone_number),
+        };
+        if let Some(client) = client {
+            let flood_waits = client.flood_waits();
+            if !flood_waits.is_empty() {
+                let flood_waits: Vec<_> = flood_waits
+                    .iter()
+                    .map(|(method, remaining)| format!("{method} {}s", remaining.as_secs()))
+                    .collect();
+                write!(line, ", flood waits: {}", flood_waits.join(", ")).unwrap();
+            }
+        }
+        if let Some(proxy) = &account.proxy {
+            write!(line, ", proxy {}", proxy.address()).unwrap();
+        }
+        line
+    }))
+    .await;
+
+    let active = entries
+        .iter()
+        .filter(|(_, client)| {
+            client
+                .as_ref()
+                .is_some_and(|client| !client.is_deauthorized())
+        })
+        .count();
+    format!(
+        "👥 Accounts: {} configured, {active} active\n{}",
+        accounts.len(),
+        lines.join("\n")
+    )
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use grammers_client::grammers_tl_types::enums::StarGift;
+use sqlx::SqlitePool;
+
+use crate::{
+    db::{self, GiftObservation, get_gift_observations, insert_gift_observation, to_unix_millis},
+    stats::format_time,
+};
+
+// weight of the latest rate in the smoothed sell rate
+const SELL_RATE_SMOOTHING: f64 = 0.5;
+
+// only changes are stored, a catalog change usually touches a few gifts
+#[derive(Default)]
+pub struct GiftHistory {
+    // gift_id -> (stars, availability_remains, sold_out)
+    last: HashMap<i64, (i64, Option<i32>, bool)>,
+    sell_rates: HashMap<i64, SellRate>,
+}
+
+struct SellRate {
+    remains: i32,
+    observed_at: i64,
+    // units per second, unknown until remains decrease once
+    per_sec: Option<f64>,
+}
+
+impl GiftHistory {
+    // gifts on sale at the last observation that are sold out in `gifts`, so it's called
+    // before `observe`
+    pub fn sold_out_since_last(&self, gifts: &[StarGift]) -> Vec<i64> {
+        gifts
+            .iter()
+            .filter_map(|gift| match gift {
+                StarGift::Gift(gift) if gift.sold_out => Some(gift.id),
+                _ => None,
+            })
+            .filter(|gift_id| {
+                self.last
+                    .get(gift_id)
+                    .is_some_and(|&(_, _, sold_out)| !sold_out)
+            })
+            .collect()
+    }
+
+    pub fn observe(&mut self, gifts: &[StarGift], observed_at: i64) -> Vec<GiftObservation> {
+        for gift in gifts {
+            if let StarGift::Gift(gift) = gift
+                && let Some(remains) = gift.availability_remains
+            {
+                self.update_sell_rate(gift.id, remains, observed_at);
+            }
+        }
+
+        gifts
+            .iter()
+            .filter_map(|gift| match gift {
+                StarGift::Gift(gift) => Some(gift),
+                StarGift::Unique(_) => None,
+            })
+            .filter_map(|gift| {
+                let state = (gift.stars, gift.availability_remains, gift.sold_out);
+                if self.last.insert(gift.id, state) == Some(state) {
+                    return None;
+                }
+
+                Some(GiftObservation {
+                    gift_id: gift.id,
+                    observed_at,
+                    stars: gift.stars,
+                    availability_remains: gift.availability_remains,
+                    sold_out: gift.sold_out,
+                })
+            })
+            .collect()
+    }
+
+    fn update_sell_rate(&mut self, gift_id: i64, remains: i32, observed_at: i64) {
+        let Some(rate) = self.sell_rates.get_mut(&gift_id) else {
+            self.sell_rates.insert(
+                gift_id,
+                SellRate {
+                    remains,
+                    observed_at,
+                    per_sec: None,
+                },
+            );
+            return;
+        };
+
+        let sold = rate.remains - remains;
+        let elapsed_secs = (observed_at - rate.observed_at) as f64 / 1000.0;
+        // unchanged remains are kept as the start of the next interval
+        if sold <= 0 || elapsed_secs <= 0.0 {
+            if sold < 0 {
+                // restocked
+                *rate = SellRate {
+                    remains,
+                    observed_at,
+                    per_sec: None,
+                };
+            }
+            return;
+        }
+
+        let latest = f64::from(sold) / elapsed_secs;
+        rate.per_sec = Some(match rate.per_sec {
+            Some(per_sec) => SELL_RATE_SMOOTHING * latest + (1.0 - SELL_RATE_SMOOTHING) * per_sec,
+            None => latest,
+        });
+        rate.remains = remains;
+        rate.observed_at = observed_at;
+    }
+
+    // at the current sell rate, known after remains decreased between two observations
+    pub fn sell_out_eta(&self, gift_id: i64) -> Option<Duration> {
+        let rate = self.sell_rates.get(&gift_id)?;
+        let per_sec = rate.per_sec.filter(|per_sec| *per_sec > 0.0)?;
+        Some(Duration::from_secs_f64(f64::from(rate.remains) / per_sec))
+    }
+}
+
+pub fn spawn_record_observations(pool: Arc<SqlitePool>, observations: Vec<GiftObservation>) {
+    if observations.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        for observation in &observations {
+            if let Err(err) = insert_gift_observation(&*pool, observation).await {
+                tracing::error!(?err, ?observation, "failed to record gift observation");
+            }
+        }
+    });
+}
+
+pub async fn history_report(
+    pool: &SqlitePool,
+    gift_id: i64,
+    window: Duration,
+    csv: bool,
+) -> db::Result<String> {
+    let since = to_unix_millis(SystemTime::now() - window);
+    let observations = get_gift_observations(pool, gift_id, since).await?;
+
+    let mut report = if csv {
+        "observed_at,stars,availability_remains,sold_out\n".to_string()
+    } else {
+        format!(
+            "{:<17} {:>8} {:>10} SOLD OUT\n",
+            "OBSERVED AT", "STARS", "REMAINS"
+        )
+    };
+
+    for observation in observations {
+        let remains = observation
+            .availability_remains
+            .map_or(String::new(), |remains| remains.to_string());
+
+        if csv {
+            writeln!(
+                report,
+                "{},{},{remains},{}",
+                observation.observed_at, observation.stars, observation.sold_out
+            )
+        } else {
+            writeln!(
+                report,
+                "{:<17} {:>8} {:>10} {}",
+                format_time(observation.observed_at),
+                observation.stars,
+                remains,
+                if observation.sold_out { "yes" } else { "no" }
+            )
+        }
+        .unwrap();
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,284 @@
+// languages of the messages the bot sends to a chat, picked with /language
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    En,
+    Ru,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Text {
+    Buy,
+    UserNotAdmin,
+    AddedToTrustedChats,
+    CancellingPurchases,
+    NoPurchasesRunning,
+    ChooseLanguage,
+    LanguageSet,
+    Limited,
+    Stars,
+    Supply,
+    Remains,
+    GiftBought,
+    Error,
+    Count,
+    PhoneNumber,
+    Balance,
+    Recipient,
+    Gift,
+    Cancel,
+    Confirm,
+    Untitled,
+    NoGiftsOnSale,
+    WizardNotStarted,
+    WizardCancelled,
+    AskQuantity,
+    InvalidQuantity,
+    AskDestination,
+    DefaultDestination,
+    ToSelf,
+    NoActiveAccounts,
+}
+
+// of a buy job, shown in its progress message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuyState {
+    Buying,
+    Cancelled,
+    Finished,
+}
+
+impl Language {
+    pub const ALL: [Self; 2] = [Self::En, Self::Ru];
+
+    // stored in `chat_settings`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Ru => "ru",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.code() == code)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::En => "🇬🇧 English",
+            Self::Ru => "🇷🇺 Русский",
+        }
+    }
+
+    pub fn text(&self, text: Text) -> &'static str {
+        match (self, text) {
+            (Self::En, Text::Buy) => "Buy",
+            (Self::Ru, Text::Buy) => "Купить",
+            (Self::En, Text::UserNotAdmin) => "User not in admins list",
+            (Self::Ru, Text::UserNotAdmin) => "Пользователь не в списке админов",
+            (Self::En, Text::AddedToTrustedChats) => "Added to trusted chats",
+            (Self::Ru, Text::AddedToTrustedChats) => "Добавлено в доверенные чаты",
+            (Self::En, Text::CancellingPurchases) => "Cancelling running purchases",
+            (Self::Ru, Text::CancellingPurchases) => "Отменяю текущие покупки",
+            (Self::En, Text::NoPurchasesRunning) => "No purchases are running",
+            (Self::Ru, Text::NoPurchasesRunning) => "Нет текущих покупок",
+            (Self::En, Text::ChooseLanguage) => "Choose the language",
+            (Self::Ru, Text::ChooseLanguage) => "Выберите язык",
+            (Self::En, Text::LanguageSet) => "Language set to English",
+            (Self::Ru, Text::LanguageSet) => "Выбран русский язык",
+            (Self::En, Text::Limited) => "Limited",
+            (Self::Ru, Text::Limited) => "Лимитированный",
+            (Self::En, Text::Stars) => "Stars",
+            (Self::Ru, Text::Stars) => "Звёзды",
+            (Self::En, Text::Supply) => "Supply",
+            (Self::Ru, Text::Supply) => "Тираж",
+            (Self::En, Text::Remains) => "Remains",
+            (Self::Ru, Text::Remains) => "Осталось",
+            (Self::En, Text::GiftBought) => "Gift bought",
+            (Self::Ru, Text::GiftBought) => "Подарок куплен",
+            (Self::En, Text::Error) => "Error",
+            (Self::Ru, Text::Error) => "Ошибка",
+            (Self::En, Text::Count) => "Count",
+            (Self::Ru, Text::Count) => "Количество",
+            (Self::En, Text::PhoneNumber) => "Phone Number",
+            (Self::Ru, Text::PhoneNumber) => "Номер телефона",
+            (Self::En, Text::Balance) => "Balance",
+            (Self::Ru, Text::Balance) => "Баланс",
+            (Self::En, Text::Recipient) => "Recipient",
+            (Self::Ru, Text::Recipient) => "Получатель",
+            (Self::En, Text::Gift) => "Gift",
+            (Self::Ru, Text::Gift) => "Подарок",
+            (Self::En, Text::Cancel) => "Cancel",
+            (Self::Ru, Text::Cancel) => "Отменить",
+            (Self::En, Text::Confirm) => "Confirm",
+            (Self::Ru, Text::Confirm) => "Подтвердить",
+            (Self::En, Text::Untitled) => "untitled",
+            (Self::Ru, Text::Untitled) => "без названия",
+            (Self::En, Text::NoGiftsOnSale) => "No gifts on sale in the last catalog",
+            (Self::Ru, Text::NoGiftsOnSale) => "В последнем каталоге нет подарков в продаже",
+            (Self::En, Text::WizardNotStarted) => {
+                "No purchase is being set up, start one with /wizard"
+            }
+            (Self::Ru, Text::WizardNotStarted) => "Покупка не начата, начните её с /wizard",
+            (Self::En, Text::WizardCancelled) => "Purchase cancelled",
+            (Self::Ru, Text::WizardCancelled) => "Покупка отменена",
+            (Self::En, Text::AskQuantity) => "How many units per account?",
+            (Self::Ru, Text::AskQuantity) => "Сколько штук на аккаунт?",
+            (Self::En, Text::InvalidQuantity) => "Send the number of units per account, e.g. 3",
+            (Self::Ru, Text::InvalidQuantity) => "Отправьте число штук на аккаунт, например 3",
+            (Self::En, Text::AskDestination) => {
+                "Where to? Pick one or send destinations as for /setdest"
+            }
+            (Self::Ru, Text::AskDestination) => {
+                "Куда отправить? Выберите или пришлите получателей как для /setdest"
+            }
+            (Self::En, Text::DefaultDestination) => "Default",
+            (Self::Ru, Text::DefaultDestination) => "По умолчанию",
+            (Self::En, Text::ToSelf) => "Self",
+            (Self::Ru, Text::ToSelf) => "Себе",
+            (Self::En, Text::NoActiveAccounts) => {
+                "No active accounts to check the destination with"
+            }
+            (Self::Ru, Text::NoActiveAccounts) => "Нет активных аккаунтов для проверки получателя",
+        }
+    }
+
+    // what pressing Buy spends at most, per account and across all accounts
+    pub fn estimated_cost(&self, limit: u64, stars: i64, accounts: u64) -> String {
+        let cost = limit as i64 * stars;
+        let fleet_cost = cost * accounts as i64;
+        match self {
+            Self::En => format!("Buy: {limit} × {stars} = {cost} ⭐ (fleet max {fleet_cost} ⭐)"),
+            Self::Ru => format!(
+                "Покупка: {limit} × {stars} = {cost} ⭐ (максимум на все аккаунты {fleet_cost} ⭐)"
+            ),
+        }
+    }
+
+    // heads the Buy buttons of a drop of several gifts
+    pub fn drop_summary(&self, count: usize) -> String {
+        match self {
+            Self::En => format!("🎁 {count} new gifts"),
+            Self::Ru => format!("🎁 Новых подарков: {count}"),
+        }
+    }
+
+    // `account` is `None` for the cap on all accounts together
+    pub fn daily_spend_limit_reached(
+        &self,
+        account: Option<&str>,
+        spent: i64,
+        limit: i64,
+    ) -> String {
+        match (self, account) {
+            (Self::En, Some(account)) => {
+                format!("💸 {account} reached its daily spend limit: {spent} of {limit} ⭐")
+            }
+            (Self::En, None) => format!("💸 Daily spend limit reached: {spent} of {limit} ⭐"),
+            (Self::Ru, Some(account)) => {
+                format!("💸 {account} достиг дневного лимита трат: {spent} из {limit} ⭐")
+            }
+            (Self::Ru, None) => format!("💸 Достигнут дневной лимит трат: {spent} из {limit} ⭐"),
+        }
+    }
+
+    pub fn invalid_destination(&self, destination: &str, err: &str) -> String {
+        match self {
+            Self::En => format!("Invalid destination {destination}: {err}"),
+            Self::Ru => format!("Неверный получатель {destination}: {err}"),
+        }
+    }
+
+    pub fn unresolved_destination(&self, destination: &str, err: &str) -> String {
+        match self {
+            Self::En => format!("Failed to resolve {destination}: {err}"),
+            Self::Ru => format!("Не удалось найти {destination}: {err}"),
+        }
+    }
+
+    // heads a page of the /wizard gift list
+    pub fn pick_gift(&self, page: usize, pages: usize) -> String {
+        match self {
+            Self::En => format!("Pick a gift ({page}/{pages})"),
+            Self::Ru => format!("Выберите подарок ({page}/{pages})"),
+        }
+    }
+
+    // `None` for unlimited gifts
+    pub fn units_left(&self, remains: Option<i32>) -> String {
+        match (self, remains) {
+            (Self::En, Some(remains)) => format!("{remains} left"),
+            (Self::En, None) => "unlimited".to_string(),
+            (Self::Ru, Some(remains)) => format!("осталось {remains}"),
+            (Self::Ru, None) => "без лимита".to_string(),
+        }
+    }
+
+    // `destination` is `None` for the default one
+    pub fn confirm_purchase(
+        &self,
+        quantity: i64,
+        title: &str,
+        stars: i64,
+        destination: Option<&str>,
+    ) -> String {
+        let cost = quantity * stars;
+        match self {
+            Self::En => format!(
+                "Buy {quantity} × {title} ({stars} ⭐) per account to {}?\n\
+                Up to {cost} ⭐ per account",
+                destination.unwrap_or("the default destination")
+            ),
+            Self::Ru => format!(
+                "Купить {quantity} × {title} ({stars} ⭐) на аккаунт для {}?\n\
+                До {cost} ⭐ на аккаунт",
+                destination.unwrap_or("получателя по умолчанию")
+            ),
+        }
+    }
+
+    pub fn buying_per_account(&self, quantity: i64, gift_id: i64) -> String {
+        match self {
+            Self::En => format!("Buying {quantity} × {gift_id} per account"),
+            Self::Ru => format!("Покупаю {quantity} × {gift_id} на аккаунт"),
+        }
+    }
+
+    pub fn buy_progress(&self, state: BuyState, gifts: usize, bought: u64, failed: u64) -> String {
+        match self {
+            Self::En => {
+                let status = match state {
+                    BuyState::Buying => "⏳ Buying",
+                    BuyState::Cancelled => "🛑 Cancelled buying",
+                    BuyState::Finished => "🏁 Finished buying",
+                };
+                format!("{status} {gifts} gift(s): {bought} bought, {failed} failed")
+            }
+            Self::Ru => {
+                let status = match state {
+                    BuyState::Buying => "⏳ Покупка",
+                    BuyState::Cancelled => "🛑 Покупка отменена",
+                    BuyState::Finished => "🏁 Покупка завершена",
+                };
+                format!("{status}, подарков: {gifts}, куплено {bought}, ошибок {failed}")
+            }
+        }
+    }
+
+    // heads the summary of what an account bought in a run
+    pub fn account_bought(&self, account: &str, units: u64, stars: i64) -> String {
+        match self {
+            Self::En => format!("🧾 {account} bought {units} gifts for {stars} ⭐"),
+            Self::Ru => format!("🧾 {account} купил подарков: {units} за {stars} ⭐"),
+        }
+    }
+
+    pub fn sell_out_eta(&self, minutes: u64) -> String {
+        match self {
+            Self::En => format!("Estimated sell-out in ~{minutes} min"),
+            Self::Ru => format!("Распродадут примерно через {minutes} мин"),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use grammers_client::grammers_tl_types::{
+    self as tl, Deserializable, Serializable, enums::StarGift, types,
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    core::{BuyLimits, PURCHASE_TASKS},
+    db::{self, BuyJob, insert_buy_job, to_unix_millis},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] db::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("invalid gifts of job {0}: {1}")]
+    InvalidGifts(i64, tl::deserialize::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// outcome of a claimed job, stored in its status
+#[derive(Debug, Clone, Copy)]
+pub enum JobStatus {
+    // queued longer ago than the worker's max age, or undecodable
+    Skipped,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Skipped => "skipped",
+            Self::Done => "done",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+// the gifts a watcher detected, in priority order, as the worker claiming them buys them
+#[derive(Debug)]
+pub struct Job {
+    pub id: i64,
+    pub gifts: Vec<types::StarGift>,
+    pub limits: BuyLimits,
+    pub detected_at: SystemTime,
+}
+
+impl Job {
+    pub fn decode(job: BuyJob) -> Result<Self> {
+        let gifts = Vec::<StarGift>::from_bytes(&job.gifts)
+            .map_err(|err| Error::InvalidGifts(job.id, err))?
+            .into_iter()
+            .filter_map(|gift| match gift {
+                StarGift::Gift(gift) => Some(gift),
+                StarGift::Unique(_) => None,
+            })
+            .collect();
+
+        Ok(Self {
+            id: job.id,
+            gifts,
+            limits: serde_json::from_str(&job.limits)?,
+            detected_at: SystemTime::UNIX_EPOCH
+                + Duration::from_millis(job.detected_at.max(0) as u64),
+        })
+    }
+}
+
+// queues the gifts for the workers instead of buying them, gifts are stored whole so
+// workers don't have to fetch the catalog again
+pub fn spawn_enqueue(
+    pool: Arc<SqlitePool>,
+    gifts: &[types::StarGift],
+    limits: &BuyLimits,
+    detected_at: SystemTime,
+) {
+    let data = gifts
+        .iter()
+        .cloned()
+        .map(StarGift::Gift)
+        .collect::<Vec<_>>()
+        .to_bytes();
+    let limits = serde_json::to_string(limits).expect("limits are serializable");
+    let detected_at = to_unix_millis(detected_at);
+
+    PURCHASE_TASKS.spawn(async move {
+        let created_at = to_unix_millis(SystemTime::now());
+        match insert_buy_job(&*pool, created_at, detected_at, &data, &limits).await {
+            Ok(id) => tracing::info!(id, "buy job queued"),
+            Err(err) => tracing::error!(?err, "failed to queue buy job"),
+        }
+    });
+}
@@ -0,0 +1,89 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    db::{to_unix_millis, try_acquire_lease},
+};
+
+const PURCHASES_LEASE: &str = "purchases";
+
+// whether this instance executes purchases, instances sharing a database elect one
+// through a lease, every instance keeps polling and notifying,
+// the database is a SQLite file, so the instances have to run on the host it's on,
+// SQLite's locking doesn't hold up over network file systems and two hosts could both
+// take the lease
+#[derive(Debug, Clone)]
+pub struct Leadership(Arc<AtomicBool>);
+
+impl Leadership {
+    // a single instance is always the leader
+    pub fn always() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// acquires or renews the purchases lease every third of `ttl`, another instance takes
+// over once this one stops renewing it for `ttl`
+pub fn spawn_lease(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    instance_id: String,
+    ttl: Duration,
+) -> Leadership {
+    let leadership = Leadership(Arc::new(AtomicBool::new(false)));
+
+    tokio::spawn({
+        let leadership = leadership.clone();
+        async move {
+            loop {
+                let now = to_unix_millis(SystemTime::now());
+                let is_leader = match try_acquire_lease(
+                    &*pool,
+                    PURCHASES_LEASE,
+                    &instance_id,
+                    now,
+                    now + ttl.as_millis() as i64,
+                )
+                .await
+                {
+                    Ok(t) => t,
+                    Err(err) => {
+                        // the other instances can't tell it's still alive either
+                        tracing::error!(?err, "failed to renew lease");
+                        false
+                    }
+                };
+
+                if leadership.0.swap(is_leader, Ordering::Relaxed) != is_leader {
+                    tracing::info!(instance_id, is_leader, "leadership changed");
+
+                    let text = if is_leader {
+                        format!("👑 {instance_id} executes purchases now")
+                    } else {
+                        format!("💤 {instance_id} stopped executing purchases")
+                    };
+                    if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                        tracing::error!(?err, "failed to send leadership alert");
+                    }
+                }
+
+                tokio::time::sleep(ttl / 3).await;
+            }
+        }
+    });
+
+    leadership
+}
@@ -1,21 +1,56 @@
 #![allow(clippy::result_large_err)]
 
+use std::process::ExitCode;
+
 use anyhow::Result;
 use clap::Parser;
 use tracing_appender::non_blocking;
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::cli::Cli;
+use crate::cli::{Cli, exit_code};
 
 mod bot;
+mod catalog_changes;
 mod cli;
+mod config;
 mod core;
+mod daemon;
 mod db;
+mod health;
+mod history;
+mod i18n;
+mod jobs;
+mod lease;
+mod mini_app;
+mod polling;
+mod preflight;
+mod rate_limit;
+mod resale;
+mod script;
+mod stats;
+mod systemd;
+mod telegram_api;
+mod topup;
+mod transactions;
+mod uniques;
+mod updates;
+mod wizard;
 mod wrapped_client;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     dotenvy::dotenv().ok();
+
+    let cli = Cli::parse();
+
+    let pid_file = match cli.daemon() {
+        Some((pid_file, log_file)) => {
+            daemon::daemonize(pid_file, log_file)?;
+            Some(pid_file.to_path_buf())
+        }
+        None => None,
+    };
+    let is_daemon = pid_file.is_some();
+
     // tracing_subscriber::fmt::init();
 
     let file_appender = tracing_appender::rolling::hourly("logs", "app.log");
@@ -23,7 +58,9 @@ async fn main() -> Result<()> {
 
     let filter = EnvFilter::from_default_env();
 
-    let stderr_layer = fmt::layer().with_ansi(true).with_writer(std::io::stderr);
+    let stderr_layer = fmt::layer()
+        .with_ansi(!is_daemon)
+        .with_writer(std::io::stderr);
 
     let file_layer = fmt::layer().with_ansi(false).with_writer(file_nb);
 
@@ -33,7 +70,29 @@ async fn main() -> Result<()> {
         .with(file_layer)
         .init();
 
-    Cli::parse().process().await?;
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(async {
+            if !is_daemon {
+                return cli.process().await;
+            }
+            // `stop` ends the daemon here, so it removes its pid file on the way out
+            tokio::select! {
+                result = cli.process() => result,
+                result = daemon::terminated() => Ok(result?),
+            }
+        });
+
+    if let Some(pid_file) = &pid_file {
+        std::fs::remove_file(pid_file)?;
+    }
+
+    // printed like an error returned from main, but with its own exit code
+    if let Err(err) = result {
+        eprintln!("Error: {err:?}");
+        return Ok(ExitCode::from(exit_code(&err)));
+    }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
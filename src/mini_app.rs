@@ -0,0 +1,435 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    response::{Html, IntoResponse, Response},
+    routing::{get, put},
+};
+use futures::future::join_all;
+use grammers_client::grammers_tl_types::enums::StarGift;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use teloxide::{
+    Bot,
+    payloads::SetChatMenuButtonSetters,
+    prelude::Requester,
+    types::{MenuButton, WebAppInfo},
+};
+
+use crate::{
+    db::{self, Purchase, get_recent_purchases},
+    wrapped_client::Clients,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] db::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    TeloxideRequest(#[from] teloxide::RequestError),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error("missing or invalid init data")]
+    Unauthorized,
+    #[error("user not in admins list")]
+    Forbidden,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            _ => {
+                tracing::error!(err = ?self, "mini app request failed");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+// init data older than this is rejected, so a leaked one can't be replayed for long
+const INIT_DATA_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const RECENT_PURCHASES_LIMIT: i64 = 50;
+
+// the page refreshes every 10 seconds, the balances are fetched at most this often
+const BALANCES_MAX_AGE: Duration = Duration::from_secs(60);
+
+const INDEX_HTML: &str = include_str!("mini_app.html");
+
+// whether detected gifts are bought, toggled from the mini app
+#[derive(Debug, Clone)]
+pub struct AutoBuy(Arc<AtomicBool>);
+
+impl AutoBuy {
+    pub fn new(enabled: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogGift {
+    pub id: i64,
+    pub title: Option<String>,
+    pub stars: i64,
+    pub limited: bool,
+    pub sold_out: bool,
+    pub supply: Option<i32>,
+    pub remains: Option<i32>,
+}
+
+// the last catalog received by the poll loop
+#[derive(Debug, Clone, Default)]
+pub struct Catalog(Arc<Mutex<Vec<CatalogGift>>>);
+
+impl Catalog {
+    pub fn update(&self, gifts: &[StarGift]) {
+        let gifts = gifts
+            .iter()
+            .filter_map(|gift| match gift {
+                StarGift::Gift(gift) => Some(CatalogGift {
+                    id: gift.id,
+                    title: gift.title.clone(),
+                    stars: gift.stars,
+                    limited: gift.limited,
+                    sold_out: gift.sold_out,
+                    supply: gift.availability_total,
+                    remains: gift.availability_remains,
+                }),
+                StarGift::Unique(_) => None,
+            })
+            .collect();
+        *self.0.lock().unwrap() = gifts;
+    }
+
+    pub fn snapshot(&self) -> Vec<CatalogGift> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+// the balances shown by the dashboard, fetched from every account when stale
+#[derive(Debug, Default)]
+pub struct Balances(tokio::sync::Mutex<Option<(Instant, BTreeMap<String, Option<i64>>)>>);
+
+impl Balances {
+    // locked while fetching, so concurrent requests wait for a single fetch
+    async fn get(&self, clients: &Clients) -> BTreeMap<String, Option<i64>> {
+        let mut cached = self.0.lock().await;
+        if let Some((fetched_at, balances)) = &*cached
+            && fetched_at.elapsed() < BALANCES_MAX_AGE
+        {
+            return balances.clone();
+        }
+
+        let balances: BTreeMap<_, _> =
+            join_all(clients.snapshot().into_iter().map(|client| async move {
+                let balance = client
+                    .get_stars_balance()
+                    .await
+                    .inspect_err(|err| {
+                        tracing::error!(
+                            ?err,
+                            phone_number = client.phone_number(),
+                            "failed to get stars balance"
+                        )
+                    })
+                    .ok();
+                (client.phone_number().to_string(), balance)
+            }))
+            .await
+            .into_iter()
+            .collect();
+        *cached = Some((Instant::now(), balances.clone()));
+        balances
+    }
+}
+
+pub struct MiniApp {
+    pub bot_token: String,
+    pub admin_usernames: Arc<[String]>,
+    pub pool: Arc<SqlitePool>,
+    pub clients: Clients,
+    pub catalog: Catalog,
+    pub auto_buy: AutoBuy,
+    pub balances: Balances,
+}
+
+#[derive(Serialize)]
+struct Dashboard {
+    auto_buy: bool,
+    // `None` when the balance couldn't be fetched
+    balances: BTreeMap<String, Option<i64>>,
+    catalog: Vec<CatalogGift>,
+    purchases: Vec<Purchase>,
+}
+
+#[derive(Deserialize)]
+struct AutoBuyToggle {
+    enabled: bool,
+}
+
+// sets the bot's menu button to open the mini app at `url`, which has to be served
+// over https (e.g. by a reverse proxy in front of `listen`), and serves it until
+// the listener fails
+pub async fn run_mini_app(
+    state: Arc<MiniApp>,
+    bot: Arc<Bot>,
+    url: &str,
+    listen: SocketAddr,
+) -> Result<()> {
+    bot.set_chat_menu_button()
+        .menu_button(MenuButton::WebApp {
+            text: "Dashboard".to_string(),
+            web_app: WebAppInfo { url: url.parse()? },
+        })
+        .await?;
+
+    let router = Router::new()
+        .route("/", get(|| async { Html(INDEX_HTML) }))
+        .route("/api/dashboard", get(get_dashboard))
+        .route("/api/auto-buy", put(put_auto_buy))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    tracing::info!(%listen, "mini app listening");
+    axum::serve(listener, router).await?;
+
+    Ok(())
+}
+
+async fn get_dashboard(
+    State(state): State<Arc<MiniApp>>,
+    headers: HeaderMap,
+) -> Result<Json<Dashboard>> {
+    authorize(&state, &headers)?;
+
+    let balances = state.balances.get(&state.clients).await;
+
+    Ok(Json(Dashboard {
+        auto_buy: state.auto_buy.is_enabled(),
+        balances,
+        catalog: state.catalog.snapshot(),
+        purchases: get_recent_purchases(&*state.pool, RECENT_PURCHASES_LIMIT).await?,
+    }))
+}
+
+async fn put_auto_buy(
+    State(state): State<Arc<MiniApp>>,
+    headers: HeaderMap,
+    Json(toggle): Json<AutoBuyToggle>,
+) -> Result<StatusCode> {
+    let username = authorize(&state, &headers)?;
+
+    state.auto_buy.set(toggle.enabled);
+    tracing::info!(username, enabled = toggle.enabled, "auto-buy toggled");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// the page sends its init data as `Authorization: tma <init data>`,
+// returns the username of the admin
+fn authorize(state: &MiniApp, headers: &HeaderMap) -> Result<String> {
+    let init_data = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("tma "))
+        .ok_or(Error::Unauthorized)?;
+
+    let username = validate_init_data(&state.bot_token, init_data, SystemTime::now())?
+        .ok_or(Error::Forbidden)?;
+    if !state.admin_usernames.contains(&username) {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(username)
+}
+
+#[derive(Deserialize)]
+struct InitDataUser {
+    username: Option<String>,
+}
+
+// checks the signature of the init data the way Telegram describes at
+// https://core.telegram.org/bots/webapps#validating-data-received-via-the-mini-app,
+// returns the username of the user who opened the app
+fn validate_init_data(bot_token: &str, init_data: &str, now: SystemTime) -> Result<Option<String>> {
+    let mut hash = None;
+    let mut fields = BTreeMap::new();
+    for (key, value) in url::form_urlencoded::parse(init_data.as_bytes()) {
+        if key == "hash" {
+            hash = Some(value.into_owned());
+        } else {
+            fields.insert(key.into_owned(), value.into_owned());
+        }
+    }
+    let hash = hash
+        .as_deref()
+        .and_then(decode_hex)
+        .ok_or(Error::Unauthorized)?;
+
+    let data_check_string = fields
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut secret_key = Hmac::<Sha256>::new_from_slice(b"WebAppData").unwrap();
+    secret_key.update(bot_token.as_bytes());
+    let secret_key = secret_key.finalize().into_bytes();
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&secret_key).unwrap();
+    mac.update(data_check_string.as_bytes());
+    mac.verify_slice(&hash).map_err(|_| Error::Unauthorized)?;
+
+    let auth_date = fields
+        .get("auth_date")
+        .and_then(|auth_date| auth_date.parse().ok())
+        .map(|auth_date| UNIX_EPOCH + Duration::from_secs(auth_date))
+        .ok_or(Error::Unauthorized)?;
+    if now
+        .duration_since(auth_date)
+        .is_ok_and(|age| age > INIT_DATA_MAX_AGE)
+    {
+        return Err(Error::Unauthorized);
+    }
+
+    let user: InitDataUser = fields
+        .get("user")
+        .and_then(|user| serde_json::from_str(user).ok())
+        .ok_or(Error::Unauthorized)?;
+
+    Ok(user.username)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOT_TOKEN: &str = "123456:test-token";
+    const AUTH_DATE: u64 = 1_700_000_000;
+
+    // init data signed with `bot_token` as Telegram signs it
+    fn sign(bot_token: &str, fields: &[(&str, &str)]) -> String {
+        let mut sorted = fields.to_vec();
+        sorted.sort();
+        let data_check_string = sorted
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut secret_key = Hmac::<Sha256>::new_from_slice(b"WebAppData").unwrap();
+        secret_key.update(bot_token.as_bytes());
+        let mut mac = Hmac::<Sha256>::new_from_slice(&secret_key.finalize().into_bytes()).unwrap();
+        mac.update(data_check_string.as_bytes());
+        let hash: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(fields)
+            .append_pair("hash", &hash)
+            .finish()
+    }
+
+    fn fields(user: &str) -> Vec<(&str, &str)> {
+        vec![
+            ("query_id", "AAHdF6IQAAAAAN0XohDhrOrc"),
+            ("user", user),
+            ("auth_date", "1700000000"),
+        ]
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn accepts_signed_init_data() {
+        let init_data = sign(BOT_TOKEN, &fields(r#"{"id":1,"username":"admin"}"#));
+        assert_eq!(
+            validate_init_data(BOT_TOKEN, &init_data, at(AUTH_DATE + 60)).unwrap(),
+            Some("admin".to_string())
+        );
+
+        let init_data = sign(BOT_TOKEN, &fields(r#"{"id":1}"#));
+        assert_eq!(
+            validate_init_data(BOT_TOKEN, &init_data, at(AUTH_DATE)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_init_data() {
+        let init_data = sign(BOT_TOKEN, &fields(r#"{"id":1,"username":"guest"}"#));
+        let tampered = init_data.replace("guest", "admin");
+        assert!(matches!(
+            validate_init_data(BOT_TOKEN, &tampered, at(AUTH_DATE)),
+            Err(Error::Unauthorized)
+        ));
+
+        let foreign = sign(
+            "654321:other-token",
+            &fields(r#"{"id":1,"username":"admin"}"#),
+        );
+        assert!(matches!(
+            validate_init_data(BOT_TOKEN, &foreign, at(AUTH_DATE)),
+            Err(Error::Unauthorized)
+        ));
+
+        let unsigned = init_data.split("&hash=").next().unwrap();
+        assert!(matches!(
+            validate_init_data(BOT_TOKEN, unsigned, at(AUTH_DATE)),
+            Err(Error::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_init_data() {
+        let init_data = sign(BOT_TOKEN, &fields(r#"{"id":1,"username":"admin"}"#));
+        let expired = at(AUTH_DATE) + INIT_DATA_MAX_AGE + Duration::from_secs(1);
+        assert!(matches!(
+            validate_init_data(BOT_TOKEN, &init_data, expired),
+            Err(Error::Unauthorized)
+        ));
+    }
+}
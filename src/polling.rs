@@ -0,0 +1,494 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{Timelike, Utc};
+use chrono_tz::Tz;
+use futures::{StreamExt, stream::FuturesUnordered};
+use grammers_client::grammers_tl_types::{
+    enums::payments::StarGifts, functions::payments::GetStarGifts,
+};
+use rand::Rng;
+use sqlx::SqlitePool;
+use teloxide::Bot;
+use tokio::{sync::Notify, time::Instant};
+
+use crate::{
+    bot::alert_chats,
+    telegram_api::TelegramApi,
+    wrapped_client::{Clients, ErrorClass, WrappedClient},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid burst window, expected HH:MM-HH:MM (window = {0})")]
+    InvalidWindow(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// how long the accounts of a sustained poll failure are only used as a backup
+const POLL_FAILOVER_DURATION: Duration = Duration::from_secs(10 * 60);
+
+// time of day range in the configured timezone, may wrap over midnight (e.g. 23:30-00:30)
+#[derive(Debug, Clone, Copy)]
+pub struct DailyWindow {
+    start: u64,
+    end: u64,
+}
+
+impl DailyWindow {
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidWindow(s.to_string());
+
+        let parse_time = |time: &str| -> Result<u64> {
+            let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+            let hours: u64 = hours.parse().map_err(|_| invalid())?;
+            let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+            if hours >= 24 || minutes >= 60 {
+                return Err(invalid());
+            }
+            Ok(hours * 3600 + minutes * 60)
+        };
+
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+
+        Ok(Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    fn contains(&self, secs_of_day: u64) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&secs_of_day)
+        } else {
+            secs_of_day >= self.start || secs_of_day < self.end
+        }
+    }
+
+    pub fn until_start(&self, secs_of_day: u64) -> Duration {
+        Duration::from_secs((self.start + SECS_PER_DAY - secs_of_day) % SECS_PER_DAY)
+    }
+}
+
+pub fn secs_of_day(timezone: Tz) -> u64 {
+    u64::from(
+        Utc::now()
+            .with_timezone(&timezone)
+            .num_seconds_from_midnight(),
+    )
+}
+
+pub struct AdaptivePolling {
+    interval: Duration,
+    burst_interval: Duration,
+    burst_duration: Duration,
+    burst_windows: Vec<DailyWindow>,
+    burst_until: Option<Instant>,
+    jitter: Duration,
+    // of `burst_windows`
+    timezone: Tz,
+}
+
+impl AdaptivePolling {
+    pub fn new(
+        interval: Duration,
+        burst_interval: Duration,
+        burst_duration: Duration,
+        burst_windows: &[String],
+        jitter: Duration,
+        timezone: Tz,
+    ) -> Result<Self> {
+        Ok(Self {
+            interval,
+            burst_interval,
+            burst_duration,
+            burst_windows: burst_windows
+                .iter()
+                .map(|window| DailyWindow::parse(window))
+                .collect::<Result<_>>()?,
+            burst_until: None,
+            jitter,
+            timezone,
+        })
+    }
+
+    pub fn on_catalog_changed(&mut self) {
+        if !self.is_bursting() {
+            tracing::info!(burst_duration = ?self.burst_duration, "catalog changed, bursting");
+        }
+        self.burst_until = Some(Instant::now() + self.burst_duration);
+    }
+
+    pub fn is_bursting(&self) -> bool {
+        let secs_of_day = secs_of_day(self.timezone);
+
+        self.burst_until
+            .is_some_and(|burst_until| Instant::now() < burst_until)
+            || self
+                .burst_windows
+                .iter()
+                .any(|window| window.contains(secs_of_day))
+    }
+
+    // randomized so that several instances/accounts don't hit the server in lockstep
+    pub fn interval(&self) -> Duration {
+        let interval = if self.is_bursting() {
+            self.burst_interval
+        } else {
+            self.interval
+        };
+
+        interval + rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+    }
+}
+
+// returns early when `trigger` is notified, e.g. by an update hinting at a catalog change
+pub async fn wait_next_poll(deadline: Instant, trigger: &Notify) {
+    tokio::select! {
+        _ = tokio::time::sleep_until(deadline) => {}
+        _ = trigger.notified() => {}
+    }
+}
+
+// sends the request through all `clients` at once and returns the first successful
+// response, so a single slow connection doesn't delay detection, or the error of every
+// client when they all fail
+pub async fn race_get_star_gifts<C: TelegramApi>(
+    clients: &[Arc<C>],
+    hash: i32,
+) -> Result<(Arc<C>, StarGifts), Vec<(Arc<C>, ErrorClass)>> {
+    let request = GetStarGifts { hash };
+
+    let mut responses: FuturesUnordered<_> = clients
+        .iter()
+        .map(|client| async { (client, client.invoke(&request).await) })
+        .collect();
+
+    let mut errors = vec![];
+    while let Some((client, result)) = responses.next().await {
+        match result {
+            Ok(star_gifts) => return Ok((client.clone(), star_gifts)),
+            Err(err) => {
+                // sustained failures are escalated by `PollFailures`
+                tracing::warn!(
+                    ?err,
+                    phone_number = client.phone_number(),
+                    "failed to get star gifts"
+                );
+                errors.push((client.clone(), ErrorClass::of(&err)));
+            }
+        }
+    }
+
+    Err(errors)
+}
+
+pub struct ClientRotation {
+    clients: Clients,
+    next: usize,
+    // accounts only polled through when no other is available, until the deadline
+    backup_until: HashMap<String, Instant>,
+}
+
+impl ClientRotation {
+    pub fn new(clients: Clients) -> Self {
+        Self {
+            clients,
+            next: 0,
+            backup_until: HashMap::new(),
+        }
+    }
+
+    // round-robin, skipping deauthorized clients and clients in flood wait,
+    // and preferring clients not demoted by `fail_over`
+    pub fn next_clients(&mut self, count: usize) -> Vec<Arc<WrappedClient>> {
+        // accounts may have been added or removed since the last call
+        let clients = self.clients.snapshot();
+
+        let now = Instant::now();
+        self.backup_until.retain(|_, until| *until > now);
+
+        let usable: Vec<_> = (0..clients.len())
+            .map(|offset| (self.next + offset) % clients.len())
+            .filter(|&index| !clients[index].is_deauthorized())
+            .filter(|&index| clients[index].flood_wait::<GetStarGifts>().is_none())
+            .collect();
+        let (primary, backup): (Vec<_>, Vec<_>) = usable.into_iter().partition(|&index| {
+            !self
+                .backup_until
+                .contains_key(clients[index].phone_number())
+        });
+        let indices: Vec<_> = primary.into_iter().chain(backup).take(count).collect();
+
+        if let Some(&first) = indices.first() {
+            self.next = first + 1;
+        }
+
+        indices
+            .into_iter()
+            .map(|index| clients[index].clone())
+            .collect()
+    }
+
+    // demotes the accounts of a sustained poll failure, so polling switches to the others
+    pub fn fail_over<C: TelegramApi>(&mut self, clients: &[Arc<C>]) {
+        let until = Instant::now() + POLL_FAILOVER_DURATION;
+        for client in clients {
+            tracing::warn!(
+                phone_number = client.phone_number(),
+                "polling failed over to other accounts"
+            );
+            self.backup_until
+                .insert(client.phone_number().to_string(), until);
+        }
+    }
+}
+
+// consecutive failed polls, a failure is only logged until `threshold` polls in a row
+// failed, then polling fails over and the chats are alerted once, with the kind of errors
+pub struct PollFailures {
+    threshold: u32,
+    consecutive: u32,
+}
+
+impl PollFailures {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            consecutive: 0,
+        }
+    }
+
+    // the alert to send when the failures just became sustained
+    pub fn record_failure<C: TelegramApi>(
+        &mut self,
+        errors: &[(Arc<C>, ErrorClass)],
+    ) -> Option<String> {
+        self.consecutive += 1;
+        if self.consecutive != self.threshold {
+            return None;
+        }
+
+        tracing::error!(
+            consecutive = self.consecutive,
+            "catalog polling keeps failing"
+        );
+
+        let mut text = format!(
+            "🚨 Catalog polling failed {} times in a row, switching to other accounts\n",
+            self.consecutive
+        );
+        for (client, class) in errors {
+            let account = match client.label() {
+                Some(label) => format!("{label} ({})", client.phone_number()),
+                None => client.phone_number().to_string(),
+            };
+            writeln!(text, "{account}: {} error", class.as_str()).unwrap();
+        }
+
+        Some(text)
+    }
+
+    // the alert to send when polling recovered from a sustained failure
+    pub fn record_success(&mut self) -> Option<String> {
+        let failed = std::mem::take(&mut self.consecutive);
+        (self.threshold > 0 && failed >= self.threshold)
+            .then(|| format!("✅ Catalog polling recovered after {failed} failed polls"))
+    }
+}
+
+// when the poll loop last got a catalog response, shared with `run_stall_watchdog`
+#[derive(Clone)]
+pub struct PollProgress(Arc<Mutex<PollProgressState>>);
+
+struct PollProgressState {
+    last_success: Instant,
+    // the loop doesn't poll while it buys, which may take long with retries and flood
+    // waits, so that time doesn't count towards a stall
+    buying: bool,
+}
+
+impl Default for PollProgress {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(PollProgressState {
+            last_success: Instant::now(),
+            buying: false,
+        })))
+    }
+}
+
+impl PollProgress {
+    pub fn record_success(&self) {
+        self.0.lock().unwrap().last_success = Instant::now();
+    }
+
+    pub fn start_buying(&self) {
+        self.0.lock().unwrap().buying = true;
+    }
+
+    // the loop is back to polling, counted from now
+    pub fn finish_buying(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.buying = false;
+        state.last_success = Instant::now();
+    }
+
+    fn since_success(&self) -> Duration {
+        let state = self.0.lock().unwrap();
+        if state.buying {
+            Duration::ZERO
+        } else {
+            state.last_success.elapsed()
+        }
+    }
+}
+
+// alerts once the poll loop went `threshold` without a catalog response, whether it keeps
+// failing or hangs, and once it recovers, with `restart` the loop is also told to drop
+// whatever it's stuck on and start over
+pub async fn run_stall_watchdog(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    progress: PollProgress,
+    threshold: Duration,
+    restart: Option<Arc<Notify>>,
+) {
+    let mut stalled = false;
+    let mut restarted_at: Option<Instant> = None;
+
+    loop {
+        tokio::time::sleep(STALL_CHECK_INTERVAL.min(threshold)).await;
+
+        let since_success = progress.since_success();
+        if (since_success >= threshold) != stalled {
+            stalled = !stalled;
+            let text = if stalled {
+                tracing::error!(?since_success, "poll loop stalled");
+                format!(
+                    "🚨 No gift catalog fetched for {}s, new gifts aren't being detected{}",
+                    since_success.as_secs(),
+                    if restart.is_some() {
+                        ", restarting the poll loop"
+                    } else {
+                        ""
+                    }
+                )
+            } else {
+                restarted_at = None;
+                "✅ Gift catalog polling recovered".to_string()
+            };
+
+            if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                tracing::error!(?err, "failed to send stall alert");
+            }
+        }
+
+        // a loop still stuck after a restart is restarted again every `threshold`
+        if stalled
+            && let Some(restart) = &restart
+            && restarted_at.is_none_or(|restarted_at| restarted_at.elapsed() >= threshold)
+        {
+            tracing::warn!("restarting the poll loop");
+            restart.notify_one();
+            restarted_at = Some(Instant::now());
+        }
+    }
+}
+
+pub fn spawn_poll_alert(bot: Arc<Bot>, pool: Arc<SqlitePool>, text: String) {
+    tokio::spawn(async move {
+        if let Err(err) = alert_chats(&bot, &pool, &text).await {
+            tracing::error!(?err, "failed to send poll alert");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telegram_api::mock::{MockClient, rpc_error};
+
+    #[tokio::test]
+    async fn race_returns_the_first_catalog() {
+        let clients = [
+            Arc::new(MockClient::new("a").on::<GetStarGifts>(|| Err(rpc_error("INTERNAL")))),
+            Arc::new(MockClient::new("b").on::<GetStarGifts>(|| Ok(StarGifts::NotModified))),
+        ];
+        let Ok((client, star_gifts)) = race_get_star_gifts(&clients, 0).await else {
+            panic!("no account polled the catalog");
+        };
+        assert_eq!(client.phone_number(), "b");
+        assert!(matches!(star_gifts, StarGifts::NotModified));
+    }
+
+    #[tokio::test]
+    async fn race_collects_every_error() {
+        let clients = [
+            Arc::new(MockClient::new("a").on::<GetStarGifts>(|| Err(rpc_error("INTERNAL")))),
+            Arc::new(MockClient::new("b")),
+        ];
+        let Err(errors) = race_get_star_gifts(&clients, 0).await else {
+            panic!("an account polled the catalog");
+        };
+        let mut phone_numbers: Vec<_> = errors
+            .iter()
+            .map(|(client, _)| client.phone_number())
+            .collect();
+        phone_numbers.sort();
+        assert_eq!(phone_numbers, ["a", "b"]);
+    }
+
+    #[test]
+    fn poll_failures_alert_once() {
+        let mut failures = PollFailures::new(2);
+        let errors = [(
+            Arc::new(MockClient::new("+100").with_label("main")),
+            ErrorClass::Network,
+        )];
+        assert_eq!(failures.record_failure(&errors), None);
+        let alert = failures.record_failure(&errors).unwrap();
+        assert!(alert.contains("main (+100): network error"));
+        assert_eq!(failures.record_failure(&errors), None);
+
+        assert!(failures.record_success().is_some());
+        assert_eq!(failures.record_success(), None);
+    }
+
+    #[test]
+    fn daily_window_parse() {
+        let window = DailyWindow::parse("09:15-10:00").unwrap();
+        assert!(window.contains(9 * 3600 + 15 * 60));
+        assert!(!window.contains(10 * 3600));
+        assert_eq!(window.until_start(9 * 3600), Duration::from_secs(15 * 60));
+
+        // wraps over midnight
+        let window = DailyWindow::parse("23:30 - 00:30").unwrap();
+        assert!(window.contains(23 * 3600 + 45 * 60));
+        assert!(window.contains(15 * 60));
+        assert!(!window.contains(3600));
+        assert_eq!(
+            window.until_start(3600),
+            Duration::from_secs(22 * 3600 + 30 * 60)
+        );
+
+        for invalid in [
+            "",
+            "09:15",
+            "24:00-01:00",
+            "09:60-10:00",
+            "9-10",
+            "ab:cd-10:00",
+        ] {
+            assert!(DailyWindow::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use chrono_tz::Tz;
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    polling::{DailyWindow, secs_of_day},
+    wrapped_client::Clients,
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// what every account is expected to spend during a drop
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub gift_price: i64,
+    pub gifts: u64,
+    // units of each gift per account
+    pub limit: u64,
+}
+
+impl Budget {
+    fn required(&self) -> i64 {
+        self.gift_price
+            .saturating_mul(self.gifts as i64)
+            .saturating_mul(self.limit as i64)
+    }
+}
+
+// checks balances at startup and `lead` before each of `windows` (in `timezone`) starts,
+// alerting about accounts that can't afford the budget
+pub async fn run_preflight_checks(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    budget: Budget,
+    windows: Vec<DailyWindow>,
+    timezone: Tz,
+    lead: Duration,
+) {
+    check(&bot, &pool, &clients, budget, "startup").await;
+
+    loop {
+        let now = secs_of_day(timezone);
+        let Some(wait) = windows
+            .iter()
+            .map(|window| {
+                let until_start = window.until_start(now);
+                match until_start.checked_sub(lead) {
+                    Some(wait) if !wait.is_zero() => wait,
+                    Some(_) => DAY,
+                    None => (until_start + DAY)
+                        .saturating_sub(lead)
+                        .max(Duration::from_secs(1)),
+                }
+            })
+            .min()
+        else {
+            return;
+        };
+
+        tokio::time::sleep(wait).await;
+
+        let reason = format!("drop window in {} min", lead.as_secs() / 60);
+        check(&bot, &pool, &clients, budget, &reason).await;
+    }
+}
+
+async fn check(bot: &Bot, pool: &SqlitePool, clients: &Clients, budget: Budget, reason: &str) {
+    let required = budget.required();
+    let mut shortfalls = String::new();
+
+    for client in clients.snapshot() {
+        if client.is_deauthorized() {
+            continue;
+        }
+
+        let phone_number = client.phone_number();
+        match client.get_stars_balance().await {
+            Ok(balance) if balance < required => {
+                writeln!(
+                    shortfalls,
+                    "{phone_number}: {balance} ⭐, short {} ⭐",
+                    required - balance
+                )
+                .unwrap();
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::error!(?err, phone_number, "failed to get stars balance");
+                writeln!(shortfalls, "{phone_number}: failed to get balance: {err}").unwrap();
+            }
+        }
+    }
+
+    if shortfalls.is_empty() {
+        tracing::info!(reason, required, "preflight passed");
+        return;
+    }
+
+    let text = format!(
+        "🧮 Preflight ({reason}): {} gift(s) × {} at {} ⭐ need {required} ⭐ per account\n\
+        {shortfalls}",
+        budget.gifts, budget.limit, budget.gift_price
+    );
+    if let Err(err) = alert_chats(bot, pool, &text).await {
+        tracing::error!(?err, "failed to send preflight alert");
+    }
+}
@@ -0,0 +1,52 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+// allows bursts of up to `capacity` requests, refilled at `rate` per second
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<State>,
+}
+
+struct State {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: u32) -> Self {
+        let capacity = f64::from(capacity.max(1));
+
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new(State {
+                tokens: capacity,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    // waits until a token is available and takes it, concurrent callers are queued up
+    // by taking tokens ahead of time (the balance goes negative)
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+
+            let now = Instant::now();
+            let refilled = (now - state.refilled_at).as_secs_f64() * self.rate;
+            state.tokens = (state.tokens + refilled).min(self.capacity);
+            state.refilled_at = now;
+
+            state.tokens -= 1.0;
+            if state.tokens >= 0.0 {
+                return;
+            }
+
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        };
+
+        tokio::time::sleep(wait).await;
+    }
+}
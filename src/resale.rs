@@ -0,0 +1,241 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use grammers_client::grammers_tl_types::{
+    enums::{InputPeer, StarGift, payments::ResaleStarGifts},
+    functions::payments::{GetResaleStarGifts, GetSavedStarGifts},
+    types,
+};
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    db::{insert_resale_floor, to_unix_millis},
+    uniques::{SAVED_GIFTS_PAGE_LIMIT, get_saved_gifts},
+    wrapped_client::{Clients, WrappedClient},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    GrammersInvocation(#[from] grammers_client::InvocationError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// the cheapest listings are enough to find the floor and who holds it
+const RESALE_PAGE_LIMIT: i32 = 10;
+
+// a unique gift held by one of the accounts
+struct HeldUnique {
+    phone_number: String,
+    unique: types::StarGiftUnique,
+}
+
+// samples the resale listings of every gift the accounts hold a unique of on `interval`,
+// storing the floor prices, alerting when a floor moved by `floor_change_percent` since
+// the last alert and when a listing of the accounts is no longer the cheapest
+pub async fn run_resale_tracking(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    floor_change_percent: f64,
+    interval: Duration,
+) {
+    // the first sample of every gift is the baseline
+    let mut alerted_floors = HashMap::<i64, i64>::new();
+    let mut undercut = HashSet::<String>::new();
+
+    loop {
+        let mut report = String::new();
+
+        let held = get_held_uniques(&clients).await;
+
+        if let Some(client) = clients
+            .snapshot()
+            .into_iter()
+            .find(|client| !client.is_deauthorized())
+        {
+            for (gift_id, held) in group_by_gift(&held) {
+                let listings = match get_cheapest_listings(&client, gift_id).await {
+                    Ok(t) => t,
+                    Err(err) => {
+                        tracing::error!(?err, gift_id, "failed to get resale listings");
+                        continue;
+                    }
+                };
+
+                let floor = listings.first().and_then(|listing| listing.resell_stars);
+                let sampled_at = to_unix_millis(SystemTime::now());
+                if let Err(err) =
+                    insert_resale_floor(&*pool, gift_id, sampled_at, floor, listings.len()).await
+                {
+                    tracing::error!(?err, gift_id, "failed to record resale floor");
+                }
+
+                let Some(floor) = floor else {
+                    continue;
+                };
+
+                report_floor_change(
+                    &mut alerted_floors,
+                    gift_id,
+                    floor,
+                    floor_change_percent,
+                    &mut report,
+                );
+                report_undercuts(&held, floor, &mut undercut, &mut report);
+            }
+        }
+
+        if !report.is_empty() {
+            let text = format!("🏷️ Resale:\n{report}");
+            if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                tracing::error!(?err, "failed to send resale alert");
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn get_held_uniques(clients: &Clients) -> Vec<HeldUnique> {
+    let mut held = vec![];
+
+    for client in clients.snapshot() {
+        if client.is_deauthorized() {
+            continue;
+        }
+
+        let saved_gifts = get_saved_gifts(
+            &client,
+            GetSavedStarGifts {
+                exclude_unsaved: false,
+                exclude_saved: false,
+                exclude_unlimited: true,
+                exclude_unique: false,
+                sort_by_value: false,
+                exclude_upgradable: true,
+                exclude_unupgradable: true,
+                peer: InputPeer::PeerSelf,
+                collection_id: None,
+                offset: String::new(),
+                limit: SAVED_GIFTS_PAGE_LIMIT,
+            },
+        )
+        .await;
+
+        match saved_gifts {
+            Ok(saved_gifts) => {
+                held.extend(saved_gifts.into_iter().filter_map(
+                    |saved_gift| match saved_gift.gift {
+                        StarGift::Unique(unique) => Some(HeldUnique {
+                            phone_number: client.phone_number().to_string(),
+                            unique,
+                        }),
+                        StarGift::Gift(_) => None,
+                    },
+                ))
+            }
+            Err(err) => tracing::error!(
+                ?err,
+                phone_number = client.phone_number(),
+                "failed to get saved gifts"
+            ),
+        }
+    }
+
+    held
+}
+
+fn group_by_gift(held: &[HeldUnique]) -> BTreeMap<i64, Vec<&HeldUnique>> {
+    let mut grouped = BTreeMap::<_, Vec<_>>::new();
+    for held in held {
+        grouped.entry(held.unique.gift_id).or_default().push(held);
+    }
+    grouped
+}
+
+// cheapest first
+async fn get_cheapest_listings(
+    client: &WrappedClient,
+    gift_id: i64,
+) -> Result<Vec<types::StarGiftUnique>> {
+    let ResaleStarGifts::Gifts(resale) = client
+        .invoke(&GetResaleStarGifts {
+            sort_by_price: true,
+            sort_by_num: false,
+            attributes_hash: None,
+            gift_id,
+            attributes: None,
+            offset: String::new(),
+            limit: RESALE_PAGE_LIMIT,
+        })
+        .await?;
+
+    Ok(resale
+        .gifts
+        .into_iter()
+        .filter_map(|gift| match gift {
+            StarGift::Unique(unique) => Some(unique),
+            StarGift::Gift(_) => None,
+        })
+        .collect())
+}
+
+fn report_floor_change(
+    alerted_floors: &mut HashMap<i64, i64>,
+    gift_id: i64,
+    floor: i64,
+    floor_change_percent: f64,
+    report: &mut String,
+) {
+    let Some(&alerted_floor) = alerted_floors.get(&gift_id) else {
+        alerted_floors.insert(gift_id, floor);
+        return;
+    };
+
+    let change_percent = (floor - alerted_floor) as f64 / alerted_floor.max(1) as f64 * 100.0;
+    if change_percent.abs() < floor_change_percent {
+        return;
+    }
+
+    alerted_floors.insert(gift_id, floor);
+    writeln!(
+        report,
+        "{} gift {gift_id} floor {alerted_floor} ⭐ → {floor} ⭐ ({change_percent:+.1}%)",
+        if change_percent < 0.0 { "📉" } else { "📈" }
+    )
+    .unwrap();
+}
+
+// a listing is undercut once another one is cheaper than it, reported once until it's
+// the cheapest again or delisted
+fn report_undercuts(
+    held: &[&HeldUnique],
+    floor: i64,
+    undercut: &mut HashSet<String>,
+    report: &mut String,
+) {
+    for held in held {
+        let unique = &held.unique;
+        let Some(price) = unique.resell_stars.filter(|&price| price > floor) else {
+            undercut.remove(&unique.slug);
+            continue;
+        };
+
+        if undercut.insert(unique.slug.clone()) {
+            writeln!(
+                report,
+                "⚠️ {}: {} #{} listed at {price} ⭐ undercut, floor {floor} ⭐ https://t.me/nft/{}",
+                held.phone_number, unique.title, unique.num, unique.slug,
+            )
+            .unwrap();
+        }
+    }
+}
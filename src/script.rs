@@ -0,0 +1,159 @@
+use std::{collections::HashMap, path::Path};
+
+use futures::future::join_all;
+use grammers_client::grammers_tl_types::types;
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+
+use crate::{
+    core::{BuyLimits, DEFAULT_BUY_LIMIT},
+    wrapped_client::Clients,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Rhai(#[from] Box<rhai::EvalAltResult>),
+    #[error("`decide` returned {0}, expected a bool, an int or ()")]
+    UnexpectedDecision(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// keeps a runaway script from stalling the poll loop
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// a rhai script defining `decide(gift, balances)`, called for every detected gift with
+// the gift's fields and the stars balance of every account by phone number, it returns
+// `true` to buy the default number of units, an int to buy that many per account,
+// or `false`/`()` to skip the gift
+pub struct BuyScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl BuyScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let ast = engine.compile_file(path.into())?;
+
+        Ok(Self { engine, ast })
+    }
+
+    // units per account, `None` skips the gift
+    pub fn decide(
+        &self,
+        gift: &types::StarGift,
+        balances: &HashMap<String, i64>,
+        default_limit: u64,
+    ) -> Result<Option<u64>> {
+        let balances: Map = balances
+            .iter()
+            .map(|(phone_number, balance)| (phone_number.as_str().into(), Dynamic::from(*balance)))
+            .collect();
+
+        let decision: Dynamic = self.engine.call_fn(
+            &mut Scope::new(),
+            &self.ast,
+            "decide",
+            (gift_map(gift), balances),
+        )?;
+
+        if decision.is_unit() {
+            Ok(None)
+        } else if let Ok(buy) = decision.as_bool() {
+            Ok(buy.then_some(default_limit))
+        } else if let Ok(units) = decision.as_int() {
+            Ok((units > 0).then_some(units as u64))
+        } else {
+            Err(Error::UnexpectedDecision(decision.type_name().to_string()))
+        }
+    }
+
+    // replaces the declarative rules, the balances are fetched only when there are gifts
+    // to decide on, gifts keep their order and failing decisions skip the gift
+    pub async fn select_gifts_to_buy(
+        &self,
+        clients: &Clients,
+        gifts: Vec<types::StarGift>,
+        default_limit: Option<u64>,
+    ) -> (Vec<types::StarGift>, BuyLimits) {
+        let mut limits = BuyLimits::from(default_limit);
+        if gifts.is_empty() {
+            return (gifts, limits);
+        }
+
+        let balances: HashMap<_, _> = join_all(
+            clients
+                .snapshot()
+                .into_iter()
+                .filter(|client| !client.is_deauthorized())
+                .map(|client| async move {
+                    let balance = client
+                        .get_stars_balance()
+                        .await
+                        .inspect_err(|err| {
+                            tracing::error!(
+                                ?err,
+                                phone_number = client.phone_number(),
+                                "failed to get stars balance"
+                            )
+                        })
+                        .ok()?;
+                    Some((client.phone_number().to_string(), balance))
+                }),
+        )
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let default_limit = default_limit.unwrap_or(DEFAULT_BUY_LIMIT);
+
+        let gifts = gifts
+            .into_iter()
+            .filter(|gift| match self.decide(gift, &balances, default_limit) {
+                Ok(Some(limit)) => {
+                    limits.per_gift.insert(gift.id, limit);
+                    true
+                }
+                Ok(None) => false,
+                Err(err) => {
+                    tracing::error!(?err, gift_id = gift.id, "buy script failed");
+                    false
+                }
+            })
+            .collect();
+
+        (gifts, limits)
+    }
+}
+
+fn gift_map(gift: &types::StarGift) -> Map {
+    let optional = |value: Option<i64>| value.map_or(Dynamic::UNIT, Dynamic::from);
+
+    Map::from_iter([
+        ("id".into(), Dynamic::from(gift.id)),
+        ("stars".into(), Dynamic::from(gift.stars)),
+        ("limited".into(), Dynamic::from(gift.limited)),
+        ("sold_out".into(), Dynamic::from(gift.sold_out)),
+        (
+            "require_premium".into(),
+            Dynamic::from(gift.require_premium),
+        ),
+        (
+            "supply".into(),
+            optional(gift.availability_total.map(i64::from)),
+        ),
+        (
+            "remains".into(),
+            optional(gift.availability_remains.map(i64::from)),
+        ),
+        ("upgrade_stars".into(), optional(gift.upgrade_stars)),
+        (
+            "title".into(),
+            gift.title.clone().map_or(Dynamic::UNIT, Dynamic::from),
+        ),
+    ])
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use grammers_client::grammers_tl_types::functions::payments::{
+    GetPaymentForm, GetStarGifts, SendStarsForm,
+};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    db::{self, get_purchase_latencies, to_unix_millis},
+    wrapped_client::WrappedClient,
+};
+
+// in-process counters of the current day in the configured timezone, shared by the poll
+// loop, purchases and the bot, they start over with the process and at midnight
+pub static STATS: Stats = Stats::new();
+
+#[derive(Debug)]
+pub struct Stats {
+    // days since the common era the counters belong to
+    day: AtomicI64,
+    // set once at startup, UTC until then
+    timezone: OnceLock<Tz>,
+    gifts_detected: AtomicU64,
+    buys_succeeded: AtomicU64,
+    buys_failed: AtomicU64,
+    stars_spent: AtomicI64,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            day: AtomicI64::new(0),
+            timezone: OnceLock::new(),
+            gifts_detected: AtomicU64::new(0),
+            buys_succeeded: AtomicU64::new(0),
+            buys_failed: AtomicU64::new(0),
+            stars_spent: AtomicI64::new(0),
+        }
+    }
+
+    // only the first call takes effect
+    pub fn set_timezone(&self, timezone: Tz) {
+        if self.timezone.set(timezone).is_err() {
+            tracing::warn!(%timezone, "timezone of the stats already set");
+        }
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone.get().copied().unwrap_or(Tz::UTC)
+    }
+
+    // updates racing the reset may be lost, which is fine for counters
+    fn roll_over(&self) {
+        let timezone = self.timezone();
+        let today = i64::from(
+            Utc::now()
+                .with_timezone(&timezone)
+                .date_naive()
+                .num_days_from_ce(),
+        );
+        if self.day.swap(today, Ordering::Relaxed) != today {
+            self.gifts_detected.store(0, Ordering::Relaxed);
+            self.buys_succeeded.store(0, Ordering::Relaxed);
+            self.buys_failed.store(0, Ordering::Relaxed);
+            self.stars_spent.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_detected(&self, gifts: usize) {
+        self.roll_over();
+        self.gifts_detected
+            .fetch_add(gifts as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_buy(&self, stars: i64) {
+        self.roll_over();
+        self.buys_succeeded.fetch_add(1, Ordering::Relaxed);
+        self.stars_spent.fetch_add(stars, Ordering::Relaxed);
+    }
+
+    pub fn record_failed_buy(&self) {
+        self.roll_over();
+        self.buys_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, clients: &[Arc<WrappedClient>]) -> String {
+        self.roll_over();
+
+        let flood_waits = clients
+            .iter()
+            .filter(|client| {
+                client.flood_wait::<GetStarGifts>().is_some()
+                    || client.flood_wait::<GetPaymentForm>().is_some()
+                    || client.flood_wait::<SendStarsForm>().is_some()
+            })
+            .count();
+
+        format!(
+            "Today ({}): {} gifts detected, {} bought, {} failed, {} ⭐ spent\n\
+            Accounts in flood wait: {flood_waits}\n",
+            self.timezone(),
+            self.gifts_detected.load(Ordering::Relaxed),
+            self.buys_succeeded.load(Ordering::Relaxed),
+            self.buys_failed.load(Ordering::Relaxed),
+            self.stars_spent.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// failed sends to the trusted chats since the start, shared by everything notifying them
+pub static DELIVERY: Delivery = Delivery::new();
+
+pub const DEFAULT_UNDELIVERABLE_AFTER: u32 = 3;
+
+#[derive(Debug, Default)]
+struct ChatDelivery {
+    failures: u64,
+    // failures since the last successful send
+    consecutive: u32,
+    last_error: String,
+}
+
+#[derive(Debug)]
+pub struct Delivery {
+    // a chat is undeliverable once more sends than this failed in a row
+    undeliverable_after: AtomicU32,
+    chats: Mutex<BTreeMap<i64, ChatDelivery>>,
+}
+
+impl Delivery {
+    const fn new() -> Self {
+        Self {
+            undeliverable_after: AtomicU32::new(DEFAULT_UNDELIVERABLE_AFTER),
+            chats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set_undeliverable_after(&self, attempts: u32) {
+        self.undeliverable_after.store(attempts, Ordering::Relaxed);
+    }
+
+    // the alert to send when the chat was undeliverable until now
+    pub fn record_sent(&self, chat_id: i64) -> Option<String> {
+        let mut chats = self.chats.lock().unwrap();
+        let failed = std::mem::take(&mut chats.get_mut(&chat_id)?.consecutive);
+
+        (failed > self.undeliverable_after.load(Ordering::Relaxed)).then(|| {
+            format!(
+                "✅ Notifications to chat {chat_id} are delivered again after {failed} failures"
+            )
+        })
+    }
+
+    // the alert to send when the chat just became undeliverable
+    pub fn record_failed(&self, chat_id: i64, err: &impl Display) -> Option<String> {
+        let mut chats = self.chats.lock().unwrap();
+        let chat = chats.entry(chat_id).or_default();
+        chat.failures += 1;
+        chat.consecutive += 1;
+        chat.last_error = err.to_string();
+
+        (chat.consecutive == self.undeliverable_after.load(Ordering::Relaxed) + 1).then(|| {
+            format!(
+                "🚨 Notifications to chat {chat_id} failed {} times in a row, it won't see \
+                new gifts until fixed: {err}",
+                chat.consecutive
+            )
+        })
+    }
+
+    pub fn report(&self) -> String {
+        let chats = self.chats.lock().unwrap();
+        if chats.is_empty() {
+            return "Notification failures: none\n".to_string();
+        }
+
+        let mut report = "Notification failures:\n".to_string();
+        for (chat_id, chat) in chats.iter() {
+            write!(report, "{chat_id}: {} total", chat.failures).unwrap();
+            if chat.consecutive > 0 {
+                write!(
+                    report,
+                    ", {} in a row, last: {}",
+                    chat.consecutive, chat.last_error
+                )
+                .unwrap();
+            }
+            report.push('\n');
+        }
+
+        report
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Percentiles {
+    pub p50: i64,
+    pub p95: i64,
+}
+
+impl Percentiles {
+    // nearest-rank method
+    pub fn from_values(mut values: Vec<i64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort_unstable();
+
+        let rank = |p: usize| values[(values.len() * p).div_ceil(100).max(1) - 1];
+
+        Some(Self {
+            p50: rank(50),
+            p95: rank(95),
+        })
+    }
+}
+
+// in ms, `None` without successful purchases
+#[derive(Debug, Serialize)]
+pub struct LatencyStats {
+    pub purchases: usize,
+    pub detection_to_payment_form: Option<Percentiles>,
+    pub detection_to_sent: Option<Percentiles>,
+}
+
+pub async fn latency_stats(pool: &SqlitePool, window: Duration) -> db::Result<LatencyStats> {
+    let since = to_unix_millis(SystemTime::now() - window);
+    let latencies = get_purchase_latencies(pool, since).await?;
+
+    let purchases = latencies.len();
+    let (payment_form, sent): (Vec<_>, Vec<_>) = latencies.into_iter().unzip();
+
+    Ok(LatencyStats {
+        purchases,
+        detection_to_payment_form: Percentiles::from_values(payment_form),
+        detection_to_sent: Percentiles::from_values(sent),
+    })
+}
+
+pub async fn latency_report(pool: &SqlitePool, window: Duration) -> db::Result<String> {
+    let stats = latency_stats(pool, window).await?;
+
+    let mut report = format!(
+        "Successful purchases in the last {}h: {}\n",
+        window.as_secs() / 3600,
+        stats.purchases
+    );
+
+    for (label, percentiles) in [
+        ("Detection → payment form", stats.detection_to_payment_form),
+        ("Detection → sent", stats.detection_to_sent),
+    ] {
+        match percentiles {
+            Some(Percentiles { p50, p95 }) => {
+                writeln!(report, "{label}: p50 {p50} ms, p95 {p95} ms").unwrap()
+            }
+            None => writeln!(report, "{label}: no data").unwrap(),
+        }
+    }
+
+    Ok(report)
+}
+
+// YYYY-MM-DD HH:MM in the configured timezone, from unix millis
+pub fn format_time(millis: i64) -> String {
+    DateTime::from_timestamp_millis(millis).map_or_else(
+        || millis.to_string(),
+        |time| {
+            time.with_timezone(&STATS.timezone())
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        },
+    )
+}
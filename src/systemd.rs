@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+use sd_notify::NotifyState;
+
+// no-op when not running under systemd (NOTIFY_SOCKET is unset)
+pub fn notify_ready() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        tracing::error!(?err, "failed to notify systemd readiness");
+    }
+}
+
+pub struct Watchdog {
+    interval: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn from_env() -> Self {
+        let mut usec = 0;
+        let interval = sd_notify::watchdog_enabled(false, &mut usec)
+            // ping twice per WatchdogSec as recommended by sd_watchdog_enabled(3)
+            .then(|| Duration::from_micros(usec) / 2);
+
+        tracing::debug!(?interval, "systemd watchdog");
+
+        Self {
+            interval,
+            last_ping: None,
+        }
+    }
+
+    pub fn ping(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+
+        if self
+            .last_ping
+            .is_some_and(|last_ping| last_ping.elapsed() < interval)
+        {
+            return;
+        }
+
+        if let Err(err) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+            tracing::error!(?err, "failed to ping systemd watchdog");
+        }
+
+        self.last_ping = Some(Instant::now());
+    }
+}
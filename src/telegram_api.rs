@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+use grammers_client::{
+    InvocationError,
+    grammers_tl_types::{
+        RemoteCall,
+        enums::{InputInvoice, InputPeer, payments::PaymentForm},
+    },
+};
+use tokio::sync::SemaphorePermit;
+
+use crate::{
+    core::{self, BuyGiftsDestination},
+    wrapped_client::WrappedClient,
+};
+
+// what the buy pipeline needs from an account, so it can run against something other
+// than a connected `WrappedClient`
+pub trait TelegramApi: Send + Sync {
+    fn phone_number(&self) -> &str;
+
+    fn label(&self) -> Option<&str>;
+
+    // how messages to the chats name the account
+    fn display_name(&self) -> &str {
+        self.label().unwrap_or(self.phone_number())
+    }
+
+    fn is_deauthorized(&self) -> bool;
+
+    fn invoke<R>(
+        &self,
+        request: &R,
+    ) -> impl Future<Output = Result<R::Return, InvocationError>> + Send
+    where
+        R: RemoteCall + Sync,
+        R::Return: Send;
+
+    // remaining flood wait for `R`
+    fn flood_wait<R: RemoteCall>(&self) -> Option<Duration>;
+
+    // purchases of the account are made while the permit is held
+    fn begin_purchases(&self) -> impl Future<Output = SemaphorePermit<'_>> + Send;
+
+    fn put_payment_form(&self, invoice: &InputInvoice, payment_form: PaymentForm);
+
+    fn take_payment_form(&self, invoice: &InputInvoice) -> Option<PaymentForm>;
+
+    // known without a request, purchases don't wait for it
+    fn is_premium(&self) -> bool;
+
+    fn resolve_destination(
+        &self,
+        dest: &BuyGiftsDestination,
+    ) -> impl Future<Output = core::Result<InputPeer>> + Send;
+}
+
+impl TelegramApi for WrappedClient {
+    fn phone_number(&self) -> &str {
+        WrappedClient::phone_number(self)
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.account().label.as_deref()
+    }
+
+    fn is_deauthorized(&self) -> bool {
+        WrappedClient::is_deauthorized(self)
+    }
+
+    fn invoke<R>(
+        &self,
+        request: &R,
+    ) -> impl Future<Output = Result<R::Return, InvocationError>> + Send
+    where
+        R: RemoteCall + Sync,
+        R::Return: Send,
+    {
+        WrappedClient::invoke(self, request)
+    }
+
+    fn flood_wait<R: RemoteCall>(&self) -> Option<Duration> {
+        WrappedClient::flood_wait::<R>(self)
+    }
+
+    fn begin_purchases(&self) -> impl Future<Output = SemaphorePermit<'_>> + Send {
+        WrappedClient::begin_purchases(self)
+    }
+
+    fn put_payment_form(&self, invoice: &InputInvoice, payment_form: PaymentForm) {
+        WrappedClient::put_payment_form(self, invoice, payment_form)
+    }
+
+    fn take_payment_form(&self, invoice: &InputInvoice) -> Option<PaymentForm> {
+        WrappedClient::take_payment_form(self, invoice)
+    }
+
+    fn is_premium(&self) -> bool {
+        WrappedClient::is_premium(self)
+    }
+
+    fn resolve_destination(
+        &self,
+        dest: &BuyGiftsDestination,
+    ) -> impl Future<Output = core::Result<InputPeer>> + Send {
+        dest.resolve(self)
+    }
+}
+
+// an account answering each method with a canned response, for tests of the buy pipeline
+#[cfg(test)]
+pub mod mock {
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use grammers_client::{
+        InvocationError, RpcError,
+        grammers_tl_types::{
+            Deserializable, Identifiable, RemoteCall, Serializable,
+            enums::{InputInvoice, InputPeer, payments::PaymentForm},
+        },
+    };
+    use tokio::sync::{Semaphore, SemaphorePermit};
+
+    use super::TelegramApi;
+    use crate::core::{self, BuyGiftsDestination};
+
+    type Respond = Box<dyn Fn() -> Result<Vec<u8>, InvocationError> + Send + Sync>;
+
+    // methods without a response fail as if Telegram didn't know them
+    pub struct MockClient {
+        phone_number: String,
+        label: Option<String>,
+        premium: bool,
+        // serialized responses by constructor ID of the method
+        responses: HashMap<u32, Respond>,
+        // constructor IDs of the requests made, in order
+        requests: Mutex<Vec<u32>>,
+        purchases: Semaphore,
+        payment_forms: Mutex<HashMap<Vec<u8>, PaymentForm>>,
+    }
+
+    impl MockClient {
+        pub fn new(phone_number: &str) -> Self {
+            Self {
+                phone_number: phone_number.to_string(),
+                label: None,
+                premium: false,
+                responses: HashMap::new(),
+                requests: Mutex::new(vec![]),
+                purchases: Semaphore::new(1),
+                payment_forms: Mutex::new(HashMap::new()),
+            }
+        }
+
+        pub fn with_label(self, label: &str) -> Self {
+            Self {
+                label: Some(label.to_string()),
+                ..self
+            }
+        }
+
+        // answers every `R` request with what `respond` returns at the time
+        pub fn on<R>(
+            mut self,
+            respond: impl Fn() -> Result<R::Return, InvocationError> + Send + Sync + 'static,
+        ) -> Self
+        where
+            R: RemoteCall + Identifiable,
+            R::Return: Serializable,
+        {
+            self.responses.insert(
+                R::CONSTRUCTOR_ID,
+                Box::new(move || respond().map(|response| response.to_bytes())),
+            );
+            self
+        }
+
+        // how many `R` requests were made
+        pub fn calls<R: Identifiable>(&self) -> usize {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|&&id| id == R::CONSTRUCTOR_ID)
+                .count()
+        }
+    }
+
+    pub fn rpc_error(name: &str) -> InvocationError {
+        InvocationError::Rpc(RpcError {
+            code: 400,
+            name: name.to_string(),
+            value: None,
+            caused_by: None,
+        })
+    }
+
+    impl TelegramApi for MockClient {
+        fn phone_number(&self) -> &str {
+            &self.phone_number
+        }
+
+        fn label(&self) -> Option<&str> {
+            self.label.as_deref()
+        }
+
+        fn is_deauthorized(&self) -> bool {
+            false
+        }
+
+        fn invoke<R>(
+            &self,
+            request: &R,
+        ) -> impl Future<Output = Result<R::Return, InvocationError>> + Send
+        where
+            R: RemoteCall + Sync,
+            R::Return: Send,
+        {
+            // every request starts with the constructor ID of its method
+            let id = u32::from_le_bytes(request.to_bytes()[..4].try_into().unwrap());
+            self.requests.lock().unwrap().push(id);
+
+            let result = match self.responses.get(&id) {
+                Some(respond) => respond().map(|response| {
+                    R::Return::from_bytes(&response).expect("response of the method's type")
+                }),
+                None => Err(rpc_error("METHOD_NOT_MOCKED")),
+            };
+            std::future::ready(result)
+        }
+
+        fn flood_wait<R: RemoteCall>(&self) -> Option<Duration> {
+            None
+        }
+
+        fn begin_purchases(&self) -> impl Future<Output = SemaphorePermit<'_>> + Send {
+            async {
+                self.purchases
+                    .acquire()
+                    .await
+                    .expect("purchases semaphore is never closed")
+            }
+        }
+
+        fn put_payment_form(&self, invoice: &InputInvoice, payment_form: PaymentForm) {
+            self.payment_forms
+                .lock()
+                .unwrap()
+                .insert(invoice.to_bytes(), payment_form);
+        }
+
+        fn take_payment_form(&self, invoice: &InputInvoice) -> Option<PaymentForm> {
+            self.payment_forms
+                .lock()
+                .unwrap()
+                .remove(&invoice.to_bytes())
+        }
+
+        fn is_premium(&self) -> bool {
+            self.premium
+        }
+
+        // every destination is the account itself
+        fn resolve_destination(
+            &self,
+            _dest: &BuyGiftsDestination,
+        ) -> impl Future<Output = core::Result<InputPeer>> + Send {
+            std::future::ready(Ok(InputPeer::PeerSelf))
+        }
+    }
+}
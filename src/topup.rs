@@ -0,0 +1,258 @@
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use grammers_client::grammers_tl_types::{
+    enums::{
+        InputInvoice, InputPaymentCredentials, InputStorePaymentPurpose, PaymentSavedCredentials,
+        StarsTopupOption, payments::PaymentForm,
+    },
+    functions::payments::{GetPaymentForm, GetStarsTopupOptions, SendPaymentForm},
+    types,
+};
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    db::{self, get_last_topup_at, insert_topup, to_unix_millis},
+    wrapped_client::{self, Clients, WrappedClient},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    WrappedClient(#[from] wrapped_client::Error),
+    #[error(transparent)]
+    GrammersInvocation(#[from] grammers_client::InvocationError),
+    #[error("no top-up option for {0} stars")]
+    TopupOptionNotFound(i64),
+    #[error("no saved payment method")]
+    NoSavedCredentials,
+    #[error("unexpected payment form")]
+    UnexpectedPaymentForm,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// a top-up isn't reflected in the balance right away, this keeps it from being repeated,
+// attempts are stored, so restarts don't reset it
+const AUTO_TOPUP_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+// seconds the temporary password authorizing the saved payment method is valid for
+const TMP_PASSWORD_PERIOD: i32 = 60;
+
+// buys `stars` (one of the top-up options) with the first saved payment method
+// of accounts whose balance is below `threshold`
+#[derive(Debug, Clone, Copy)]
+pub struct AutoTopup {
+    pub threshold: i64,
+    pub stars: i64,
+}
+
+// alerts once when an account's balance drops below `threshold`, listing the top-up
+// options with links to buy the missing stars, and again only after it was refilled,
+// `auto_topup` refills accounts without waiting for admins
+pub async fn run_balance_checks(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    threshold: Option<i64>,
+    auto_topup: Option<AutoTopup>,
+    interval: Duration,
+) {
+    let mut low = HashSet::new();
+
+    loop {
+        let mut balances = Vec::new();
+
+        for client in clients.snapshot() {
+            if client.is_deauthorized() {
+                continue;
+            }
+
+            let phone_number = client.phone_number().to_string();
+            let balance = match client.get_stars_balance().await {
+                Ok(t) => t,
+                Err(err) => {
+                    tracing::error!(?err, phone_number, "failed to get stars balance");
+                    continue;
+                }
+            };
+
+            if let Some(auto_topup) = auto_topup
+                && balance < auto_topup.threshold
+                && begin_topup(&pool, &phone_number, auto_topup.stars).await
+            {
+                let text = match topup(&client, auto_topup.stars).await {
+                    Ok(()) => {
+                        tracing::info!(phone_number, stars = auto_topup.stars, "topped up");
+                        format!("💳 {phone_number}: topped up {} ⭐", auto_topup.stars)
+                    }
+                    Err(err) => {
+                        tracing::error!(?err, phone_number, "failed to top up");
+                        format!("❌ {phone_number}: failed to top up: {err}")
+                    }
+                };
+
+                if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                    tracing::error!(?err, phone_number, "failed to send top-up alert");
+                }
+            }
+
+            match threshold {
+                Some(threshold) if balance < threshold => {
+                    if low.insert(phone_number.clone()) {
+                        balances.push((phone_number, balance));
+                    }
+                }
+                _ => {
+                    low.remove(&phone_number);
+                }
+            }
+        }
+
+        if let Some(threshold) = threshold
+            && let Some(client) = clients.snapshot().first()
+            && !balances.is_empty()
+        {
+            let text = match topup_options(client).await {
+                Ok(options) => low_balance_report(&balances, threshold, &options),
+                Err(err) => {
+                    tracing::error!(?err, "failed to get stars top-up options");
+                    low_balance_report(&balances, threshold, &[])
+                }
+            };
+
+            if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                tracing::error!(?err, "failed to send low balance alert");
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// records the attempt once the cooldown since the last one has passed, a history that
+// can't be read or written counts as a recent attempt, so nothing is bought twice
+async fn begin_topup(pool: &SqlitePool, phone_number: &str, stars: i64) -> bool {
+    let now = to_unix_millis(SystemTime::now());
+    let result = async {
+        let last_topup_at = get_last_topup_at(pool, phone_number).await?;
+        if last_topup_at.is_some_and(|at| now - at < AUTO_TOPUP_COOLDOWN.as_millis() as i64) {
+            return Ok(false);
+        }
+        insert_topup(pool, phone_number, stars, now).await?;
+        Ok::<_, db::Error>(true)
+    }
+    .await;
+
+    result
+        .inspect_err(|err| tracing::error!(?err, phone_number, "failed to check top-ups"))
+        .unwrap_or(false)
+}
+
+pub async fn topup_options(client: &WrappedClient) -> Result<Vec<types::StarsTopupOption>> {
+    Ok(client
+        .invoke(&GetStarsTopupOptions {})
+        .await?
+        .into_iter()
+        .map(|option| {
+            let StarsTopupOption::Option(option) = option;
+            option
+        })
+        .collect())
+}
+
+// the payment form of the top-up invoice, paid like in the official apps
+pub async fn topup(client: &WrappedClient, stars: i64) -> Result<()> {
+    let option = topup_options(client)
+        .await?
+        .into_iter()
+        .find(|option| option.stars == stars)
+        .ok_or(Error::TopupOptionNotFound(stars))?;
+
+    let invoice = InputInvoice::Stars(types::InputInvoiceStars {
+        purpose: InputStorePaymentPurpose::StarsTopup(types::InputStorePaymentStarsTopup {
+            stars: option.stars,
+            currency: option.currency,
+            amount: option.amount,
+        }),
+    });
+
+    let PaymentForm::Form(form) = client
+        .invoke(&GetPaymentForm {
+            invoice: invoice.clone(),
+            theme_params: None,
+        })
+        .await?
+    else {
+        return Err(Error::UnexpectedPaymentForm);
+    };
+
+    let credentials = form
+        .saved_credentials
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .ok_or(Error::NoSavedCredentials)?;
+    let PaymentSavedCredentials::Card(credentials) = credentials;
+
+    let tmp_password = client.get_tmp_password(TMP_PASSWORD_PERIOD).await?;
+
+    client
+        .invoke(&SendPaymentForm {
+            form_id: form.form_id,
+            invoice,
+            requested_info_id: None,
+            shipping_option_id: None,
+            credentials: InputPaymentCredentials::Saved(types::InputPaymentCredentialsSaved {
+                id: credentials.id,
+                tmp_password,
+            }),
+            tip_amount: None,
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn low_balance_report(
+    balances: &[(String, i64)],
+    threshold: i64,
+    options: &[types::StarsTopupOption],
+) -> String {
+    let mut report = format!("🪫 Stars balance below {threshold} ⭐:\n");
+
+    for (phone_number, balance) in balances {
+        // opens the top-up screen of whichever account follows the link
+        let missing = threshold - balance;
+        writeln!(
+            report,
+            "{phone_number}: {balance} ⭐, top up: tg://stars_topup?balance={missing}&purpose=gifts"
+        )
+        .unwrap();
+    }
+
+    if !options.is_empty() {
+        report.push_str("\nTop-up options:\n");
+        for option in options.iter().filter(|option| !option.extended) {
+            writeln!(
+                report,
+                "{} ⭐ for {}",
+                option.stars,
+                format_price(option.amount, &option.currency)
+            )
+            .unwrap();
+        }
+    }
+
+    report
+}
+
+// amounts are in the smallest units of the currency, which have 2 decimals for most
+fn format_price(amount: i64, currency: &str) -> String {
+    format!("{}.{:02} {currency}", amount / 100, amount % 100)
+}
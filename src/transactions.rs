@@ -0,0 +1,194 @@
+use std::{
+    fmt::Write,
+    mem,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use grammers_client::grammers_tl_types::{
+    enums::{InputPeer, StarGift, StarsAmount, StarsTransaction, payments::StarsStatus},
+    functions::payments::GetStarsTransactions,
+};
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    db::{
+        self, NewStarsTransaction, has_stars_transactions, insert_stars_transaction,
+        is_purchase_transaction, to_unix_millis,
+    },
+    wrapped_client::{Clients, WrappedClient},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] db::Error),
+    #[error(transparent)]
+    GrammersInvocation(#[from] grammers_client::InvocationError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PAGE_LIMIT: i32 = 100;
+// bounds the initial import of long histories
+const MAX_PAGES: usize = 20;
+// purchases get their transactions recorded once the account is done with the job, so
+// spends are matched against them only after this long
+const UNEXPECTED_SPEND_GRACE: Duration = Duration::from_secs(10 * 60);
+
+// stores the stars transactions of every account on `interval`, alerting about refunds
+// and spends that weren't made by a purchase of this bot
+pub async fn run_transactions_sync(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    interval: Duration,
+) {
+    // spends still waiting for UNEXPECTED_SPEND_GRACE
+    let mut spends = vec![];
+
+    loop {
+        let mut report = String::new();
+
+        for client in clients.snapshot() {
+            if client.is_deauthorized() {
+                continue;
+            }
+
+            if let Err(err) = sync(&pool, &client, &mut report, &mut spends).await {
+                tracing::error!(
+                    ?err,
+                    phone_number = client.phone_number(),
+                    "failed to sync stars transactions"
+                );
+            }
+        }
+
+        let matched_before = to_unix_millis(SystemTime::now() - UNEXPECTED_SPEND_GRACE);
+        for spend in mem::take(&mut spends) {
+            if spend.date > matched_before {
+                spends.push(spend);
+                continue;
+            }
+            if let Err(err) = report_spend(&pool, &spend, &mut report).await {
+                tracing::error!(?err, ?spend, "failed to match spend");
+            }
+        }
+
+        if !report.is_empty() {
+            let text = format!("💫 Stars transactions:\n{report}");
+            if let Err(err) = alert_chats(&bot, &pool, &text).await {
+                tracing::error!(?err, "failed to report stars transactions");
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// newest first, stops at the first page with an already stored transaction, new spends
+// are pushed to `spends` to be matched against the purchases later
+async fn sync(
+    pool: &SqlitePool,
+    client: &WrappedClient,
+    report: &mut String,
+    spends: &mut Vec<NewStarsTransaction>,
+) -> Result<()> {
+    let phone_number = client.phone_number();
+    // the first import is stored silently
+    let is_initial = !has_stars_transactions(pool, phone_number).await?;
+
+    let mut offset = String::new();
+    let mut inserted = 0;
+
+    for _ in 0..MAX_PAGES {
+        let StarsStatus::Status(status) = client
+            .invoke(&GetStarsTransactions {
+                subscription_id: None,
+                inbound: false,
+                outbound: false,
+                ascending: false,
+                peer: InputPeer::PeerSelf,
+                offset,
+                limit: PAGE_LIMIT,
+            })
+            .await?;
+
+        let mut reached_stored = false;
+
+        for transaction in status.history.unwrap_or_default() {
+            let StarsTransaction::Transaction(transaction) = transaction;
+            let StarsAmount::Amount(stars) = transaction.stars;
+
+            let transaction = NewStarsTransaction {
+                phone_number: phone_number.to_string(),
+                transaction_id: transaction.id,
+                stars: stars.amount,
+                date: i64::from(transaction.date) * 1000,
+                gift_id: match transaction.stargift {
+                    Some(StarGift::Gift(gift)) => Some(gift.id),
+                    _ => None,
+                },
+                msg_id: transaction.msg_id,
+                refund: transaction.refund,
+                pending: transaction.pending,
+                failed: transaction.failed,
+            };
+
+            if !insert_stars_transaction(pool, &transaction).await? {
+                reached_stored = true;
+                continue;
+            }
+            inserted += 1;
+
+            if is_initial {
+                continue;
+            }
+            if transaction.refund {
+                writeln!(
+                    report,
+                    "↩️ {}: refund of {} ⭐ ({})",
+                    transaction.phone_number, transaction.stars, transaction.transaction_id
+                )
+                .unwrap();
+            } else if transaction.stars < 0 && !transaction.failed {
+                spends.push(transaction);
+            }
+        }
+
+        match status.next_offset {
+            Some(next_offset) if !reached_stored => offset = next_offset,
+            _ => break,
+        }
+    }
+
+    tracing::debug!(phone_number, inserted, "stars transactions synced");
+
+    Ok(())
+}
+
+async fn report_spend(
+    pool: &SqlitePool,
+    spend: &NewStarsTransaction,
+    report: &mut String,
+) -> Result<()> {
+    let NewStarsTransaction {
+        phone_number,
+        transaction_id,
+        stars,
+        ..
+    } = spend;
+
+    if !is_purchase_transaction(pool, transaction_id).await? {
+        writeln!(
+            report,
+            "💸 {phone_number}: unexpected spend of {} ⭐ ({transaction_id})",
+            -stars
+        )
+        .unwrap();
+    }
+
+    Ok(())
+}
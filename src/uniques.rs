@@ -0,0 +1,426 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use grammers_client::grammers_tl_types::{
+    enums::{
+        InputPeer, InputSavedStarGift, Message, MessageAction, SavedStarGift, StarGift,
+        StarGiftAttribute, Update, Updates,
+        payments::{SavedStarGifts, StarGiftUpgradePreview},
+    },
+    functions::payments::{
+        GetSavedStarGifts, GetStarGiftUpgradePreview, ToggleStarGiftsPinnedToTop, UpgradeStarGift,
+    },
+    types::{self, InputSavedStarGiftUser},
+};
+use sqlx::SqlitePool;
+use teloxide::Bot;
+
+use crate::{
+    bot::alert_chats,
+    db::{
+        self, NewUniqueGift, get_unique_gift_percentile, insert_unique_gift, is_purchase_message,
+        to_unix_millis,
+    },
+    wrapped_client::{Clients, WrappedClient},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] db::Error),
+    #[error(transparent)]
+    GrammersInvocation(#[from] grammers_client::InvocationError),
+    #[error("no unique gift in the upgrade result (msg_id = {0})")]
+    UpgradeResultNotFound(i32),
+    #[error("unique gift misses a model, pattern or backdrop (unique_id = {0})")]
+    MissingAttributes(i64),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub const SAVED_GIFTS_PAGE_LIMIT: i32 = 100;
+// `stargifts_pinned_to_top_limit` of the app config
+const PINNED_GIFTS_LIMIT: usize = 6;
+
+// the rolled attributes of a unique gift as (name, rarity permille)
+#[derive(Debug, Clone)]
+pub struct Rarity {
+    pub model: (String, i32),
+    pub pattern: (String, i32),
+    pub backdrop: (String, i32),
+    // share of upgrades rolling an equally rare or rarer combination, in percent
+    pub percentile: f64,
+}
+
+impl Rarity {
+    // `sample_attributes` of the upgrade preview are every attribute the gift can roll
+    pub fn new(
+        unique: &types::StarGiftUnique,
+        sample_attributes: &[StarGiftAttribute],
+    ) -> Result<Self> {
+        let mut model = None;
+        let mut pattern = None;
+        let mut backdrop = None;
+        for attribute in &unique.attributes {
+            match attribute {
+                StarGiftAttribute::Model(m) => model = Some((m.name.clone(), m.rarity_permille)),
+                StarGiftAttribute::Pattern(p) => {
+                    pattern = Some((p.name.clone(), p.rarity_permille))
+                }
+                StarGiftAttribute::Backdrop(b) => {
+                    backdrop = Some((b.name.clone(), b.rarity_permille))
+                }
+                StarGiftAttribute::OriginalDetails(_) => {}
+            }
+        }
+        let (Some(model), Some(pattern), Some(backdrop)) = (model, pattern, backdrop) else {
+            return Err(Error::MissingAttributes(unique.id));
+        };
+
+        let percentile = percentile([model.1, pattern.1, backdrop.1], sample_attributes);
+
+        Ok(Self {
+            model,
+            pattern,
+            backdrop,
+            percentile,
+        })
+    }
+}
+
+// attributes are rolled independently, so a combination's chance is the product of
+// its permilles, counted exactly in integers to keep ties
+fn percentile(permilles: [i32; 3], sample_attributes: &[StarGiftAttribute]) -> f64 {
+    let mut models = vec![];
+    let mut patterns = vec![];
+    let mut backdrops = vec![];
+    for attribute in sample_attributes {
+        match attribute {
+            StarGiftAttribute::Model(model) => models.push(i64::from(model.rarity_permille)),
+            StarGiftAttribute::Pattern(pattern) => {
+                patterns.push(i64::from(pattern.rarity_permille))
+            }
+            StarGiftAttribute::Backdrop(backdrop) => {
+                backdrops.push(i64::from(backdrop.rarity_permille))
+            }
+            StarGiftAttribute::OriginalDetails(_) => {}
+        }
+    }
+
+    let chance: i64 = permilles.iter().copied().map(i64::from).product();
+    let total: i64 = [&models, &patterns, &backdrops]
+        .iter()
+        .map(|permilles| permilles.iter().sum::<i64>())
+        .product();
+    if total == 0 {
+        return 100.0;
+    }
+
+    let mut rarer = 0;
+    for model in &models {
+        for pattern in &patterns {
+            for backdrop in &backdrops {
+                let combination = model * pattern * backdrop;
+                if combination <= chance {
+                    rarer += combination;
+                }
+            }
+        }
+    }
+
+    rarer as f64 / total as f64 * 100.0
+}
+
+pub fn format_permille(rarity_permille: i32) -> String {
+    format!("{}.{}%", rarity_permille / 10, rarity_permille % 10)
+}
+
+// rarity thresholds as the top percent of combinations
+#[derive(Debug, Clone, Copy)]
+pub struct UpgradeSettings {
+    pub alert_percentile: f64,
+    // pinned to the top of the account's profile, not pinned when unset
+    pub pin_percentile: Option<f64>,
+}
+
+// upgrades gifts bought by this bot to the account itself whose upgrade was paid along
+// with the gift on `interval`, recording the rarity of every roll, alerting admins about
+// and pinning the rare ones
+pub async fn run_upgrades(
+    bot: Arc<Bot>,
+    pool: Arc<SqlitePool>,
+    clients: Clients,
+    settings: UpgradeSettings,
+    interval: Duration,
+) {
+    loop {
+        for client in clients.snapshot() {
+            if client.is_deauthorized() {
+                continue;
+            }
+
+            if let Err(err) = upgrade_prepaid_gifts(&bot, &pool, &client, settings).await {
+                tracing::error!(
+                    ?err,
+                    phone_number = client.phone_number(),
+                    "failed to upgrade gifts"
+                );
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn upgrade_prepaid_gifts(
+    bot: &Bot,
+    pool: &SqlitePool,
+    client: &WrappedClient,
+    settings: UpgradeSettings,
+) -> Result<()> {
+    let phone_number = client.phone_number();
+
+    let saved_gifts = get_saved_gifts(
+        client,
+        GetSavedStarGifts {
+            exclude_unsaved: false,
+            exclude_saved: false,
+            exclude_unlimited: false,
+            exclude_unique: true,
+            sort_by_value: false,
+            exclude_upgradable: false,
+            exclude_unupgradable: true,
+            peer: InputPeer::PeerSelf,
+            collection_id: None,
+            offset: String::new(),
+            limit: SAVED_GIFTS_PAGE_LIMIT,
+        },
+    )
+    .await?;
+
+    for saved_gift in saved_gifts {
+        let StarGift::Gift(gift) = &saved_gift.gift else {
+            continue;
+        };
+        let Some(msg_id) = saved_gift.msg_id else {
+            continue;
+        };
+        // `upgrade_stars` is only set when the upgrade has been paid for
+        if !saved_gift.can_upgrade
+            || saved_gift.upgrade_stars.is_none()
+            || !is_purchase_message(pool, phone_number, msg_id).await?
+        {
+            continue;
+        }
+
+        let (unique_msg_id, unique) = match upgrade_gift(client, msg_id).await {
+            Ok(t) => t,
+            Err(err) => {
+                tracing::error!(?err, phone_number, msg_id, "failed to upgrade gift");
+                continue;
+            }
+        };
+
+        let StarGiftUpgradePreview::Preview(preview) = client
+            .invoke(&GetStarGiftUpgradePreview { gift_id: gift.id })
+            .await?;
+        let rarity = Rarity::new(&unique, &preview.sample_attributes)?;
+
+        tracing::info!(
+            phone_number,
+            gift_id = gift.id,
+            slug = unique.slug,
+            percentile = rarity.percentile,
+            "gift upgraded"
+        );
+
+        insert_unique_gift(
+            pool,
+            &NewUniqueGift {
+                unique_id: unique.id,
+                phone_number: phone_number.to_string(),
+                gift_id: gift.id,
+                msg_id: unique_msg_id,
+                slug: unique.slug.clone(),
+                num: unique.num,
+                model: rarity.model.clone(),
+                pattern: rarity.pattern.clone(),
+                backdrop: rarity.backdrop.clone(),
+                percentile: rarity.percentile,
+                upgraded_at: to_unix_millis(SystemTime::now()),
+            },
+        )
+        .await?;
+
+        if let Some(pin_percentile) = settings.pin_percentile
+            && rarity.percentile <= pin_percentile
+        {
+            match pin_unique(pool, client, unique_msg_id, rarity.percentile).await {
+                Ok(true) => tracing::info!(phone_number, slug = unique.slug, "unique pinned"),
+                Ok(false) => {}
+                Err(err) => tracing::error!(?err, phone_number, "failed to pin unique"),
+            }
+        }
+
+        if rarity.percentile <= settings.alert_percentile {
+            let text = format!(
+                "💎 {phone_number}: {} #{} rolled a top {:.2}% combination, worth listing on resale\n\
+                Model: {} ({})\n\
+                Symbol: {} ({})\n\
+                Backdrop: {} ({})\n\
+                https://t.me/nft/{}",
+                unique.title,
+                unique.num,
+                rarity.percentile,
+                rarity.model.0,
+                format_permille(rarity.model.1),
+                rarity.pattern.0,
+                format_permille(rarity.pattern.1),
+                rarity.backdrop.0,
+                format_permille(rarity.backdrop.1),
+                unique.slug,
+            );
+            if let Err(err) = alert_chats(bot, pool, &text).await {
+                tracing::error!(?err, "failed to send rare upgrade alert");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// pins the unique (`msg_id` of its service message) to the top of the account's profile,
+// once the limit is reached it replaces the least rare pinned upgrade rarer than it,
+// gifts pinned by hand are kept, returns whether it was pinned
+async fn pin_unique(
+    pool: &SqlitePool,
+    client: &WrappedClient,
+    msg_id: i32,
+    percentile: f64,
+) -> Result<bool> {
+    let phone_number = client.phone_number();
+
+    let mut pinned: Vec<i32> = get_saved_gifts(
+        client,
+        GetSavedStarGifts {
+            exclude_unsaved: false,
+            exclude_saved: false,
+            exclude_unlimited: true,
+            exclude_unique: false,
+            sort_by_value: false,
+            exclude_upgradable: true,
+            exclude_unupgradable: true,
+            peer: InputPeer::PeerSelf,
+            collection_id: None,
+            offset: String::new(),
+            limit: SAVED_GIFTS_PAGE_LIMIT,
+        },
+    )
+    .await?
+    .into_iter()
+    .filter(|saved_gift| saved_gift.pinned_to_top)
+    .filter_map(|saved_gift| saved_gift.msg_id)
+    .collect();
+
+    if pinned.contains(&msg_id) {
+        return Ok(false);
+    }
+
+    if pinned.len() >= PINNED_GIFTS_LIMIT {
+        let mut least_rare: Option<(usize, f64)> = None;
+        for (i, &pinned_msg_id) in pinned.iter().enumerate() {
+            if let Some(pinned_percentile) =
+                get_unique_gift_percentile(pool, phone_number, pinned_msg_id).await?
+                && pinned_percentile > percentile
+                && least_rare.is_none_or(|(_, least_rare)| pinned_percentile > least_rare)
+            {
+                least_rare = Some((i, pinned_percentile));
+            }
+        }
+
+        let Some((i, _)) = least_rare else {
+            return Ok(false);
+        };
+        pinned.remove(i);
+    }
+
+    pinned.push(msg_id);
+
+    client
+        .invoke(&ToggleStarGiftsPinnedToTop {
+            peer: InputPeer::PeerSelf,
+            stargift: pinned
+                .into_iter()
+                .map(|msg_id| InputSavedStarGift::User(InputSavedStarGiftUser { msg_id }))
+                .collect(),
+        })
+        .await?;
+
+    Ok(true)
+}
+
+// every page of `request`, whose offset is overwritten
+pub async fn get_saved_gifts(
+    client: &WrappedClient,
+    mut request: GetSavedStarGifts,
+) -> Result<Vec<types::SavedStarGift>> {
+    let mut gifts = vec![];
+    request.offset = String::new();
+
+    loop {
+        let SavedStarGifts::Gifts(page) = client.invoke(&request).await?;
+
+        gifts.extend(page.gifts.into_iter().map(|gift| {
+            let SavedStarGift::Gift(gift) = gift;
+            gift
+        }));
+
+        match page.next_offset {
+            Some(next_offset) if !next_offset.is_empty() => request.offset = next_offset,
+            _ => break,
+        }
+    }
+
+    Ok(gifts)
+}
+
+// upgrades an owned gift (`msg_id` of its service message) whose upgrade is already
+// paid for, returning the unique gift with the id of its new service message
+pub async fn upgrade_gift(
+    client: &WrappedClient,
+    msg_id: i32,
+) -> Result<(i32, types::StarGiftUnique)> {
+    let updates = client
+        .invoke(&UpgradeStarGift {
+            keep_original_details: false,
+            stargift: InputSavedStarGift::User(InputSavedStarGiftUser { msg_id }),
+        })
+        .await?;
+
+    let updates = match updates {
+        Updates::Updates(updates) => updates.updates,
+        Updates::Combined(updates) => updates.updates,
+        _ => return Err(Error::UpgradeResultNotFound(msg_id)),
+    };
+
+    updates
+        .into_iter()
+        .find_map(|update| {
+            let Update::NewMessage(update) = update else {
+                return None;
+            };
+            let Message::Service(message) = update.message else {
+                return None;
+            };
+            match message.action {
+                MessageAction::StarGiftUnique(action) => match action.gift {
+                    StarGift::Unique(unique) => Some((message.id, unique)),
+                    StarGift::Gift(_) => None,
+                },
+                _ => None,
+            }
+        })
+        .ok_or(Error::UpgradeResultNotFound(msg_id))
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use futures::TryFutureExt;
+use grammers_client::Update;
+use tokio::sync::Notify;
+
+use crate::wrapped_client::WrappedClient;
+
+pub type Result<T, E = grammers_client::InvocationError> = std::result::Result<T, E>;
+
+// starts `watch_updates` for the accounts active at startup and the ones added at runtime
+#[derive(Clone)]
+pub struct UpdateWatchers {
+    trigger_usernames: Arc<[String]>,
+    poll_trigger: Arc<Notify>,
+}
+
+impl UpdateWatchers {
+    pub fn new(trigger_usernames: Arc<[String]>, poll_trigger: Arc<Notify>) -> Self {
+        Self {
+            trigger_usernames,
+            poll_trigger,
+        }
+    }
+
+    pub fn spawn(&self, client: Arc<WrappedClient>) {
+        tokio::spawn(
+            watch_updates(
+                client,
+                self.trigger_usernames.clone(),
+                self.poll_trigger.clone(),
+            )
+            .inspect_err(|err| tracing::error!(?err, "watch_updates exited with error")),
+        );
+    }
+}
+
+// wakes up the poll loop when a post appears in one of the `trigger_usernames` chats
+// (e.g. official announcement channels), so new gifts are fetched without waiting
+// for the next poll
+pub async fn watch_updates(
+    client: Arc<WrappedClient>,
+    trigger_usernames: Arc<[String]>,
+    poll_trigger: Arc<Notify>,
+) -> Result<()> {
+    loop {
+        let update = client.next_update().await?;
+
+        let Update::NewMessage(message) = update else {
+            tracing::trace!(?update, "update skipped");
+            continue;
+        };
+
+        let chat = message.chat();
+        let is_trigger = chat.username().is_some_and(|username| {
+            trigger_usernames
+                .iter()
+                .any(|trigger| trigger.eq_ignore_ascii_case(username))
+        });
+
+        if is_trigger {
+            tracing::info!(
+                chat_id = chat.id(),
+                message_id = message.id(),
+                phone_number = client.phone_number(),
+                "poll triggered by update"
+            );
+            poll_trigger.notify_one();
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use teloxide::{
+    payloads::{EditMessageTextSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::{
+    bot::{BotState, Result, admin_buy_destinations, chat_language, check_destination},
+    core::{BuyDestinations, BuyLimits, buy_gifts},
+    db::{WizardState, delete_wizard_state, get_wizard_state, set_wizard_state, to_unix_millis},
+    i18n::{Language, Text},
+};
+
+// callback data of the /wizard buttons, followed by the action
+pub const WIZARD_CALLBACK_PREFIX: &str = "wiz:";
+
+const GIFTS_PER_PAGE: usize = 8;
+
+// a wizard left untouched this long is forgotten, so a number sent much later isn't taken
+// for its quantity
+const WIZARD_STATE_TTL: Duration = Duration::from_secs(30 * 60);
+
+// what the admin running /wizard is asked for next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Gift,
+    Quantity,
+    Destination,
+    Confirm,
+}
+
+impl Step {
+    // stored in `wizard_states`
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gift => "gift",
+            Self::Quantity => "quantity",
+            Self::Destination => "destination",
+            Self::Confirm => "confirm",
+        }
+    }
+
+    fn from_str(step: &str) -> Option<Self> {
+        [Self::Gift, Self::Quantity, Self::Destination, Self::Confirm]
+            .into_iter()
+            .find(|s| s.as_str() == step)
+    }
+}
+
+// the admin's wizard, unless it expired
+async fn load(state: &BotState, username: &str) -> Result<Option<WizardState>> {
+    let updated_since = to_unix_millis(SystemTime::now() - WIZARD_STATE_TTL);
+    Ok(get_wizard_state(&*state.pool, username, updated_since).await?)
+}
+
+// starts over any wizard the admin left unfinished
+pub async fn start(
+    state: &BotState,
+    language: Language,
+    chat_id: ChatId,
+    username: &str,
+) -> Result<()> {
+    set_wizard_state(
+        &*state.pool,
+        &WizardState {
+            username: username.to_string(),
+            step: Step::Gift.as_str().to_string(),
+            gift_id: None,
+            quantity: None,
+            destination: None,
+            updated_at: to_unix_millis(SystemTime::now()),
+        },
+    )
+    .await?;
+
+    let (text, keyboard) = gift_page(state, language, 0);
+    state
+        .bot
+        .send_message(chat_id, text)
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+// the quantity and destination are typed, returns false when the admin isn't asked for
+// either, so the message is handled as usual
+pub async fn on_text(
+    state: &BotState,
+    language: Language,
+    chat_id: ChatId,
+    username: &str,
+    text: &str,
+) -> Result<bool> {
+    let Some(mut wizard) = load(state, username).await? else {
+        return Ok(false);
+    };
+
+    let reply = match Step::from_str(&wizard.step) {
+        Some(Step::Quantity) => match text.trim().parse::<i64>() {
+            Ok(quantity) if quantity > 0 => {
+                wizard.quantity = Some(quantity);
+                return ask_destination(state, language, chat_id, wizard)
+                    .await
+                    .map(|()| true);
+            }
+            _ => language.text(Text::InvalidQuantity).to_string(),
+        },
+        // resolved now, so the purchase doesn't fail on it after the confirmation
+        Some(Step::Destination) => match check_destination(language, state, text.trim()).await {
+            None => {
+                wizard.destination = Some(text.trim().to_string());
+                return ask_confirmation(state, language, chat_id, wizard)
+                    .await
+                    .map(|()| true);
+            }
+            Some(err) => err,
+        },
+        _ => return Ok(false),
+    };
+
+    state.bot.send_message(chat_id, reply).await?;
+
+    Ok(true)
+}
+
+// `data` is the callback data without `WIZARD_CALLBACK_PREFIX`
+pub async fn on_callback(
+    state: &Arc<BotState>,
+    callback_query: &CallbackQuery,
+    data: &str,
+) -> Result<()> {
+    let bot = &state.bot;
+    bot.answer_callback_query(callback_query.id.clone()).await?;
+
+    let (Some(message), Some(username)) = (
+        callback_query.message.as_ref(),
+        callback_query.from.username.as_deref(),
+    ) else {
+        return Ok(());
+    };
+    let (chat_id, message_id) = (message.chat().id, message.id());
+
+    if !state.admin_usernames.iter().any(|admin| admin == username) {
+        tracing::debug!(username, "user not in admins list");
+        return Ok(());
+    }
+
+    let language = chat_language(&state.pool, chat_id).await?;
+    let Some(mut wizard) = load(state, username).await? else {
+        bot.send_message(chat_id, language.text(Text::WizardNotStarted))
+            .await?;
+        return Ok(());
+    };
+
+    let (action, arg) = data.split_once(':').unwrap_or((data, ""));
+    match action {
+        "page" => {
+            let (text, keyboard) = gift_page(state, language, arg.parse().unwrap_or_default());
+            bot.edit_message_text(chat_id, message_id, text)
+                .reply_markup(keyboard)
+                .await?;
+        }
+        "gift" => {
+            let Ok(gift_id) = arg.parse() else {
+                return Ok(());
+            };
+            wizard.gift_id = Some(gift_id);
+            save(state, wizard, Step::Quantity).await?;
+            bot.send_message(chat_id, language.text(Text::AskQuantity))
+                .await?;
+        }
+        "dest" if Step::from_str(&wizard.step) == Some(Step::Destination) => {
+            // the admin's /setdest destination or the configured one
+            wizard.destination = match arg {
+                "self" => Some("self".to_string()),
+                _ => None,
+            };
+            ask_confirmation(state, language, chat_id, wizard).await?;
+        }
+        "confirm" if Step::from_str(&wizard.step) == Some(Step::Confirm) => {
+            delete_wizard_state(&*state.pool, username).await?;
+            confirm(state, language, chat_id, username, wizard).await?;
+        }
+        "cancel" => {
+            delete_wizard_state(&*state.pool, username).await?;
+            bot.send_message(chat_id, language.text(Text::WizardCancelled))
+                .await?;
+        }
+        _ => tracing::debug!(data, "unexpected wizard callback"),
+    }
+
+    Ok(())
+}
+
+async fn save(state: &BotState, mut wizard: WizardState, step: Step) -> Result<()> {
+    wizard.step = step.as_str().to_string();
+    wizard.updated_at = to_unix_millis(SystemTime::now());
+    set_wizard_state(&*state.pool, &wizard).await?;
+    Ok(())
+}
+
+async fn ask_destination(
+    state: &BotState,
+    language: Language,
+    chat_id: ChatId,
+    wizard: WizardState,
+) -> Result<()> {
+    save(state, wizard, Step::Destination).await?;
+
+    let keyboard = InlineKeyboardMarkup::new(vec![
+        vec![
+            InlineKeyboardButton::callback(
+                language.text(Text::DefaultDestination),
+                format!("{WIZARD_CALLBACK_PREFIX}dest"),
+            ),
+            InlineKeyboardButton::callback(
+                language.text(Text::ToSelf),
+                format!("{WIZARD_CALLBACK_PREFIX}dest:self"),
+            ),
+        ],
+        vec![cancel_button(language)],
+    ]);
+    state
+        .bot
+        .send_message(chat_id, language.text(Text::AskDestination))
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+async fn ask_confirmation(
+    state: &BotState,
+    language: Language,
+    chat_id: ChatId,
+    wizard: WizardState,
+) -> Result<()> {
+    let gift = wizard.gift_id.and_then(|gift_id| {
+        state
+            .catalog
+            .snapshot()
+            .into_iter()
+            .find(|gift| gift.id == gift_id)
+    });
+    let text = language.confirm_purchase(
+        wizard.quantity.unwrap_or_default(),
+        gift.as_ref()
+            .and_then(|gift| gift.title.as_deref())
+            .unwrap_or(language.text(Text::Untitled)),
+        gift.as_ref().map_or(0, |gift| gift.stars),
+        wizard.destination.as_deref(),
+    );
+
+    save(state, wizard, Step::Confirm).await?;
+
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            language.text(Text::Confirm),
+            format!("{WIZARD_CALLBACK_PREFIX}confirm"),
+        ),
+        cancel_button(language),
+    ]]);
+    state
+        .bot
+        .send_message(chat_id, text)
+        .reply_markup(keyboard)
+        .await?;
+
+    Ok(())
+}
+
+async fn confirm(
+    state: &Arc<BotState>,
+    language: Language,
+    chat_id: ChatId,
+    username: &str,
+    wizard: WizardState,
+) -> Result<()> {
+    let (Some(gift_id), Some(quantity)) = (wizard.gift_id, wizard.quantity) else {
+        return Ok(());
+    };
+
+    let buy_dest = match wizard
+        .destination
+        .as_deref()
+        .map(str::parse::<BuyDestinations>)
+    {
+        // checked when it was typed
+        Some(destinations) => Arc::new(destinations.unwrap()),
+        None => admin_buy_destinations(state, Some(username)).await?,
+    };
+    let limits = BuyLimits {
+        per_gift: HashMap::from([(gift_id, quantity as u64)]),
+        ..BuyLimits::from(state.buy_limit)
+    };
+
+    state
+        .bot
+        .send_message(chat_id, language.buying_per_account(quantity, gift_id))
+        .await?;
+    tracing::info!(username, gift_id, quantity, "wizard purchase confirmed");
+
+    let detected_at = SystemTime::now();
+    let state = state.clone();
+    tokio::spawn(async move {
+        buy_gifts(
+            &state.clients.snapshot(),
+            state.bot.clone(),
+            state.pool.clone(),
+            &state.coordinator,
+            vec![gift_id],
+            None,
+            &limits,
+            &buy_dest,
+            &state.gift_options,
+            detected_at,
+        )
+        .await
+        .inspect_err(|err| tracing::error!(?err, "buy_gifts exited with error"))
+    });
+
+    Ok(())
+}
+
+// gifts still on sale in the last catalog, `page` is clamped to the last page
+fn gift_page(state: &BotState, language: Language, page: usize) -> (String, InlineKeyboardMarkup) {
+    let gifts: Vec<_> = state
+        .catalog
+        .snapshot()
+        .into_iter()
+        .filter(|gift| !gift.sold_out)
+        .collect();
+    if gifts.is_empty() {
+        return (
+            language.text(Text::NoGiftsOnSale).to_string(),
+            InlineKeyboardMarkup::new(vec![vec![cancel_button(language)]]),
+        );
+    }
+
+    let pages = gifts.len().div_ceil(GIFTS_PER_PAGE);
+    let page = page.min(pages - 1);
+
+    let mut rows: Vec<_> = gifts
+        .iter()
+        .skip(page * GIFTS_PER_PAGE)
+        .take(GIFTS_PER_PAGE)
+        .map(|gift| {
+            vec![InlineKeyboardButton::callback(
+                format!(
+                    "{} · {} ⭐ · {}",
+                    gift.title
+                        .as_deref()
+                        .unwrap_or(language.text(Text::Untitled)),
+                    gift.stars,
+                    language.units_left(gift.remains)
+                ),
+                format!("{WIZARD_CALLBACK_PREFIX}gift:{}", gift.id),
+            )]
+        })
+        .collect();
+
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            "◀️",
+            format!("{WIZARD_CALLBACK_PREFIX}page:{}", page - 1),
+        ));
+    }
+    if page + 1 < pages {
+        navigation.push(InlineKeyboardButton::callback(
+            "▶️",
+            format!("{WIZARD_CALLBACK_PREFIX}page:{}", page + 1),
+        ));
+    }
+    if !navigation.is_empty() {
+        rows.push(navigation);
+    }
+    rows.push(vec![cancel_button(language)]);
+
+    (
+        language.pick_gift(page + 1, pages),
+        InlineKeyboardMarkup::new(rows),
+    )
+}
+
+fn cancel_button(language: Language) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(
+        language.text(Text::Cancel),
+        format!("{WIZARD_CALLBACK_PREFIX}cancel"),
+    )
+}
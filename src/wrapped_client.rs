@@ -1,10 +1,48 @@
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs,
+    io::BufRead,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use dialoguer::Input;
-use grammers_client::{Client, SignInError, session::Session};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use dialoguer::{Input, Password};
+use grammers_client::{
+    Client, InitParams, InvocationError, SignInError, Update,
+    grammers_tl_types::{
+        self as tl, RemoteCall, Serializable,
+        enums::{
+            InputInvoice, InputPeer, InputUser, StarsAmount,
+            auth::LoginToken,
+            payments::{PaymentForm, StarsStatus},
+        },
+        functions::{
+            account::{GetPassword, GetTmpPassword},
+            auth::{CheckPassword, ExportLoginToken, ImportLoginToken},
+            help::GetNearestDc,
+            payments::GetStarsStatus,
+            users::GetUsers,
+        },
+    },
+    session::Session,
+    types::PasswordToken,
+};
+use qrcode::{QrCode, render::unicode::Dense1x2};
+use serde::Deserialize;
 use sqlx::SqlitePool;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
-use crate::db::{self, get_session, insert_or_replace_session};
+use crate::{
+    bot::{self, BotLoginCodes},
+    config::{Account, Proxy},
+    db::{self, get_session, insert_or_replace_session, mark_session_deauthorized, to_unix_millis},
+    health::Health,
+    rate_limit::TokenBucket,
+};
 
 #[derive(Debug, thiserror::Error)]
 #[allow(clippy::large_enum_variant)]
@@ -19,57 +57,406 @@ pub enum Error {
     GrammersSignIn(#[from] grammers_client::SignInError),
     #[error(transparent)]
     Dialoguer(#[from] dialoguer::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Bot(#[from] bot::Error),
+    #[error("login code source is `bot`, but the bot isn't available")]
+    BotLoginCodesUnavailable,
+    #[error(transparent)]
+    Qr(#[from] qrcode::types::QrError),
+    #[error("unexpected login token response")]
+    UnexpectedLoginToken,
+    #[error("2FA isn't enabled or uses an unknown algorithm (phone_number = {0})")]
+    UnsupportedPassword(String),
+    #[error("failed to reconnect")]
+    ReconnectFailed,
+    #[error("MTProto proxies aren't supported by the client library yet (proxy = {0})")]
+    MtProtoProxyUnsupported(String),
+    #[error("2FA password isn't configured (phone_number = {0})")]
+    PasswordNotConfigured(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+const LOGIN_CODE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginCodeSource {
+    // interactive terminal prompt
+    #[default]
+    Prompt,
+    // one line per requested code (and password, if not configured) from piped stdin
+    Stdin,
+    // polls the file until it's non-empty, then removes it,
+    // `{phone_number}` in the path is replaced with the phone number
+    File(String),
+    // `LOGIN_CODE_<phone number digits>` from the process env, which is fixed once the
+    // process runs, otherwise polls it in the .env file
+    Env,
+    // asks trusted bot chats, admins reply with `/code <phone number> <code>`
+    Bot,
+}
+
+impl LoginCodeSource {
+    async fn login_code(
+        &self,
+        phone_number: &str,
+        bot_login_codes: Option<&BotLoginCodes>,
+    ) -> Result<String> {
+        match self {
+            Self::Prompt => Ok(Input::new()
+                .with_prompt(format!("Please enter login code for {phone_number}"))
+                .interact()?),
+            Self::Stdin => {
+                tracing::info!(phone_number, "waiting for login code on stdin");
+                read_stdin_line().await
+            }
+            Self::File(template) => {
+                let path = template.replace("{phone_number}", phone_number);
+                tracing::info!(phone_number, path, "waiting for login code in file");
+
+                loop {
+                    if let Ok(contents) = fs::read_to_string(&path) {
+                        let code = contents.trim();
+                        if !code.is_empty() {
+                            fs::remove_file(&path)?;
+                            return Ok(code.to_string());
+                        }
+                    }
+                    tokio::time::sleep(LOGIN_CODE_POLL_INTERVAL).await;
+                }
+            }
+            Self::Env => {
+                let digits: String = phone_number.chars().filter(char::is_ascii_digit).collect();
+                let key = format!("LOGIN_CODE_{digits}");
+                tracing::info!(phone_number, key, "waiting for login code in env");
+
+                let non_empty = |code: String| {
+                    let code = code.trim();
+                    (!code.is_empty()).then(|| code.to_string())
+                };
+                if let Some(code) = std::env::var(&key).ok().and_then(non_empty) {
+                    return Ok(code);
+                }
+
+                loop {
+                    let code = dotenvy::from_path_iter(".env")
+                        .ok()
+                        .and_then(|iter| iter.flatten().find_map(|(k, v)| (k == key).then_some(v)));
+                    if let Some(code) = code.and_then(non_empty) {
+                        return Ok(code);
+                    }
+                    tokio::time::sleep(LOGIN_CODE_POLL_INTERVAL).await;
+                }
+            }
+            Self::Bot => Ok(bot_login_codes
+                .ok_or(Error::BotLoginCodesUnavailable)?
+                .request(phone_number)
+                .await?),
+        }
+    }
+
+    async fn password(&self, phone_number: &str, hint: Option<&str>) -> Result<String> {
+        match self {
+            Self::Stdin => {
+                tracing::info!(phone_number, "waiting for password on stdin");
+                read_stdin_line().await
+            }
+            _ => Ok(Password::new()
+                .with_prompt(format!(
+                    "Please enter password for {phone_number} (hint: {})",
+                    hint.unwrap_or("none")
+                ))
+                .interact()?),
+        }
+    }
+}
+
+async fn connect_client(pool: &SqlitePool, account: &Account) -> Result<Client> {
+    let session = get_session(pool, &account.phone_number)
+        .await?
+        .unwrap_or_else(Session::new);
+
+    let proxy_url = match &account.proxy {
+        None => None,
+        Some(Proxy::Socks5(url)) => Some(url.clone()),
+        // grammers only speaks SOCKS5, MTProto proxies need the obfuscated transport
+        Some(Proxy::MtProto { server, port, .. }) => {
+            return Err(Error::MtProtoProxyUnsupported(format!("{server}:{port}")));
+        }
+    };
+
+    let client = Client::connect(grammers_client::Config {
+        session,
+        api_id: account.api_id,
+        api_hash: account.api_hash.clone(),
+        params: InitParams {
+            proxy_url,
+            ..Default::default()
+        },
+    })
+    .await?;
+
+    Ok(client)
+}
+
+fn is_connection_error(err: &InvocationError) -> bool {
+    matches!(
+        err,
+        InvocationError::Io(_) | InvocationError::Transport(_) | InvocationError::Dropped
+    )
+}
+
+fn method_name<R>() -> &'static str {
+    let name = std::any::type_name::<R>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+// -503 is the server side timeout, 500 are internal errors like RPC_CALL_FAIL
+fn is_transient_error(err: &InvocationError) -> bool {
+    matches!(err, InvocationError::Rpc(err) if err.code == -503 || err.code == 500)
+}
+
+fn is_deauthorization_error(err: &InvocationError) -> bool {
+    matches!(
+        err,
+        InvocationError::Rpc(err) if matches!(
+            err.name.as_str(),
+            "AUTH_KEY_UNREGISTERED"
+                | "AUTH_KEY_INVALID"
+                | "SESSION_REVOKED"
+                | "SESSION_EXPIRED"
+                | "USER_DEACTIVATED"
+                | "USER_DEACTIVATED_BAN"
+        )
+    )
+}
+
+// what kind of failure a request ran into, shown in alerts so it's clear whether to
+// wait, log in again or check the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Flood,
+    Auth,
+    Network,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn of(err: &InvocationError) -> Self {
+        if flood_wait_duration(err).is_some() {
+            Self::Flood
+        } else if is_deauthorization_error(err) {
+            Self::Auth
+        } else if is_connection_error(err) || is_transient_error(err) {
+            Self::Network
+        } else {
+            Self::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Flood => "flood wait",
+            Self::Auth => "auth",
+            Self::Network => "network",
+            Self::Other => "other",
+        }
+    }
+}
+
+fn print_login_qr(phone_number: &str, token: &[u8]) -> Result<()> {
+    let url = format!("tg://login?token={}", URL_SAFE_NO_PAD.encode(token));
+
+    let qr = QrCode::new(&url)?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+
+    println!("Scan with the app logged in as {phone_number}:\n{qr}\n{url}");
+
+    Ok(())
+}
+
+// the SRP proof of `password` for the methods checking it
+fn input_check_password(
+    phone_number: &str,
+    password_info: tl::types::account::Password,
+    password: &str,
+) -> Result<tl::enums::InputCheckPasswordSrp> {
+    let (
+        Some(tl::enums::PasswordKdfAlgo::Sha256Sha256Pbkdf2Hmacsha512iter100000Sha256ModPow(algo)),
+        Some(srp_b),
+        Some(srp_id),
+    ) = (
+        password_info.current_algo,
+        password_info.srp_b,
+        password_info.srp_id,
+    )
+    else {
+        return Err(Error::UnsupportedPassword(phone_number.to_string()));
+    };
+
+    let (m1, g_a) = grammers_crypto::two_factor_auth::calculate_2fa(
+        &algo.salt1,
+        &algo.salt2,
+        &algo.g,
+        &algo.p,
+        srp_b,
+        password_info.secure_random,
+        password,
+    );
+
+    Ok(tl::enums::InputCheckPasswordSrp::Srp(
+        tl::types::InputCheckPasswordSrp { srp_id, a: g_a, m1 },
+    ))
+}
+
+fn authorized_user_id(authorization: &tl::enums::auth::Authorization) -> Result<i64> {
+    match authorization {
+        tl::enums::auth::Authorization::Authorization(authorization) => {
+            Ok(match &authorization.user {
+                tl::enums::User::User(user) => user.id,
+                tl::enums::User::Empty(user) => user.id,
+            })
+        }
+        tl::enums::auth::Authorization::SignUpRequired(_) => Err(Error::UnexpectedLoginToken),
+    }
+}
+
+// on a blocking thread, the other accounts keep running while this one waits for input
+async fn read_stdin_line() -> Result<String> {
+    let line = tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line).map(|_| line)
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    Ok(line.trim().to_string())
+}
+
+// accounts can be added and removed at runtime, consumers take a snapshot when they need them
+#[derive(Clone, Default)]
+pub struct Clients(Arc<RwLock<Vec<Arc<WrappedClient>>>>);
+
+impl Clients {
+    pub fn new(clients: Vec<Arc<WrappedClient>>) -> Self {
+        Self(Arc::new(RwLock::new(clients)))
+    }
+
+    pub fn snapshot(&self) -> Vec<Arc<WrappedClient>> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn get(&self, phone_number: &str) -> Option<Arc<WrappedClient>> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .find(|client| client.phone_number() == phone_number)
+            .cloned()
+    }
+
+    pub fn add(&self, client: Arc<WrappedClient>) {
+        self.0.write().unwrap().push(client);
+    }
+
+    pub fn remove(&self, phone_number: &str) -> Option<Arc<WrappedClient>> {
+        let mut clients = self.0.write().unwrap();
+        let index = clients
+            .iter()
+            .position(|client| client.phone_number() == phone_number)?;
+        Some(clients.remove(index))
+    }
+}
+
+// a token that already looks expired (clock skew) is exported again after this long
+const QR_LOGIN_TOKEN_MIN_WAIT: Duration = Duration::from_secs(5);
+
+// payment sends spend stars or charge the card, a resent one may have gone through already,
+// so they're never retried and a lost reply is reported as a failure
+const NON_IDEMPOTENT_METHODS: [&str; 2] = ["SendStarsForm", "SendPaymentForm"];
+
+pub const PAYMENT_FORM_TTL: Duration = Duration::from_secs(60);
+
+// requests counted by the payment rate limit, so bursts of purchases slow down
+// on our side before Telegram answers with a long FLOOD_WAIT
+const PAYMENT_METHODS: [&str; 2] = ["GetPaymentForm", "SendStarsForm"];
+
+const RECONNECT_MAX_ATTEMPTS: u32 = 6;
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
 pub struct WrappedClient {
-    phone_number: String,
+    account: Account,
     pool: Arc<SqlitePool>,
-    client: Client,
+    // replaced when the connection drops, see `reconnect`
+    client: RwLock<Arc<Client>>,
+    // held while reconnecting, so concurrent failed calls reconnect only once
+    reconnecting: tokio::sync::Mutex<()>,
+    // consecutive reconnects that ran out of attempts, reset once one succeeds
+    reconnect_failures: AtomicU32,
+    // set once Telegram revokes the session, see `check_deauthorized`
+    deauthorized: AtomicBool,
+    // set once the revocation was alerted about, see `claim_deauthorization_alert`
+    deauthorization_alerted: AtomicBool,
+    // flood wait deadlines by method, shared by everything using this client
+    flood_waits: Mutex<HashMap<&'static str, Instant>>,
+    // result of the last background health check
+    health: Mutex<Option<Health>>,
+    // prefetched payment forms by serialized invoice, which holds the gift, the recipient and
+    // the options, with the time they were fetched at
+    payment_forms: Mutex<HashMap<Vec<u8>, (PaymentForm, Instant)>>,
+    // throttles payment requests, see `PAYMENT_METHODS`
+    payment_limiter: Option<TokenBucket>,
+    // one purchase stream at a time, see `begin_purchases`
+    purchases: Semaphore,
+    queued_purchases: AtomicUsize,
+    // Telegram Premium, fetched once authorized so purchases don't wait for it,
+    // false until known
+    premium: AtomicBool,
 }
 
 impl WrappedClient {
+    // asks `login_code_source` for the login code (and the 2FA password unless the account
+    // has one configured) if the stored session isn't authorized
     pub async fn new(
         pool: Arc<SqlitePool>,
-        phone_number: String,
-        api_id: i32,
-        api_hash: String,
+        account: Account,
+        login_code_source: &LoginCodeSource,
+        bot_login_codes: Option<&BotLoginCodes>,
     ) -> Result<Self> {
-        let session = get_session(&*pool, &phone_number)
-            .await?
-            .unwrap_or_else(Session::new);
-
-        let client = Client::connect(grammers_client::Config {
-            session,
-            api_id,
-            api_hash,
-            params: Default::default(),
-        })
-        .await?;
-
-        let this = Self {
-            phone_number,
-            pool,
-            client,
-        };
+        let this = Self::connect(pool, account).await?;
 
-        if !this.client.is_authorized().await? {
-            let login_token = this.client.request_login_code(&this.phone_number).await?;
+        if !this.client().is_authorized().await? {
+            let login_token = this
+                .client()
+                .request_login_code(&this.account.phone_number)
+                .await?;
 
-            let login_code: String = Input::new()
-                .with_prompt(format!("Please enter login code for {}", this.phone_number))
-                .interact()?;
+            let login_code = login_code_source
+                .login_code(&this.account.phone_number, bot_login_codes)
+                .await?;
 
-            let sing_in_result = this.client.sign_in(&login_token, &login_code).await;
+            let sing_in_result = this.client().sign_in(&login_token, &login_code).await;
 
             match sing_in_result {
                 Err(SignInError::PasswordRequired(password_token)) => {
-                    let password: String = Input::new()
-                        .with_prompt(format!("Please enter password for {}", this.phone_number))
-                        .interact()?;
+                    let password = match this.account.password.clone() {
+                        Some(t) => t,
+                        None => {
+                            login_code_source
+                                .password(&this.account.phone_number, password_token.hint())
+                                .await?
+                        }
+                    };
 
-                    this.client.check_password(password_token, password).await?;
+                    this.client()
+                        .check_password(password_token, password)
+                        .await?;
                 }
                 result => {
                     result?;
@@ -77,26 +464,592 @@ impl WrappedClient {
             }
 
             this.sync_session().await?;
+            this.refresh_premium().await;
         }
 
         Ok(this)
     }
 
+    // connects using the stored session without going through the login flow
+    pub async fn connect(pool: Arc<SqlitePool>, account: Account) -> Result<Self> {
+        let client = connect_client(&pool, &account).await?;
+        let payment_limiter = account
+            .payment_rate_limit
+            .map(|limit| TokenBucket::new(limit.per_second, limit.burst));
+
+        let this = Self {
+            account,
+            pool,
+            client: RwLock::new(Arc::new(client)),
+            reconnecting: tokio::sync::Mutex::new(()),
+            reconnect_failures: AtomicU32::new(0),
+            deauthorized: AtomicBool::new(false),
+            deauthorization_alerted: AtomicBool::new(false),
+            flood_waits: Mutex::new(HashMap::new()),
+            health: Mutex::new(None),
+            payment_forms: Mutex::new(HashMap::new()),
+            payment_limiter,
+            purchases: Semaphore::new(1),
+            queued_purchases: AtomicUsize::new(0),
+            premium: AtomicBool::new(false),
+        };
+        this.refresh_premium().await;
+
+        Ok(this)
+    }
+
+    // authorizes by scanning a QR code from the official app
+    // (Settings > Devices > Link Desktop Device), if the stored session isn't authorized
+    pub async fn qr_login(self, login_code_source: &LoginCodeSource) -> Result<Self> {
+        if self.client().is_authorized().await? {
+            return Ok(self);
+        }
+
+        let export_login_token = ExportLoginToken {
+            api_id: self.account.api_id,
+            api_hash: self.account.api_hash.clone(),
+            except_ids: vec![],
+        };
+
+        loop {
+            let login_token = match self.client().invoke(&export_login_token).await {
+                Ok(t) => t,
+                Err(InvocationError::Rpc(err)) if err.name == "SESSION_PASSWORD_NEEDED" => {
+                    let tl::enums::account::Password::Password(password_info) =
+                        self.client().invoke(&GetPassword {}).await?;
+                    let password_token = PasswordToken::new(password_info);
+
+                    let password = match self.account.password.clone() {
+                        Some(t) => t,
+                        None => {
+                            login_code_source
+                                .password(&self.account.phone_number, password_token.hint())
+                                .await?
+                        }
+                    };
+
+                    self.client()
+                        .check_password(password_token, password)
+                        .await?;
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            match login_token {
+                LoginToken::Token(token) => {
+                    print_login_qr(&self.account.phone_number, &token.token)?;
+
+                    // scanning the token triggers UpdateLoginToken, otherwise a new one is
+                    // exported once it expires
+                    let now = to_unix_millis(SystemTime::now()) / 1000;
+                    let expires_in = u64::try_from(i64::from(token.expires) - now).unwrap_or(0);
+                    let _ = tokio::time::timeout(
+                        Duration::from_secs(expires_in).max(QR_LOGIN_TOKEN_MIN_WAIT),
+                        self.wait_login_token_update(),
+                    )
+                    .await;
+                }
+                LoginToken::MigrateTo(migrate_to) => {
+                    // the account lives in another DC, the token has to be imported there and
+                    // the session has to be switched to that DC
+                    let result = self
+                        .client()
+                        .invoke_in_dc(
+                            &ImportLoginToken {
+                                token: migrate_to.token,
+                            },
+                            migrate_to.dc_id,
+                        )
+                        .await;
+
+                    let authorization = match result {
+                        Ok(LoginToken::Success(success)) => success.authorization,
+                        Ok(_) => return Err(Error::UnexpectedLoginToken),
+                        Err(InvocationError::Rpc(err)) if err.name == "SESSION_PASSWORD_NEEDED" => {
+                            self.check_password_in_dc(login_code_source, migrate_to.dc_id)
+                                .await?
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    let user_id = authorized_user_id(&authorization)?;
+                    self.client()
+                        .session()
+                        .set_user(user_id, migrate_to.dc_id, false);
+                    self.sync_session().await?;
+
+                    return Self::connect(self.pool, self.account).await;
+                }
+                LoginToken::Success(_) => break,
+            }
+        }
+
+        tracing::info!(
+            phone_number = self.phone_number(),
+            "authorized with QR code"
+        );
+        self.sync_session().await?;
+        self.refresh_premium().await;
+
+        Ok(self)
+    }
+
+    // the 2FA step of a QR login imported in another DC, it has to happen in that DC too,
+    // before the session is switched to it
+    async fn check_password_in_dc(
+        &self,
+        login_code_source: &LoginCodeSource,
+        dc_id: i32,
+    ) -> Result<tl::enums::auth::Authorization> {
+        let tl::enums::account::Password::Password(password_info) =
+            self.client().invoke_in_dc(&GetPassword {}, dc_id).await?;
+
+        let password = match self.account.password.clone() {
+            Some(t) => t,
+            None => {
+                login_code_source
+                    .password(&self.account.phone_number, password_info.hint.as_deref())
+                    .await?
+            }
+        };
+        let password = input_check_password(self.phone_number(), password_info, &password)?;
+
+        Ok(self
+            .client()
+            .invoke_in_dc(&CheckPassword { password }, dc_id)
+            .await?)
+    }
+
+    async fn wait_login_token_update(&self) -> Result<()> {
+        loop {
+            if let Update::Raw(tl::enums::Update::LoginToken) = self.client().next_update().await? {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn client(&self) -> Arc<Client> {
+        self.client.read().unwrap().clone()
+    }
+
+    pub async fn invoke<R: RemoteCall>(&self, request: &R) -> Result<R::Return, InvocationError> {
+        self.call(method_name::<R>(), move |client| async move {
+            client.invoke(request).await
+        })
+        .await
+    }
+
+    pub async fn invoke_in_dc<R: RemoteCall>(
+        &self,
+        request: &R,
+        dc_id: i32,
+    ) -> Result<R::Return, InvocationError> {
+        self.call(method_name::<R>(), move |client| async move {
+            client.invoke_in_dc(request, dc_id).await
+        })
+        .await
+    }
+
+    // reconnects and retries once if the connection dropped, and retries transient errors
+    // according to the account's retry policy, except for `NON_IDEMPOTENT_METHODS`
+    async fn call<T, F, Fut>(&self, method: &'static str, call: F) -> Result<T, InvocationError>
+    where
+        F: Fn(Arc<Client>) -> Fut,
+        Fut: Future<Output = Result<T, InvocationError>>,
+    {
+        let policy = &self.account.retry_policy;
+        let retryable = !NON_IDEMPOTENT_METHODS.contains(&method);
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+
+        loop {
+            if let Some(limiter) = &self.payment_limiter
+                && PAYMENT_METHODS.contains(&method)
+            {
+                limiter.acquire().await;
+            }
+
+            let client = self.client();
+
+            let mut result = call(client.clone()).await;
+            if let Err(err) = &result
+                && is_connection_error(err)
+                && self.recover(&client, err).await
+                && retryable
+            {
+                result = call(self.client()).await;
+            }
+
+            let Err(err) = &result else {
+                return result;
+            };
+
+            if retryable && is_transient_error(err) && attempt < policy.max_attempts {
+                tracing::warn!(
+                    phone_number = self.phone_number(),
+                    method,
+                    attempt,
+                    ?backoff,
+                    ?err,
+                    "transient error, retrying"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(policy.max_backoff);
+                attempt += 1;
+                continue;
+            }
+
+            self.check_deauthorized(err).await;
+            self.record_flood_wait(method, err);
+
+            return result;
+        }
+    }
+
+    pub async fn next_update(&self) -> Result<Update, InvocationError> {
+        let client = self.client();
+
+        let mut result = client.next_update().await;
+        if let Err(err) = &result
+            && is_connection_error(err)
+            && self.recover(&client, err).await
+        {
+            result = self.client().next_update().await;
+        }
+
+        if let Err(err) = &result {
+            self.check_deauthorized(err).await;
+        }
+
+        result
+    }
+
+    pub async fn is_authorized(&self) -> Result<bool, InvocationError> {
+        self.client().is_authorized().await
+    }
+
+    fn record_flood_wait(&self, method: &'static str, err: &InvocationError) {
+        let Some(flood_wait) = flood_wait_duration(err) else {
+            return;
+        };
+
+        tracing::warn!(
+            phone_number = self.phone_number(),
+            method,
+            ?flood_wait,
+            "flood wait"
+        );
+        self.flood_waits
+            .lock()
+            .unwrap()
+            .insert(method, Instant::now() + flood_wait);
+    }
+
+    // remaining flood wait for `R`, callers should skip this client until it passes
+    pub fn flood_wait<R: RemoteCall>(&self) -> Option<Duration> {
+        let until = *self.flood_waits.lock().unwrap().get(method_name::<R>())?;
+        until
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    // every method still in flood wait with the remaining wait
+    pub fn flood_waits(&self) -> Vec<(&'static str, Duration)> {
+        let now = Instant::now();
+        self.flood_waits
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(&method, &until)| {
+                Some((method, until.checked_duration_since(now)?))
+                    .filter(|(_, remaining)| !remaining.is_zero())
+            })
+            .collect()
+    }
+
+    // quarantines the account once Telegram revokes its session, it's excluded from polling
+    // and buying until it's logged in again
+    async fn check_deauthorized(&self, err: &InvocationError) {
+        if !is_deauthorization_error(err) || self.deauthorized.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        tracing::error!(
+            phone_number = self.phone_number(),
+            ?err,
+            "session was revoked"
+        );
+
+        if let Err(err) = mark_session_deauthorized(
+            &*self.pool,
+            self.phone_number(),
+            to_unix_millis(SystemTime::now()),
+        )
+        .await
+        {
+            tracing::error!(
+                phone_number = self.phone_number(),
+                ?err,
+                "failed to mark session as deauthorized"
+            );
+        }
+    }
+
+    pub fn is_deauthorized(&self) -> bool {
+        self.deauthorized.load(Ordering::Relaxed)
+    }
+
+    // true for the first caller once the session is revoked, so whoever notices first
+    // alerts and the revocation is alerted about only once
+    pub fn claim_deauthorization_alert(&self) -> bool {
+        self.is_deauthorized() && !self.deauthorization_alerted.swap(true, Ordering::Relaxed)
+    }
+
+    // false if reconnecting failed, the original error should be returned then
+    async fn recover(&self, failed: &Arc<Client>, err: &InvocationError) -> bool {
+        tracing::warn!(phone_number = self.phone_number(), ?err, "connection lost");
+
+        match self.reconnect(failed).await {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::error!(
+                    phone_number = self.phone_number(),
+                    ?err,
+                    "failed to reconnect"
+                );
+                false
+            }
+        }
+    }
+
+    async fn reconnect(&self, failed: &Arc<Client>) -> Result<()> {
+        let _reconnecting = self.reconnecting.lock().await;
+
+        // another caller already reconnected while we were waiting for the lock
+        if !Arc::ptr_eq(&self.client(), failed) {
+            return Ok(());
+        }
+
+        // keeps the auth key and update state of the dropped connection
+        if let Err(err) = self.sync_session().await {
+            tracing::error!(
+                phone_number = self.phone_number(),
+                ?err,
+                "failed to sync session"
+            );
+        }
+
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+        let mut result = Err(Error::ReconnectFailed);
+
+        for attempt in 1..=RECONNECT_MAX_ATTEMPTS {
+            match connect_client(&self.pool, &self.account).await {
+                Ok(client) => {
+                    *self.client.write().unwrap() = Arc::new(client);
+                    self.reconnect_failures.store(0, Ordering::Relaxed);
+                    tracing::info!(phone_number = self.phone_number(), attempt, "reconnected");
+                    return Ok(());
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        phone_number = self.phone_number(),
+                        attempt,
+                        ?backoff,
+                        ?err,
+                        "reconnect attempt failed"
+                    );
+                    result = Err(err);
+                }
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+        }
+
+        self.reconnect_failures.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    // establishes (and authorizes) the connection to `dc_id` ahead of time, so the first
+    // file download from it doesn't pay the setup latency
+    pub async fn warm_up_dc(&self, dc_id: i32) {
+        let started_at = Instant::now();
+
+        match self.invoke_in_dc(&GetNearestDc {}, dc_id).await {
+            Ok(_) => tracing::debug!(
+                phone_number = self.phone_number(),
+                dc_id,
+                elapsed = ?started_at.elapsed(),
+                "dc warmed up"
+            ),
+            Err(err) => tracing::warn!(
+                phone_number = self.phone_number(),
+                dc_id,
+                ?err,
+                "failed to warm up dc"
+            ),
+        }
+    }
+
+    // waits until purchases started earlier (e.g. by the poll loop and a Buy button at once)
+    // are finished, purchases of this account are made while the permit is held
+    pub async fn begin_purchases(&self) -> SemaphorePermit<'_> {
+        self.queued_purchases.fetch_add(1, Ordering::Relaxed);
+        let started_at = Instant::now();
+
+        let permit = self
+            .purchases
+            .acquire()
+            .await
+            .expect("purchases semaphore is never closed");
+
+        self.queued_purchases.fetch_sub(1, Ordering::Relaxed);
+        tracing::debug!(
+            phone_number = self.phone_number(),
+            waited = ?started_at.elapsed(),
+            "purchases started"
+        );
+
+        permit
+    }
+
+    pub fn put_payment_form(&self, invoice: &InputInvoice, payment_form: PaymentForm) {
+        let mut payment_forms = self.payment_forms.lock().unwrap();
+        // forms that are never taken, e.g. of units that weren't bought, would pile up
+        payment_forms.retain(|_, (_, fetched_at)| fetched_at.elapsed() < PAYMENT_FORM_TTL);
+        payment_forms.insert(invoice.to_bytes(), (payment_form, Instant::now()));
+    }
+
+    // each form is used once, forms older than `PAYMENT_FORM_TTL` are dropped so they're
+    // fetched again instead of failing at SendStarsForm
+    pub fn take_payment_form(&self, invoice: &InputInvoice) -> Option<PaymentForm> {
+        let (payment_form, fetched_at) = self
+            .payment_forms
+            .lock()
+            .unwrap()
+            .remove(&invoice.to_bytes())?;
+        (fetched_at.elapsed() < PAYMENT_FORM_TTL).then_some(payment_form)
+    }
+
+    pub fn is_purchasing(&self) -> bool {
+        self.purchases.available_permits() == 0
+    }
+
+    pub fn queued_purchases(&self) -> usize {
+        self.queued_purchases.load(Ordering::Relaxed)
+    }
+
+    pub fn health(&self) -> Option<Health> {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn set_health(&self, health: Health) {
+        *self.health.lock().unwrap() = Some(health);
+    }
+
+    pub fn reconnect_failures(&self) -> u32 {
+        self.reconnect_failures.load(Ordering::Relaxed)
+    }
+
     pub fn phone_number(&self) -> &str {
-        &self.phone_number
+        &self.account.phone_number
+    }
+
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    // authorizes payments with saved credentials for `period` seconds,
+    // requires the configured 2FA password
+    pub async fn get_tmp_password(&self, period: i32) -> Result<Vec<u8>> {
+        let phone_number = self.phone_number();
+        let password = self
+            .account
+            .password
+            .as_deref()
+            .ok_or_else(|| Error::PasswordNotConfigured(phone_number.to_string()))?;
+
+        let tl::enums::account::Password::Password(password_info) =
+            self.invoke(&GetPassword {}).await?;
+
+        let tl::enums::account::TmpPassword::Password(tmp_password) = self
+            .invoke(&GetTmpPassword {
+                password: input_check_password(phone_number, password_info, password)?,
+                period,
+            })
+            .await?;
+
+        Ok(tmp_password.tmp_password)
+    }
+
+    pub fn is_premium(&self) -> bool {
+        self.premium.load(Ordering::Relaxed)
+    }
+
+    // bypasses `invoke`, a session that isn't logged in yet isn't deauthorized
+    pub async fn refresh_premium(&self) {
+        let result = self
+            .client()
+            .invoke(&GetUsers {
+                id: vec![InputUser::UserSelf],
+            })
+            .await;
+
+        match result {
+            Ok(users) => {
+                let premium = users.iter().any(|user| match user {
+                    tl::enums::User::User(user) => user.is_self && user.premium,
+                    tl::enums::User::Empty(_) => false,
+                });
+                self.premium.store(premium, Ordering::Relaxed);
+            }
+            Err(err) if is_deauthorization_error(&err) => {
+                tracing::debug!(phone_number = self.phone_number(), "not logged in yet");
+            }
+            Err(err) => tracing::warn!(
+                ?err,
+                phone_number = self.phone_number(),
+                "failed to check Premium, assuming none"
+            ),
+        }
+    }
+
+    pub async fn get_stars_balance(&self) -> Result<i64> {
+        let StarsStatus::Status(status) = self
+            .client()
+            .invoke(&GetStarsStatus {
+                peer: InputPeer::PeerSelf,
+            })
+            .await?;
+        let StarsAmount::Amount(amount) = status.balance;
+        Ok(amount.amount)
     }
 
     pub async fn sync_session(&self) -> Result<()> {
-        self.client.sync_update_state();
-        insert_or_replace_session(&*self.pool, &self.phone_number, self.client.session()).await?;
+        // replacing the row would clear the deauthorization mark
+        if self.is_deauthorized() {
+            return Ok(());
+        }
+
+        self.client().sync_update_state();
+        insert_or_replace_session(
+            &*self.pool,
+            &self.account.phone_number,
+            self.client().session(),
+        )
+        .await?;
         Ok(())
     }
 }
 
-impl Deref for WrappedClient {
-    type Target = Client;
-
-    fn deref(&self) -> &Self::Target {
-        &self.client
+fn flood_wait_duration(err: &InvocationError) -> Option<Duration> {
+    match err {
+        InvocationError::Rpc(err)
+            if matches!(err.name.as_str(), "FLOOD_WAIT" | "FLOOD_PREMIUM_WAIT") =>
+        {
+            Some(Duration::from_secs(err.value?.into()))
+        }
+        _ => None,
     }
 }